@@ -0,0 +1,268 @@
+//! Computer-controlled paddles.
+//!
+//! An `AiController` looks at the simulation like a player looks at the screen, and answers with
+//! the same `Effect::Move` a held key would give, so AI and human players share one input path.
+//! How well it plays depends on its `Difficulty`: how late it sees the ball, how many wall
+//! bounces ahead it can work out where the ball goes, and how far off it aims on purpose.
+
+use std::collections::VecDeque;
+
+use bevy::math::Vec2;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::{reflect, Shape};
+use crate::simulation::{BallState, PaddleState, PlayerInput, Simulation, TIMESTEP};
+use crate::types::parameters::Effect;
+
+/// How far ahead the ball's path is worked out, in steps.
+const LOOKAHEAD_TICKS: usize = 256;
+
+/// How close to its target the paddle stops moving, in steps of its own movement. Any closer and
+/// it would overshoot back and forth.
+const ARRIVAL_STEPS: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    /// Steps between the ball moving and the AI noticing.
+    pub fn reaction_ticks(self) -> usize {
+        match self {
+            Difficulty::Easy => 20,
+            Difficulty::Medium => 10,
+            Difficulty::Hard => 3,
+        }
+    }
+
+    /// Wall bounces the AI follows the ball through; past them it aims where the ball would be
+    /// without bouncing again.
+    pub fn predicted_bounces(self) -> usize {
+        match self {
+            Difficulty::Easy => 0,
+            Difficulty::Medium => 1,
+            Difficulty::Hard => 4,
+        }
+    }
+
+    /// Largest distance between where the AI aims and where the ball will be.
+    pub fn error(self) -> f32 {
+        match self {
+            Difficulty::Easy => 60.,
+            Difficulty::Medium => 25.,
+            Difficulty::Hard => 5.,
+        }
+    }
+}
+
+/// Drives one paddle.
+#[derive(Debug, Clone)]
+pub struct AiController {
+    pub paddle: usize,
+    pub difficulty: Difficulty,
+    /// What the balls looked like over the last steps, oldest first.
+    seen: VecDeque<Vec<BallState>>,
+    /// Miss added to the aim, redrawn whenever the ball it follows changes course.
+    error: Vec2,
+    followed: Option<(u64, Vec2)>,
+    rng: StdRng,
+}
+
+impl AiController {
+    pub fn new(paddle: usize, difficulty: Difficulty, seed: u64) -> Self {
+        Self {
+            paddle,
+            difficulty,
+            seen: VecDeque::new(),
+            error: Vec2::ZERO,
+            followed: None,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Looks at the simulation and decides where to move the paddle this step.
+    pub fn think(&mut self, simulation: &Simulation) -> Effect {
+        let Some(paddle) = simulation.paddles.get(self.paddle) else {
+            return Effect::Nothing;
+        };
+
+        self.seen.push_back(simulation.balls.clone());
+        while self.seen.len() > self.difficulty.reaction_ticks() + 1 {
+            self.seen.pop_front();
+        }
+        let seen = self.seen.front().cloned().unwrap_or_default();
+
+        // Follow whichever ball reaches the paddle first, or go back to the starting spot
+        let walls: Vec<Shape> = simulation.walls.iter().map(|wall| wall.shape()).collect();
+        let position = paddle.position.truncate();
+        let first = seen
+            .iter()
+            .filter_map(|ball| {
+                let (ticks, target) = self.predict(ball, &walls, paddle)?;
+                Some((ticks, ball, target))
+            })
+            .min_by_key(|(ticks, _, _)| *ticks);
+        let target = match first {
+            Some((_, ball, target)) => {
+                let course = (ball.id, ball.velocity.normalize_or_zero());
+                if self.followed != Some(course) {
+                    self.followed = Some(course);
+                    let error = self.difficulty.error();
+                    self.error = Vec2::new(
+                        self.rng.gen_range(-error..=error),
+                        self.rng.gen_range(-error..=error),
+                    );
+                }
+                target + self.error
+            }
+            None => paddle.paddle.position().truncate(),
+        };
+
+        let target = paddle.region.closest_point(target);
+        let offset = target - position;
+        if offset.length() <= paddle.paddle.speed() * TIMESTEP * ARRIVAL_STEPS {
+            return Effect::Nothing;
+        }
+        // Moves are given in the paddle's own directions
+        let offset = Vec2::from_angle(-paddle.paddle.angle()).rotate(offset);
+        Effect::Move(offset.normalize().extend(0.))
+    }
+
+    /// Where the paddle should be to meet `ball`, and in how many steps the ball gets there.
+    /// `None` if the ball doesn't come near the paddle's bounds within `LOOKAHEAD_TICKS`.
+    fn predict(
+        &self,
+        ball: &BallState,
+        walls: &[Shape],
+        paddle: &PaddleState,
+    ) -> Option<(usize, Vec2)> {
+        let reach = ball.radius() + paddle.size.truncate().max_element() / 2.;
+        let mut position = ball.position.truncate();
+        let mut velocity = ball.velocity;
+        let mut bounces = 0;
+
+        let mut closest: Option<(usize, f32, Vec2)> = None;
+        for tick in 0..LOOKAHEAD_TICKS {
+            let motion = velocity * TIMESTEP;
+            let hit = walls
+                .iter()
+                .filter_map(|wall| wall.sweep_circle(position, motion, ball.radius()))
+                .min_by(|x, y| x.time.total_cmp(&y.time));
+            match hit {
+                Some(hit) if bounces < self.difficulty.predicted_bounces() => {
+                    position += motion * hit.time;
+                    velocity = reflect(velocity, hit.normal);
+                    bounces += 1;
+                }
+                // Past what the AI can foresee, the ball just goes on straight
+                _ => position += motion,
+            }
+
+            let on_bounds = paddle.region.closest_point(position);
+            let distance = on_bounds.distance(position);
+            match closest {
+                Some((_, best, _)) if distance >= best => {
+                    // Moving away again after getting within reach: that was the meeting point
+                    if best <= reach {
+                        break;
+                    }
+                }
+                _ => closest = Some((tick, distance, position)),
+            }
+        }
+
+        closest
+            .filter(|(_, distance, _)| *distance <= reach)
+            .map(|(tick, _, position)| (tick, position))
+    }
+}
+
+/// Replaces the inputs of every paddle an AI drives with what the AI decided.
+pub fn apply(
+    controllers: &mut [AiController],
+    simulation: &Simulation,
+    inputs: &mut Vec<PlayerInput>,
+) {
+    for controller in controllers {
+        if inputs.len() <= controller.paddle {
+            inputs.resize(controller.paddle + 1, PlayerInput::default());
+        }
+        let effect = controller.think(simulation);
+        inputs[controller.paddle] = PlayerInput::from_effects([&effect]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::parameters::Parameters;
+
+    /// The default game with a single ball at `position`, going at `velocity`. The right paddle,
+    /// number 1, stands at x = 100 and may go from y = -100 to 100.
+    fn lone_ball(position: Vec2, velocity: Vec2) -> Simulation {
+        let parameters = Parameters::default();
+        let mut simulation = Simulation::new(&parameters, &parameters.levels[0], 0);
+        simulation.balls.clear();
+        simulation.spawn_ball(position.extend(0.), velocity, None);
+        simulation
+    }
+
+    /// Steps until `controller` first moves its paddle up, watching `simulation` as it is.
+    fn steps_to_move_up(controller: &mut AiController, simulation: &Simulation) -> usize {
+        (1..=100)
+            .find(|_| matches!(controller.think(simulation), Effect::Move(direction) if direction.y > 0.))
+            .expect("the paddle never went for the ball")
+    }
+
+    #[test]
+    fn predicts_where_a_straight_shot_meets_the_paddle() {
+        let simulation = lone_ball(Vec2::new(0., 30.), Vec2::new(400., 0.));
+        let walls: Vec<Shape> = simulation.walls.iter().map(|wall| wall.shape()).collect();
+        let mut controller = AiController::new(1, Difficulty::Hard, 0);
+
+        let prediction = controller.predict(&simulation.balls[0], &walls, &simulation.paddles[1]);
+        // 6.25 a step, so at x = 100 after the 16th one
+        assert_eq!(prediction, Some((15, Vec2::new(100., 30.))));
+
+        match controller.think(&simulation) {
+            Effect::Move(direction) => assert!(direction.y > 0.99, "{direction}"),
+            _ => panic!("the paddle stays put"),
+        }
+        let aim = Vec2::new(100., 30.) + controller.error;
+        assert!(aim.distance(Vec2::new(100., 30.)) <= Difficulty::Hard.error() * 2f32.sqrt());
+    }
+
+    #[test]
+    fn difficulty_changes_the_reaction_and_the_aim() {
+        // Slow enough that, going left, it comes nowhere near the right paddle
+        let mut leaving = lone_ball(Vec2::new(0., 90.), Vec2::new(-100., 0.));
+        let mut easy = AiController::new(1, Difficulty::Easy, 0);
+        let mut hard = AiController::new(1, Difficulty::Hard, 0);
+        for _ in 0..30 {
+            assert!(matches!(easy.think(&leaving), Effect::Nothing));
+            assert!(matches!(hard.think(&leaving), Effect::Nothing));
+        }
+        // The ball turns around; each AI sees it as late as its difficulty says
+        leaving.balls[0].velocity = Vec2::new(100., 0.);
+        let coming = leaving;
+        assert_eq!(steps_to_move_up(&mut hard, &coming), 4);
+        assert_eq!(steps_to_move_up(&mut easy, &coming), 21);
+
+        let misses = |difficulty: Difficulty| {
+            (0..50)
+                .map(|seed| {
+                    let mut controller = AiController::new(1, difficulty, seed);
+                    controller.think(&coming);
+                    controller.error.abs().max_element()
+                })
+                .fold(0., f32::max)
+        };
+        let (easy_miss, hard_miss) = (misses(Difficulty::Easy), misses(Difficulty::Hard));
+        assert!(hard_miss <= Difficulty::Hard.error());
+        assert!(easy_miss > Difficulty::Hard.error() && easy_miss <= Difficulty::Easy.error());
+    }
+}
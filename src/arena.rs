@@ -0,0 +1,551 @@
+//! Arenas generated from a few numbers, instead of written out wall by wall.
+//!
+//! `generate` turns an `ArenaSpec` into a complete `Level`: the sides of a `RegularPolygon`, some
+//! of them holding a goal, a paddle in front of each goal, obstacles and ball spawns, all worked
+//! out from the geometry. `random_spec` picks a spec for "random arena" matches, and
+//! `goal_bricks` fits brick walls in front of the goals of any level.
+
+use std::f32::consts::{FRAC_PI_2, TAU};
+use std::ops::RangeInclusive;
+
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::parameters::{validate_level, R32};
+use crate::types::components::{Paddle, Wall};
+use crate::types::parameters::{GoalBricks, Level, ParametersMisc, WinCondition};
+use crate::types::regular_polygon::RegularPolygon;
+
+/// Player counts a free-for-all arena can be generated for.
+pub const FREE_FOR_ALL_PLAYERS: RangeInclusive<usize> = 3..=8;
+
+/// Side counts an arena can be generated with.
+pub const ARENA_SIDES: RangeInclusive<usize> = 3..=12;
+
+const WALL_THICKNESS: f32 = 10.;
+const WALL_COLOR: (f32, f32, f32, f32) = (0.8, 0.8, 0.8, 1.);
+
+/// Distance between a side and the middle of the area of the paddle defending it, as a share of
+/// the distance from the side to the center.
+const PADDLE_INSET: f32 = 0.12;
+/// How far a paddle may move toward or away from its goal, in total.
+const PADDLE_DEPTH: f32 = 40.;
+/// Length of a paddle, as a share of the goal it defends.
+const PADDLE_SHARE: f32 = 0.3;
+const PADDLE_WIDTH: f32 = 20.;
+const PADDLE_SPEED: f32 = 500.;
+
+/// Room kept between a ball spawn and any obstacle.
+const SPAWN_CLEARANCE: f32 = 30.;
+/// Spawns tried around the center when an obstacle covers it, as a share of the radius.
+const SPAWN_RING: f32 = 0.4;
+const SPAWN_RING_POINTS: usize = 8;
+
+/// What `generate` builds a level from.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ArenaSpec {
+    pub sides: usize,
+    /// Distance from the center to the corners.
+    pub radius: f32,
+    /// Share of a defended side taken by its goal, centered on it, from above 0 to 1. The rest of
+    /// the side is plain wall.
+    pub goal_width: f32,
+    /// Goals a player may let in before being eliminated, see `Level.goal_limit`. With one, the
+    /// last player left wins; without, the match rules decide.
+    #[serde(default)]
+    pub goal_limit: Option<u32>,
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+}
+
+/// A solid regular polygon inside the arena, for the ball to bounce off.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Obstacle {
+    pub center: (f32, f32),
+    pub radius: f32,
+    pub sides: usize,
+}
+
+/// Builds the level described by `spec` for `n_players`, which must not be more than
+/// `spec.sides`.
+///
+/// Side 0 lies flat at the bottom and the others follow counterclockwise. The players get sides
+/// spread as evenly as possible, starting from side 0; player `i` defends goal wall `i`, and is
+/// credited with the goals no paddle touched on the next player's wall. The other walls, sides
+/// without a player and obstacles, get the ids after the goals.
+pub fn generate(spec: &ArenaSpec, n_players: usize) -> Level {
+    let n = spec.sides;
+    let step = TAU / n as f32;
+    // `RegularPolygon` lists its corners clockwise from the top one; turn them around so side
+    // `k` runs counterclockwise from corner `k` to corner `k + 1`, with side 0 at the bottom
+    let mut corners = RegularPolygon::new(spec.radius, n).vertices();
+    corners.reverse();
+    let turn = Vec2::from_angle(-FRAC_PI_2 - step / 2. - (FRAC_PI_2 + step));
+    let corners: Vec<Vec2> = corners.iter().map(|corner| turn.rotate(*corner)).collect();
+
+    let defended: Vec<usize> = (0..n_players).map(|i| i * n / n_players).collect();
+    let mut walls = Vec::new();
+    let mut next_id = n_players;
+    let mut plain_wall = |a: Vec2, b: Vec2, walls: &mut Vec<Wall>| {
+        walls.push(wall(next_id, a, b));
+        next_id += 1;
+    };
+
+    let mut paddles = Vec::new();
+    for k in 0..n {
+        let (a, b) = (corners[k], corners[(k + 1) % n]);
+        let Some(player) = defended.iter().position(|side| *side == k) else {
+            plain_wall(a, b, &mut walls);
+            continue;
+        };
+
+        // The goal in the middle of the side, plain wall on both ends
+        let goal_width = spec.goal_width.clamp(0., 1.);
+        let (goal_a, goal_b) = (
+            a.lerp(b, (1. - goal_width) / 2.),
+            a.lerp(b, (1. + goal_width) / 2.),
+        );
+        if goal_width < 1. {
+            plain_wall(a, goal_a, &mut walls);
+            plain_wall(goal_b, b, &mut walls);
+        }
+        walls.push(wall(player, goal_a, goal_b));
+        paddles.push(paddle(player, n_players, (a, b), goal_a.distance(goal_b)));
+    }
+
+    for obstacle in &spec.obstacles {
+        let center = Vec2::new(obstacle.center.0, obstacle.center.1);
+        let corners = RegularPolygon::new(obstacle.radius, obstacle.sides).vertices();
+        for (i, corner) in corners.iter().enumerate() {
+            let next = corners[(i + 1) % corners.len()];
+            plain_wall(center + *corner, center + next, &mut walls);
+        }
+    }
+
+    let win = match spec.goal_limit {
+        Some(_) => WinCondition::LastStanding,
+        None => WinCondition::Rules,
+    };
+    let level = Level {
+        walls,
+        paddles,
+        win,
+        goal_limit: spec.goal_limit,
+        bricks: Vec::new(),
+        goal_bricks: None,
+        ball_spawns: ball_spawns(spec)
+            .into_iter()
+            .map(|spawn| (R32::from(spawn.x), R32::from(spawn.y)))
+            .collect(),
+    };
+    debug_assert!(validate_level("", &level, n_players).is_empty());
+    level
+}
+
+/// A regular polygon of `radius` with one side per player, each side entirely the goal of the
+/// player defending it. Players are eliminated after `goal_limit` goals, and the last one left
+/// wins.
+pub fn free_for_all(n_players: usize, radius: f32, goal_limit: u32) -> Level {
+    generate(
+        &ArenaSpec {
+            sides: n_players,
+            radius,
+            goal_width: 1.,
+            goal_limit: Some(goal_limit),
+            obstacles: Vec::new(),
+        },
+        n_players,
+    )
+}
+
+/// A spec for a random arena fitting `n_players`: a few more sides than players, goals of varied
+/// widths, and up to two obstacles near the center.
+pub fn random_spec(n_players: usize, goal_limit: Option<u32>, rng: &mut impl Rng) -> ArenaSpec {
+    let fewest = n_players.max(*ARENA_SIDES.start());
+    let sides = rng.gen_range(fewest..=(fewest + 3).min(*ARENA_SIDES.end()));
+    let radius = rng.gen_range(280. ..=340.);
+    let obstacles = (0..rng.gen_range(0..=2))
+        .map(|_| {
+            let center =
+                Vec2::from_angle(rng.gen_range(0. ..TAU)) * rng.gen_range(0. ..=radius * 0.15);
+            Obstacle {
+                center: (center.x, center.y),
+                radius: rng.gen_range(20. ..=45.),
+                sides: rng.gen_range(3..=6),
+            }
+        })
+        .collect();
+    ArenaSpec {
+        sides,
+        radius,
+        goal_width: rng.gen_range(0.4..=0.9),
+        goal_limit,
+        obstacles,
+    }
+}
+
+/// A brick of a wall guarding a goal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlacedBrick {
+    pub position: Vec2,
+    /// Turn of the brick, in radians. Its height runs along the goal.
+    pub angle: f32,
+    pub hit_points: u32,
+}
+
+/// The bricks guarding `goal` from the ball, laid `spec.layers` deep between it and the `paddle`
+/// defending it and spread along it, leaving the gaps of `misc`. Layers that don't fit before the
+/// paddle are left out.
+pub fn goal_bricks(
+    goal: &Wall,
+    paddle: &Paddle,
+    brick: Vec2,
+    misc: &ParametersMisc,
+    spec: &GoalBricks,
+) -> Vec<PlacedBrick> {
+    let (a, b) = (goal.end_a(), goal.end_b());
+    let length = a.distance(b);
+    let along = (b - a).normalize_or_zero();
+    let start = paddle.position().truncate();
+    let mut inward = along.perp();
+    if (start - a).dot(inward) < 0. {
+        inward = -inward;
+    }
+
+    // Layers are counted from the inner face of the goal toward the paddle
+    let gap = misc.gap_between_bricks;
+    let first = goal.thickness() / 2. + misc.minimum_gap_between_bricks_and_vertical_walls;
+    let last = (start - a).dot(inward)
+        - paddle.width.into_inner() / 2.
+        - misc.minimum_gap_between_paddle_and_goal_bricks;
+    let fitting = ((last - first + gap) / (brick.x + gap)).floor().max(0.) as usize;
+    let layers = fitting.min(spec.layers);
+
+    // Rows are centered along the goal, keeping clear of the walls at its ends
+    let room = length - 2. * misc.minimum_gap_between_bricks_and_horizontal_walls;
+    let rows = ((room + gap) / (brick.y + gap)).floor().max(0.) as usize;
+    let used = rows as f32 * (brick.y + gap) - gap;
+    let first_row = (length - used) / 2. + brick.y / 2.;
+
+    let angle = along.y.atan2(along.x) - FRAC_PI_2;
+    (0..layers)
+        .flat_map(|layer| {
+            let depth = first + brick.x / 2. + layer as f32 * (brick.x + gap);
+            let hit_points = spec.hit_points.saturating_sub(layer as u32).max(1);
+            (0..rows).map(move |row| PlacedBrick {
+                position: a + along * (first_row + row as f32 * (brick.y + gap)) + inward * depth,
+                angle,
+                hit_points,
+            })
+        })
+        .collect()
+}
+
+/// The paddle of `player`, defending the goal of length `goal` in the middle of `side`. It may
+/// move along the goal, and a little toward and away from it.
+fn paddle(player: usize, n_players: usize, (a, b): (Vec2, Vec2), goal: f32) -> Paddle {
+    let middle = (a + b) / 2.;
+    let along = (b - a).normalize();
+    let inward = -middle.normalize();
+    let center = middle * (1. - PADDLE_INSET);
+    let length = goal * PADDLE_SHARE;
+
+    // The arena narrows toward its center, so the paddle has less room than the side is long
+    let room = a.distance(b) / 2. * (1. - PADDLE_INSET) - length / 2.;
+    let half_rail = (goal / 2.).min(room).max(0.);
+    let half_depth = PADDLE_DEPTH / 2.;
+    let bounds = [
+        center - along * half_rail - inward * half_depth,
+        center + along * half_rail - inward * half_depth,
+        center + along * half_rail + inward * half_depth,
+        center - along * half_rail + inward * half_depth,
+    ];
+
+    let [red, green, blue, alpha] =
+        Color::hsl(360. * player as f32 / n_players as f32, 0.6, 0.5).as_rgba_f32();
+    Paddle {
+        width: R32::from(PADDLE_WIDTH),
+        height: R32::from(length),
+        x: R32::from(center.x),
+        y: R32::from(center.y),
+        z: R32::from(0.),
+        // Paddles are drawn upright; turn them so their height runs along the side
+        angle: R32::from((along.y.atan2(along.x) - FRAC_PI_2).to_degrees()),
+        bounds: bounds.iter().map(|point| r32_point(*point)).collect(),
+        speed: R32::from(PADDLE_SPEED),
+        color_rgba: r32_color((red, green, blue, alpha)),
+        wall_that_gives_points: (player + 1) % n_players,
+    }
+}
+
+/// The center, or if an obstacle is in the way, the points of a ring around it that are clear.
+fn ball_spawns(spec: &ArenaSpec) -> Vec<Vec2> {
+    let clear = |point: Vec2| {
+        spec.obstacles.iter().all(|obstacle| {
+            let center = Vec2::new(obstacle.center.0, obstacle.center.1);
+            point.distance(center) > obstacle.radius + SPAWN_CLEARANCE
+        })
+    };
+    if clear(Vec2::ZERO) {
+        return vec![Vec2::ZERO];
+    }
+    (0..SPAWN_RING_POINTS)
+        .map(|i| {
+            Vec2::from_angle(TAU * i as f32 / SPAWN_RING_POINTS as f32) * spec.radius * SPAWN_RING
+        })
+        .filter(|point| clear(*point))
+        .collect()
+}
+
+fn wall(id: usize, a: Vec2, b: Vec2) -> Wall {
+    Wall {
+        id,
+        ends: (r32_point(a), r32_point(b)),
+        thickness: R32::from(WALL_THICKNESS),
+        color: r32_color(WALL_COLOR),
+    }
+}
+
+fn r32_point(point: Vec2) -> (R32, R32) {
+    (R32::from(point.x), R32::from(point.y))
+}
+
+fn r32_color((red, green, blue, alpha): (f32, f32, f32, f32)) -> (R32, R32, R32, R32) {
+    (
+        R32::from(red),
+        R32::from(green),
+        R32::from(blue),
+        R32::from(alpha),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::ai::{self, AiController, Difficulty};
+    use crate::rollback::checksum;
+    use crate::simulation::{Simulation, SimulationEvent};
+    use crate::types::parameters::Parameters;
+    use crate::types::resources::GameRng;
+
+    /// Sends a lone ball into the goal of `player`, from just inside it and to the side of the
+    /// paddle defending it.
+    fn into_goal(simulation: &mut Simulation, player: usize) {
+        let goal = simulation
+            .walls
+            .iter()
+            .find(|wall| wall.id == player)
+            .expect("every player has a goal");
+        let (a, b) = (goal.end_a(), goal.end_b());
+        let middle = (a + b) / 2.;
+        let outward = middle.normalize();
+        let position = middle + (b - a) * 0.25 - outward * 60.;
+        simulation.balls.clear();
+        simulation.spawn_ball(position.extend(0.), outward * 800., None);
+    }
+
+    #[test]
+    fn free_for_all_arenas_are_valid_levels() {
+        for n_players in FREE_FOR_ALL_PLAYERS {
+            let level = free_for_all(n_players, 300., 3);
+            assert_eq!(validate_level("", &level, n_players), vec![]);
+            assert_eq!(level.walls.len(), n_players);
+            assert_eq!(level.paddles.len(), n_players);
+        }
+        // Two players face each other across a square, which free-for-all matches don't offer
+        let spec = ArenaSpec {
+            sides: 4,
+            radius: 300.,
+            goal_width: 1.,
+            goal_limit: Some(3),
+            obstacles: Vec::new(),
+        };
+        assert_eq!(validate_level("", &generate(&spec, 2), 2), vec![]);
+    }
+
+    #[test]
+    fn the_sides_of_eliminated_players_turn_solid() {
+        let level = free_for_all(3, 300., 1);
+        let mut simulation = Simulation::new(&Parameters::default(), &level, 0);
+
+        into_goal(&mut simulation, 0);
+        let events = simulation.run(30, |_| Vec::new());
+        assert!(events.contains(&SimulationEvent::Eliminated { player: 0 }));
+        assert!(simulation.paddles[0].eliminated);
+
+        into_goal(&mut simulation, 0);
+        let events = simulation.run(30, |_| Vec::new());
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, SimulationEvent::Goal { .. })));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, SimulationEvent::Collision { .. })));
+        // Bounced back toward the middle
+        let ball = &simulation.balls[0];
+        let goal = simulation.walls.iter().find(|wall| wall.id == 0).unwrap();
+        let outward = ((goal.end_a() + goal.end_b()) / 2.).normalize();
+        assert!(ball.velocity.dot(outward) < 0.);
+        assert_eq!(simulation.conceded[0], 1);
+    }
+
+    #[test]
+    fn generated_arenas_are_worked_out_from_the_spec() {
+        let spec = ArenaSpec {
+            sides: 6,
+            radius: 300.,
+            goal_width: 0.5,
+            goal_limit: None,
+            obstacles: vec![Obstacle {
+                center: (0., 0.),
+                radius: 30.,
+                sides: 4,
+            }],
+        };
+        let level = generate(&spec, 3);
+        assert_eq!(validate_level("", &level, 3), vec![]);
+        assert_eq!(level.win, WinCondition::Rules);
+        // Three plain sides, three goals with a wall on each end, and the obstacle
+        assert_eq!(level.walls.len(), 3 + 3 * 3 + 4);
+
+        // The sides of a hexagon are as long as its radius
+        for (player, paddle) in level.paddles.iter().enumerate() {
+            let goal = level.walls.iter().find(|wall| wall.id == player).unwrap();
+            assert!((goal.end_a().distance(goal.end_b()) - 150.).abs() < 1e-3);
+            assert_eq!(paddle.wall_that_gives_points, (player + 1) % 3);
+            assert!((paddle.height.into_inner() - 150. * PADDLE_SHARE).abs() < 1e-3);
+        }
+
+        // The obstacle covers the center, so balls are served from around it
+        assert_eq!(level.ball_spawns.len(), SPAWN_RING_POINTS);
+        for (x, y) in &level.ball_spawns {
+            let spawn = Vec2::new(x.into_inner(), y.into_inner());
+            assert!(spawn.length() > 30. + SPAWN_CLEARANCE);
+        }
+    }
+
+    #[test]
+    fn random_specs_fit_their_players_and_generate_valid_arenas() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for n_players in 2..=8 {
+            for _ in 0..20 {
+                let spec = random_spec(n_players, Some(3), &mut rng);
+                assert!(ARENA_SIDES.contains(&spec.sides) && spec.sides >= n_players);
+                assert!((0.4..=0.9).contains(&spec.goal_width));
+                assert!(spec.obstacles.len() <= 2);
+                assert_eq!(spec.goal_limit, Some(3));
+
+                let level = generate(&spec, n_players);
+                assert_eq!(validate_level("", &level, n_players), vec![]);
+                assert!(!level.ball_spawns.is_empty());
+            }
+        }
+    }
+
+    /// Checks every brick of `bricks` lies along `goal`, between it and `paddle`.
+    fn assert_between(goal: &Wall, paddle: &Paddle, brick: Vec2, bricks: &[PlacedBrick]) {
+        let (a, b) = (goal.end_a(), goal.end_b());
+        let along = (b - a).normalize();
+        let start = paddle.position().truncate();
+        let mut inward = along.perp();
+        if (start - a).dot(inward) < 0. {
+            inward = -inward;
+        }
+        let paddle_face = (start - a).dot(inward) - paddle.width.into_inner() / 2.;
+        for placed in bricks {
+            let depth = (placed.position - a).dot(inward);
+            assert!(
+                depth - brick.x / 2. >= goal.thickness() / 2.,
+                "{placed:?} is in the goal"
+            );
+            assert!(
+                depth + brick.x / 2. <= paddle_face,
+                "{placed:?} is past the paddle"
+            );
+            let offset = (placed.position - a).dot(along);
+            assert!(offset - brick.y / 2. >= 0. && offset + brick.y / 2. <= a.distance(b));
+        }
+    }
+
+    #[test]
+    fn goal_bricks_stay_between_the_goal_and_the_paddle() {
+        let parameters = Parameters::default();
+        let brick = parameters.brick.size().truncate();
+        let spec = GoalBricks {
+            layers: 3,
+            hit_points: 3,
+        };
+
+        // The left goal of the default level, with the paddle 500 away
+        let level = &parameters.levels[0];
+        let (goal, paddle) = (&level.walls[0], &level.paddles[0]);
+        let bricks = goal_bricks(goal, paddle, brick, &parameters.misc, &spec);
+        assert_between(goal, paddle, brick, &bricks);
+        let hit_points: Vec<u32> = bricks.iter().map(|brick| brick.hit_points).collect();
+        assert_eq!(hit_points.iter().max(), Some(&3));
+        assert_eq!(hit_points.iter().min(), Some(&1));
+        assert_eq!(bricks.len() % 3, 0);
+
+        // Slanted goals of a large generated arena, with more layers asked for than fit
+        let spec = GoalBricks {
+            layers: 100,
+            hit_points: 2,
+        };
+        let level = free_for_all(5, 1000., 3);
+        for (player, paddle) in level.paddles.iter().enumerate() {
+            let goal = level.walls.iter().find(|wall| wall.id == player).unwrap();
+            let bricks = goal_bricks(goal, paddle, brick, &parameters.misc, &spec);
+            assert!(!bricks.is_empty());
+            assert_between(goal, paddle, brick, &bricks);
+        }
+
+        // No room at all between a small arena's goals and their paddles
+        let level = free_for_all(3, 300., 3);
+        let bricks = goal_bricks(
+            &level.walls[0],
+            &level.paddles[0],
+            brick,
+            &parameters.misc,
+            &spec,
+        );
+        assert_eq!(bricks, vec![]);
+    }
+
+    /// The spec, level and simulation the first set of a session seeded with `seed` gets on a
+    /// random arena, drawn like `setup_level` does, then played for a few seconds by the computer.
+    fn first_random_set(seed: u64) -> (ArenaSpec, Level, Simulation) {
+        let parameters = Parameters::default();
+        let n_players = parameters.players.len();
+        let mut rng = GameRng(StdRng::seed_from_u64(seed));
+        let set_seed: u64 = rng.gen();
+        let spec = random_spec(n_players, Some(3), &mut StdRng::seed_from_u64(set_seed));
+        let level = generate(&spec, n_players);
+
+        let mut simulation = Simulation::new(&parameters, &level, set_seed);
+        let mut controllers: Vec<AiController> = (0..n_players)
+            .map(|i| AiController::new(i, Difficulty::Medium, rng.gen()))
+            .collect();
+        for _ in 0..64 * 5 {
+            let mut inputs = Vec::new();
+            ai::apply(&mut controllers, &simulation, &mut inputs);
+            simulation.step(&inputs);
+        }
+        (spec, level, simulation)
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_random_arena_and_set() {
+        let (spec, level, simulation) = first_random_set(11);
+        let (again_spec, again_level, again) = first_random_set(11);
+        assert_eq!(spec, again_spec);
+        assert_eq!(level, again_level);
+        assert_eq!(checksum(&simulation), checksum(&again));
+
+        let (other_spec, _, other) = first_random_set(12);
+        assert_ne!(spec, other_spec);
+        assert_ne!(checksum(&simulation), checksum(&other));
+    }
+}
@@ -0,0 +1,255 @@
+//! Command-line options, so scripts can launch a given match straight away instead of going
+//! through the menu.
+//!
+//! Options that change parameters are applied over the parameters files every time they are
+//! read, level override files included, and checked with them.
+
+use crate::ai::Difficulty;
+use crate::types::components::Player;
+use crate::types::parameters::{NetRole, Parameters, PARAMETERS_FILE_PATH};
+
+pub const USAGE: &str = "\
+Usage: rust-pong [options]
+
+  --config <file>          parameters file to read, instead of parameters.toml
+  --level <index|name>     level the match starts on: its index, counting from 0, or the name
+                           of its file in assets/levels, without .level.toml
+  --players <count>        players in the match; those added have no controls
+  --ai <players>           players the computer plays, and only them: their numbers, from 1,
+                           separated by commas, each with :easy, :medium or :hard (medium
+                           otherwise), or none
+  --seed <seed>            seed of every random decision, to play a session again
+  --windowed               play in a window (the default)
+  --headless               play without a window, moving on between sets by itself and quitting
+                           once the match is over
+  --record <file>          record the match to a replay file
+  --play <file>            play a replay file back
+  --host <address>         host the match for clients on the network, listening on <address>
+  --join <address>         join the match hosted at <address>
+  -h, --help               show this and quit
+
+Any option but --config, --seed and --windowed starts the match right away, without the menu.";
+
+/// Where the match starts, see `--level`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelChoice {
+    /// Index of the level, like `CurrentLevel`.
+    Index(usize),
+    /// Name of a level file, see `FolderLevels::index_of`.
+    Name(String),
+}
+
+/// What the command line asks for. Everything left out keeps what the parameters files say.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub config: Option<String>,
+    pub level: Option<LevelChoice>,
+    pub players: Option<usize>,
+    /// Players the computer plays, by index, with how well.
+    pub ai: Option<Vec<(usize, Difficulty)>>,
+    pub seed: Option<u64>,
+    pub headless: bool,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub host: Option<String>,
+    pub join: Option<String>,
+    pub help: bool,
+}
+
+impl Options {
+    /// Reads the options from `args`, the program name left out. Options take their value as
+    /// the next argument or after `=`, as in `--seed=7`.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => {
+                    (name.to_string(), Some(value.to_string()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{name} needs a value"))
+            };
+            match name.as_str() {
+                "--config" => options.config = Some(value()?),
+                "--level" => {
+                    let level = value()?;
+                    options.level = Some(match level.parse() {
+                        Ok(index) => LevelChoice::Index(index),
+                        Err(_) => LevelChoice::Name(level),
+                    });
+                }
+                "--players" => options.players = Some(parse_number(&name, &value()?)?),
+                "--ai" => options.ai = Some(parse_ai(&value()?)?),
+                "--seed" => options.seed = Some(parse_number(&name, &value()?)?),
+                "--windowed" => options.headless = false,
+                "--headless" => options.headless = true,
+                "--record" => options.record = Some(value()?),
+                "--play" => options.play = Some(value()?),
+                "--host" => options.host = Some(value()?),
+                "--join" => options.join = Some(value()?),
+                "-h" | "--help" => options.help = true,
+                _ => return Err(format!("unknown option {name}")),
+            }
+        }
+
+        if options.host.is_some() && options.join.is_some() {
+            return Err("--host and --join can't be used together".to_string());
+        }
+        Ok(options)
+    }
+
+    /// The parameters file to read.
+    pub fn config_path(&self) -> &str {
+        self.config.as_deref().unwrap_or(PARAMETERS_FILE_PATH)
+    }
+
+    /// Whether the match starts as the game does, skipping the menu.
+    pub fn starts_match(&self) -> bool {
+        self.headless
+            || self.level.is_some()
+            || self.players.is_some()
+            || self.ai.is_some()
+            || self.record.is_some()
+            || self.play.is_some()
+            || self.host.is_some()
+            || self.join.is_some()
+    }
+
+    /// Puts the options in `parameters`, read from the files.
+    pub fn apply(&self, parameters: &mut Parameters) {
+        if let Some(seed) = self.seed {
+            parameters.seed = Some(seed);
+        }
+        if let Some(count) = self.players {
+            parameters.players.resize(
+                count,
+                Player {
+                    controls: Vec::new(),
+                    ai: None,
+                },
+            );
+        }
+        if let Some(ai) = &self.ai {
+            for (i, player) in parameters.players.iter_mut().enumerate() {
+                player.ai = ai
+                    .iter()
+                    .find(|(played, _)| *played == i)
+                    .map(|(_, difficulty)| *difficulty);
+            }
+        }
+        if let Some(path) = &self.record {
+            parameters.replay.record = Some(path.clone());
+        }
+        if let Some(path) = &self.play {
+            parameters.replay.play = Some(path.clone());
+        }
+        let net = &mut parameters.net;
+        if let Some(address) = &self.host {
+            net.role = NetRole::Host;
+            net.address = address.clone();
+        }
+        if let Some(address) = &self.join {
+            net.role = NetRole::Join;
+            net.address = address.clone();
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{name} takes a whole number, not {value:?}"))
+}
+
+/// `--ai`'s list, as player indices.
+fn parse_ai(value: &str) -> Result<Vec<(usize, Difficulty)>, String> {
+    if value == "none" {
+        return Ok(Vec::new());
+    }
+    value
+        .split(',')
+        .map(|slot| {
+            let (player, difficulty) = slot.split_once(':').unwrap_or((slot, "medium"));
+            let player = player
+                .parse::<usize>()
+                .ok()
+                .and_then(|player| player.checked_sub(1))
+                .ok_or_else(|| format!("--ai takes player numbers from 1, not {player:?}"))?;
+            let difficulty = match difficulty {
+                "easy" => Difficulty::Easy,
+                "medium" => Difficulty::Medium,
+                "hard" => Difficulty::Hard,
+                _ => {
+                    return Err(format!(
+                        "--ai takes easy, medium or hard, not {difficulty:?}"
+                    ))
+                }
+            };
+            Ok((player, difficulty))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `Options::parse` on `args`, the program name left out.
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(ToString::to_string))
+    }
+
+    #[test]
+    fn values_follow_their_option_or_an_equals_sign() {
+        assert_eq!(parse(&["--seed=7"]).unwrap().seed, Some(7));
+        assert_eq!(parse(&["--seed", "7"]).unwrap().seed, Some(7));
+
+        let options = parse(&["--level", "arena", "--players=4", "--headless"]).unwrap();
+        assert_eq!(options.level, Some(LevelChoice::Name("arena".to_string())));
+        assert_eq!(options.players, Some(4));
+        assert!(options.headless && options.starts_match());
+        assert_eq!(
+            parse(&["--level=2"]).unwrap().level,
+            Some(LevelChoice::Index(2))
+        );
+        assert!(!parse(&["--seed", "7"]).unwrap().starts_match());
+    }
+
+    #[test]
+    fn ai_players_are_numbered_from_1_with_a_difficulty() {
+        let options = parse(&["--ai", "1,2:hard"]).unwrap();
+        assert_eq!(
+            options.ai,
+            Some(vec![(0, Difficulty::Medium), (1, Difficulty::Hard)])
+        );
+        assert_eq!(parse(&["--ai=none"]).unwrap().ai, Some(Vec::new()));
+
+        let mut parameters = Parameters::default();
+        options.apply(&mut parameters);
+        assert_eq!(parameters.players[0].ai, Some(Difficulty::Medium));
+        assert_eq!(parameters.players[1].ai, Some(Difficulty::Hard));
+    }
+
+    #[test]
+    fn mistakes_are_errors() {
+        let error = |args: &[&str]| parse(args).map(|_| ()).unwrap_err();
+        assert_eq!(error(&["--speed", "2"]), "unknown option --speed");
+        assert_eq!(error(&["windowed"]), "unknown option windowed");
+        assert_eq!(error(&["--seed"]), "--seed needs a value");
+        assert_eq!(error(&["--record"]), "--record needs a value");
+        assert!(error(&["--seed", "--headless"]).contains("whole number"));
+        assert!(error(&["--seed=-1"]).contains("whole number"));
+        assert!(error(&["--players", "two"]).contains("whole number"));
+        assert!(error(&["--ai", "0"]).contains("from 1"));
+        assert!(error(&["--ai", "1:impossible"]).contains("easy, medium or hard"));
+        assert!(
+            error(&["--host", "0.0.0.0:7777", "--join", "127.0.0.1:7777"]).contains("together")
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! The compact binary encoding of what goes over the network and into replay files: integers
+//! and floats in little-endian order, after a byte telling the version of the format.
+
+use bevy::math::{Vec2, Vec3};
+
+use crate::simulation::{Dash, PlayerInput, PowerShot};
+
+/// Little-endian encoding, starting with the version of the format.
+pub(crate) struct Writer {
+    pub(crate) bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new(version: u8) -> Self {
+        Self {
+            bytes: vec![version],
+        }
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    pub(crate) fn vec2(&mut self, value: Vec2) {
+        self.f32(value.x);
+        self.f32(value.y);
+    }
+
+    pub(crate) fn vec3(&mut self, value: Vec3) {
+        self.f32(value.x);
+        self.f32(value.y);
+        self.f32(value.z);
+    }
+
+    pub(crate) fn option<T>(&mut self, value: Option<T>, mut write: impl FnMut(&mut Self, T)) {
+        match value {
+            Some(value) => {
+                self.u8(1);
+                write(self, value);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    pub(crate) fn input(&mut self, input: &PlayerInput) {
+        self.vec3(input.direction);
+        self.option(input.dash, |writer, dash| {
+            writer.vec3(dash.direction);
+            writer.f32(dash.distance);
+            writer.f32(dash.cooldown);
+        });
+        self.f32(input.rotation);
+        self.f32(input.max_angle);
+        self.option(input.serve, Self::f32);
+        self.option(input.power, |writer, power| {
+            writer.f32(power.boost);
+            writer.f32(power.cooldown);
+        });
+    }
+}
+
+/// Reads what `Writer` wrote. Every read fails on truncated or malformed input rather than
+/// panicking, since anyone on the network can send anything, and files get damaged.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Starts reading, if `bytes` are in format `version`.
+    pub(crate) fn new(bytes: &'a [u8], version: u8) -> Option<Self> {
+        let (found, bytes) = bytes.split_first()?;
+        (*found == version).then_some(Self { bytes })
+    }
+
+    pub(crate) fn finished(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub(crate) fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.bytes.len() < N {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        taken.try_into().ok()
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        Some(self.take::<1>()?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take()?))
+    }
+
+    pub(crate) fn f32(&mut self) -> Option<f32> {
+        Some(f32::from_le_bytes(self.take()?))
+    }
+
+    pub(crate) fn string(&mut self) -> Option<String> {
+        let length = self.u32()? as usize;
+        if length > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        String::from_utf8(taken.to_vec()).ok()
+    }
+
+    pub(crate) fn vec2(&mut self) -> Option<Vec2> {
+        Some(Vec2::new(self.f32()?, self.f32()?))
+    }
+
+    pub(crate) fn vec3(&mut self) -> Option<Vec3> {
+        Some(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    pub(crate) fn option<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Self) -> Option<T>,
+    ) -> Option<Option<T>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => Some(Some(read(self)?)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    /// Reads a count followed by that many items. The count can't claim more items than bytes
+    /// left, so a forged one can't make it allocate much.
+    pub(crate) fn list<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Self) -> Option<T>,
+    ) -> Option<Vec<T>> {
+        let count = self.u32()? as usize;
+        if count > self.bytes.len() {
+            return None;
+        }
+        (0..count).map(|_| read(self)).collect()
+    }
+
+    pub(crate) fn input(&mut self) -> Option<PlayerInput> {
+        Some(PlayerInput {
+            direction: self.vec3()?,
+            dash: self.option(|reader| {
+                Some(Dash {
+                    direction: reader.vec3()?,
+                    distance: reader.f32()?,
+                    cooldown: reader.f32()?,
+                })
+            })?,
+            rotation: self.f32()?,
+            max_angle: self.f32()?,
+            serve: self.option(Self::f32)?,
+            power: self.option(|reader| {
+                Some(PowerShot {
+                    boost: reader.f32()?,
+                    cooldown: reader.f32()?,
+                })
+            })?,
+        })
+    }
+}
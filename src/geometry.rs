@@ -0,0 +1,555 @@
+//! Plane geometry shared by the simulation: shapes the ball can hit and the tests between them.
+
+use bevy::math::Vec2;
+
+/// Where a circle touches a shape: the direction that pushes the circle out of it, and how far
+/// the circle has sunk in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Contact {
+    pub normal: Vec2,
+    pub depth: f32,
+}
+
+/// Something a ball can bounce off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    /// Every point within `radius` of the segment `a`-`b`; a wall with rounded ends.
+    Capsule { a: Vec2, b: Vec2, radius: f32 },
+    /// A rectangle centered at `center`, rotated `angle` radians counter-clockwise.
+    Box {
+        center: Vec2,
+        half_size: Vec2,
+        angle: f32,
+    },
+}
+
+impl Shape {
+    pub fn contact_with_circle(&self, center: Vec2, radius: f32) -> Option<Contact> {
+        match *self {
+            Shape::Capsule {
+                a,
+                b,
+                radius: capsule_radius,
+            } => circle_capsule(center, radius, a, b, capsule_radius),
+            Shape::Box {
+                center: box_center,
+                half_size,
+                angle,
+            } => circle_box(center, radius, box_center, half_size, angle),
+        }
+    }
+}
+
+pub fn closest_point_on_segment(point: Vec2, a: Vec2, b: Vec2) -> Vec2 {
+    let ab = b - a;
+    let length_squared = ab.length_squared();
+    if length_squared == 0. {
+        return a;
+    }
+    let t = ((point - a).dot(ab) / length_squared).clamp(0., 1.);
+    a + ab * t
+}
+
+pub fn circle_capsule(
+    center: Vec2,
+    radius: f32,
+    a: Vec2,
+    b: Vec2,
+    capsule_radius: f32,
+) -> Option<Contact> {
+    let closest = closest_point_on_segment(center, a, b);
+    let offset = center - closest;
+    let distance = offset.length();
+    let reach = radius + capsule_radius;
+    if distance >= reach {
+        return None;
+    }
+
+    // With the center right on the segment any side works, so pick the segment's left
+    let normal = if distance > 0. {
+        offset / distance
+    } else {
+        (b - a).normalize_or_zero().perp()
+    };
+    Some(Contact {
+        normal,
+        depth: reach - distance,
+    })
+}
+
+pub fn circle_box(
+    center: Vec2,
+    radius: f32,
+    box_center: Vec2,
+    half_size: Vec2,
+    angle: f32,
+) -> Option<Contact> {
+    // Work in the box's frame, where it is axis-aligned and centered at the origin
+    let rotation = Vec2::from_angle(angle);
+    let unrotate = Vec2::from_angle(-angle);
+    let local_center = unrotate.rotate(center - box_center);
+
+    let closest = local_center.clamp(-half_size, half_size);
+    let offset = local_center - closest;
+    let distance = offset.length();
+
+    let (local_normal, depth) = if distance > 0. {
+        if distance >= radius {
+            return None;
+        }
+        (offset / distance, radius - distance)
+    } else {
+        // The center is inside the box: leave through the nearest face
+        let to_face = half_size - local_center.abs();
+        if to_face.x < to_face.y {
+            (Vec2::new(local_center.x.signum(), 0.), to_face.x + radius)
+        } else {
+            (Vec2::new(0., local_center.y.signum()), to_face.y + radius)
+        }
+    };
+
+    Some(Contact {
+        normal: rotation.rotate(local_normal),
+        depth,
+    })
+}
+
+/// Mirrors `velocity` about the line perpendicular to `normal`.
+pub fn reflect(velocity: Vec2, normal: Vec2) -> Vec2 {
+    velocity - 2. * velocity.dot(normal) * normal
+}
+
+/// Where a moving circle first touches a shape: the fraction of the motion travelled before
+/// touching, and the shape's normal at that point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub time: f32,
+    pub normal: Vec2,
+}
+
+impl Shape {
+    /// Sweeps a circle of `radius` from `start` along `motion`, returning the first time it
+    /// touches the shape. Circles that already overlap the shape are left to
+    /// `contact_with_circle`, as there is no well-defined first touch for them.
+    pub fn sweep_circle(&self, start: Vec2, motion: Vec2, radius: f32) -> Option<Hit> {
+        match *self {
+            Shape::Capsule {
+                a,
+                b,
+                radius: capsule_radius,
+            } => ray_capsule(start, motion, a, b, radius + capsule_radius),
+            Shape::Box {
+                center,
+                half_size,
+                angle,
+            } => {
+                // A box grown by a circle is covered by the capsules around its edges, and a ray
+                // coming from outside enters it through one of them
+                let rotation = Vec2::from_angle(angle);
+                let corners = [
+                    Vec2::new(-half_size.x, -half_size.y),
+                    Vec2::new(half_size.x, -half_size.y),
+                    Vec2::new(half_size.x, half_size.y),
+                    Vec2::new(-half_size.x, half_size.y),
+                ]
+                .map(|corner| center + rotation.rotate(corner));
+                (0..corners.len())
+                    .filter_map(|i| {
+                        let a = corners[i];
+                        let b = corners[(i + 1) % corners.len()];
+                        ray_capsule(start, motion, a, b, radius)
+                    })
+                    .min_by(|x, y| x.time.total_cmp(&y.time))
+            }
+        }
+    }
+}
+
+/// First time in `[0, 1]` the ray `start + t * motion` enters the capsule around `a`-`b`.
+fn ray_capsule(start: Vec2, motion: Vec2, a: Vec2, b: Vec2, radius: f32) -> Option<Hit> {
+    if motion == Vec2::ZERO {
+        return None;
+    }
+    let mut first: Option<Hit> = None;
+    let mut consider = |hit: Hit| {
+        if !first.is_some_and(|first| first.time <= hit.time) {
+            first = Some(hit);
+        }
+    };
+
+    // Flat sides
+    let ab = b - a;
+    let length_squared = ab.length_squared();
+    if length_squared > 0. {
+        let side_normal = ab.normalize().perp();
+        for normal in [side_normal, -side_normal] {
+            let approach = motion.dot(normal);
+            if approach >= 0. {
+                continue;
+            }
+            let distance = (start - a).dot(normal);
+            if distance < radius {
+                continue;
+            }
+            let time = (radius - distance) / approach;
+            if time > 1. {
+                continue;
+            }
+            let along = (start + motion * time - a).dot(ab) / length_squared;
+            if (0. ..=1.).contains(&along) {
+                consider(Hit { time, normal });
+            }
+        }
+    }
+
+    // Rounded ends
+    for end in [a, b] {
+        if let Some(hit) = ray_circle(start, motion, end, radius) {
+            consider(hit);
+        }
+    }
+
+    first
+}
+
+/// First time in `[0, 1]` the ray `start + t * motion` enters the circle around `center`.
+fn ray_circle(start: Vec2, motion: Vec2, center: Vec2, radius: f32) -> Option<Hit> {
+    let offset = start - center;
+    let b = offset.dot(motion);
+    let c = offset.length_squared() - radius * radius;
+    // Starting inside, or moving away
+    if c < 0. || b >= 0. {
+        return None;
+    }
+    let a = motion.length_squared();
+    let discriminant = b * b - a * c;
+    if discriminant < 0. {
+        return None;
+    }
+    let time = (-b - discriminant.sqrt()) / a;
+    if !(0. ..=1.).contains(&time) {
+        return None;
+    }
+    Some(Hit {
+        time,
+        normal: (start + motion * time - center) / radius,
+    })
+}
+
+/// An area made of one or more polygons, which may be non-convex.
+///
+/// Polygons with fewer than three points have no inside; they stand for the segment (or point)
+/// through them, so a paddle can be held on a rail.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Region {
+    pub polygons: Vec<Vec<Vec2>>,
+}
+
+impl Region {
+    /// Reads a list of points as polygons. Each polygon is closed implicitly, so the first point
+    /// does not need repeating; repeating it anyway closes the polygon and starts the next one.
+    pub fn from_points(points: &[Vec2]) -> Self {
+        let mut polygons = Vec::new();
+        let mut current: Vec<Vec2> = Vec::new();
+        for point in points {
+            if current.len() > 1 && current.first() == Some(point) {
+                polygons.push(std::mem::take(&mut current));
+            } else {
+                current.push(*point);
+            }
+        }
+        if !current.is_empty() {
+            polygons.push(current);
+        }
+        Self { polygons }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.polygons.is_empty()
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        self.polygons
+            .iter()
+            .any(|polygon| polygon.len() > 2 && point_in_polygon(point, polygon))
+    }
+
+    /// The point of the region nearest to `point`.
+    pub fn closest_point(&self, point: Vec2) -> Vec2 {
+        if self.is_empty() || self.contains(point) {
+            return point;
+        }
+        self.edges()
+            .map(|(a, b)| closest_point_on_segment(point, a, b))
+            .min_by(|x, y| {
+                x.distance_squared(point)
+                    .total_cmp(&y.distance_squared(point))
+            })
+            .unwrap_or(point)
+    }
+
+    /// Moves from `from` along `motion` without leaving the region. Motion pushing out of the
+    /// region is projected onto its border, so whatever moves slides along the edges instead of
+    /// stopping dead.
+    pub fn slide(&self, from: Vec2, motion: Vec2) -> Vec2 {
+        if self.is_empty() {
+            return from + motion;
+        }
+
+        // Short substeps keep each projection local, so it can't jump across a notch of a
+        // non-convex polygon or into a neighbouring polygon
+        let substeps = (motion.length() / REGION_SUBSTEP).ceil().max(1.);
+        let substep = motion / substeps;
+        let mut position = self.closest_point(from);
+        for _ in 0..substeps as usize {
+            position = self.closest_point(position + substep);
+        }
+        position
+    }
+
+    /// Every edge of every polygon, including the one closing it.
+    fn edges(&self) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        self.polygons.iter().flat_map(|polygon| {
+            // A segment or a point has a single edge
+            let count = if polygon.len() > 2 { polygon.len() } else { 1 };
+            (0..count).map(move |i| (polygon[i], polygon[(i + 1) % polygon.len()]))
+        })
+    }
+}
+
+/// Longest move `Region::slide` makes before projecting back onto the region.
+const REGION_SUBSTEP: f32 = 2.;
+
+/// Even-odd test, which holds for non-convex polygons too.
+pub fn point_in_polygon(point: Vec2, polygon: &[Vec2]) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[j]);
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < (b.x - a.x) * (point.y - a.y) / (b.y - a.y) + a.x
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Even-odd test against loose segments, such as the walls of a level: inside the rings they
+/// form, but outside any ring inside those, like an obstacle.
+pub fn point_enclosed(point: Vec2, segments: impl IntoIterator<Item = (Vec2, Vec2)>) -> bool {
+    let mut inside = false;
+    for (a, b) in segments {
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < (b.x - a.x) * (point.y - a.y) / (b.y - a.y) + a.x
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How far a ball at the top speed of the default game moves in a step of 1/64 s.
+    const FAST_STEP: f32 = 2000. / 64.;
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{actual} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn fast_ball_does_not_pass_through_a_thin_wall() {
+        // A 10 unit wall, and a small ball that would end the step clear on the other side
+        let wall = Shape::Capsule {
+            a: Vec2::new(0., -50.),
+            b: Vec2::new(0., 50.),
+            radius: 5.,
+        };
+        let start = Vec2::new(-12., 0.);
+        let motion = Vec2::new(FAST_STEP, 0.);
+        assert!(wall.contact_with_circle(start + motion, 2.).is_none());
+
+        let hit = wall
+            .sweep_circle(start, motion, 2.)
+            .expect("the wall is in the way");
+        assert_near(hit.time, 5. / FAST_STEP);
+        assert_eq!(hit.normal, Vec2::NEG_X);
+    }
+
+    #[test]
+    fn fast_ball_does_not_pass_through_a_paddle() {
+        // A paddle 20 units wide, turned a quarter and lying across the ball's way
+        let paddle = Shape::Box {
+            center: Vec2::new(100., 0.),
+            half_size: Vec2::new(60., 10.),
+            angle: std::f32::consts::FRAC_PI_2,
+        };
+        let start = Vec2::new(84., 0.);
+        let motion = Vec2::new(FAST_STEP, 0.);
+        assert!(paddle.contact_with_circle(start + motion, 2.).is_none());
+
+        let hit = paddle
+            .sweep_circle(start, motion, 2.)
+            .expect("the paddle is in the way");
+        assert_near(hit.time, 4. / FAST_STEP);
+        assert_near(hit.normal.x, -1.);
+        assert_near(hit.normal.y, 0.);
+    }
+
+    #[test]
+    fn sweep_hits_the_rounded_end_of_a_wall() {
+        let wall = Shape::Capsule {
+            a: Vec2::new(0., -50.),
+            b: Vec2::new(0., 50.),
+            radius: 5.,
+        };
+        let hit = wall
+            .sweep_circle(Vec2::new(0., 80.), Vec2::new(0., -FAST_STEP), 2.)
+            .expect("the end of the wall is in the way");
+        assert_near(hit.time, 23. / FAST_STEP);
+        assert_near(hit.normal.y, 1.);
+
+        // Passing beyond the end misses it
+        let beside = Vec2::new(-12., 60.);
+        assert!(wall
+            .sweep_circle(beside, Vec2::new(FAST_STEP, 0.), 2.)
+            .is_none());
+    }
+
+    #[test]
+    fn sweep_ignores_what_the_ball_moves_away_from() {
+        let wall = Shape::Capsule {
+            a: Vec2::new(0., -50.),
+            b: Vec2::new(0., 50.),
+            radius: 5.,
+        };
+        assert!(wall
+            .sweep_circle(Vec2::new(-12., 0.), Vec2::new(-FAST_STEP, 0.), 2.)
+            .is_none());
+        // Already touching: left to `contact_with_circle`
+        assert!(wall
+            .sweep_circle(Vec2::new(-6., 0.), Vec2::new(FAST_STEP, 0.), 2.)
+            .is_none());
+    }
+
+    /// An L: a wide arm along the bottom and a tall one up the left, with the reflex corner at
+    /// (40, 40).
+    fn l_shape() -> Region {
+        Region::from_points(&[
+            Vec2::new(0., 0.),
+            Vec2::new(100., 0.),
+            Vec2::new(100., 40.),
+            Vec2::new(40., 40.),
+            Vec2::new(40., 100.),
+            Vec2::new(0., 100.),
+        ])
+    }
+
+    fn assert_near_point(actual: Vec2, expected: Vec2) {
+        assert!(
+            actual.distance(expected) < 1e-3,
+            "{actual} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn point_in_a_non_convex_polygon() {
+        let region = l_shape();
+        assert!(region.contains(Vec2::new(20., 80.)));
+        assert!(region.contains(Vec2::new(80., 20.)));
+        // The notch between the arms
+        assert!(!region.contains(Vec2::new(70., 70.)));
+    }
+
+    #[test]
+    fn slides_along_an_edge_of_a_non_convex_polygon() {
+        let region = l_shape();
+        // Pushed down and right out of the wide arm, it keeps the sideways part of the motion
+        assert_near_point(
+            region.slide(Vec2::new(50., 20.), Vec2::new(40., -40.)),
+            Vec2::new(90., 0.),
+        );
+        // Pushed along and out of the tall arm, across the notch, it stays on the arm's edge
+        assert_near_point(
+            region.slide(Vec2::new(20., 80.), Vec2::new(60., 10.)),
+            Vec2::new(40., 90.),
+        );
+    }
+
+    #[test]
+    fn crosses_between_touching_polygons() {
+        // Two squares sharing the edge x = 50
+        let region = Region::from_points(&[
+            Vec2::new(0., 0.),
+            Vec2::new(50., 0.),
+            Vec2::new(50., 50.),
+            Vec2::new(0., 50.),
+            Vec2::new(0., 0.),
+            Vec2::new(50., 0.),
+            Vec2::new(100., 0.),
+            Vec2::new(100., 50.),
+            Vec2::new(50., 50.),
+        ]);
+        assert_eq!(region.polygons.len(), 2);
+        assert_near_point(
+            region.slide(Vec2::new(25., 25.), Vec2::new(50., 0.)),
+            Vec2::new(75., 25.),
+        );
+        // And on to the far edge of the second one
+        assert_near_point(
+            region.slide(Vec2::new(25., 25.), Vec2::new(100., 0.)),
+            Vec2::new(100., 25.),
+        );
+    }
+
+    #[test]
+    fn stops_against_the_edges_of_a_reflex_corner() {
+        let region = l_shape();
+        // Pushed straight into either edge meeting at the corner, there is nothing to slide along
+        let stopped = region.slide(Vec2::new(60., 30.), Vec2::new(0., 30.));
+        assert_near_point(stopped, Vec2::new(60., 40.));
+        assert_near_point(region.slide(stopped, Vec2::new(0., 5.)), stopped);
+        let stopped = region.slide(Vec2::new(30., 60.), Vec2::new(30., 0.));
+        assert_near_point(stopped, Vec2::new(40., 60.));
+        assert_near_point(region.slide(stopped, Vec2::new(5., 0.)), stopped);
+
+        // Pushed from the corner into the notch, it stays on the border
+        let mut position = Vec2::new(40., 40.);
+        for _ in 0..20 {
+            position = region.slide(position, Vec2::new(3., 3.));
+            assert_near_point(region.closest_point(position), position);
+            assert!(
+                position.x <= 40. || position.y <= 40.,
+                "{position} is in the notch"
+            );
+        }
+    }
+
+    #[test]
+    fn encloses_points_between_loose_walls() {
+        // A square of walls around an obstacle
+        let square = [
+            (Vec2::new(0., 0.), Vec2::new(100., 0.)),
+            (Vec2::new(100., 0.), Vec2::new(100., 100.)),
+            (Vec2::new(100., 100.), Vec2::new(0., 100.)),
+            (Vec2::new(0., 100.), Vec2::new(0., 0.)),
+        ];
+        let obstacle = [
+            (Vec2::new(40., 40.), Vec2::new(60., 40.)),
+            (Vec2::new(60., 40.), Vec2::new(60., 60.)),
+            (Vec2::new(60., 60.), Vec2::new(40., 60.)),
+            (Vec2::new(40., 60.), Vec2::new(40., 40.)),
+        ];
+        let walls = || square.iter().chain(&obstacle).copied();
+        assert!(point_enclosed(Vec2::new(20., 50.), walls()));
+        assert!(!point_enclosed(Vec2::new(50., 50.), walls()));
+        assert!(!point_enclosed(Vec2::new(150., 50.), walls()));
+    }
+}
@@ -0,0 +1,118 @@
+//! Reading the devices players bind their controls to: keyboard, gamepads and mouse.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+
+use crate::types::parameters::Binding;
+
+/// Every device a `Binding` can read.
+#[derive(SystemParam)]
+pub struct Devices<'w, 's> {
+    keyboard: Res<'w, Input<KeyCode>>,
+    gamepads: Res<'w, Gamepads>,
+    buttons: Res<'w, Input<GamepadButton>>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+    windows: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+    cameras: Query<'w, 's, (&'static Camera, &'static GlobalTransform)>,
+}
+
+impl Devices<'_, '_> {
+    /// How much `binding` is triggered, from -1 to 1. The mouse is measured against `paddle`,
+    /// the position of the paddle the binding moves, and pushes it at full speed when it is
+    /// further away than `reach`, the distance the paddle covers in one step.
+    pub fn amount(&self, binding: &Binding, paddle: Vec3, reach: f32) -> f32 {
+        match binding {
+            Binding::Key(key) => pressed(self.keyboard.pressed((*key).into())),
+            Binding::GamepadButton { gamepad, button } => match self.gamepad(*gamepad) {
+                Some(gamepad) => pressed(
+                    self.buttons
+                        .pressed(GamepadButton::new(gamepad, (*button).into())),
+                ),
+                None => 0.,
+            },
+            Binding::GamepadAxis {
+                gamepad,
+                axis,
+                dead_zone,
+            } => {
+                let value = self
+                    .gamepad(*gamepad)
+                    .and_then(|gamepad| self.axes.get(GamepadAxis::new(gamepad, (*axis).into())))
+                    .unwrap_or(0.);
+                outside_dead_zone(value, *dead_zone)
+            }
+            Binding::MouseY => match self.cursor() {
+                Some(cursor) if reach > 0. => ((cursor.y - paddle.y) / reach).clamp(-1., 1.),
+                _ => 0.,
+            },
+        }
+    }
+
+    /// The `index`th connected gamepad, ordered by id so the numbering doesn't change between
+    /// frames.
+    fn gamepad(&self, index: usize) -> Option<Gamepad> {
+        let mut gamepads: Vec<_> = self.gamepads.iter().collect();
+        gamepads.sort_by_key(|gamepad| gamepad.id);
+        gamepads.get(index).copied()
+    }
+
+    /// Where the mouse cursor is, in world coordinates.
+    fn cursor(&self) -> Option<Vec2> {
+        let cursor = self.windows.get_single().ok()?.cursor_position()?;
+        let (camera, transform) = self.cameras.get_single().ok()?;
+        camera.viewport_to_world_2d(transform, cursor)
+    }
+}
+
+fn pressed(pressed: bool) -> f32 {
+    if pressed {
+        1.
+    } else {
+        0.
+    }
+}
+
+/// Zero inside the dead zone, then rising to 1 at the end of the stick's travel, so the paddle
+/// doesn't jump to a fraction of its speed when the stick leaves the dead zone.
+fn outside_dead_zone(value: f32, dead_zone: f32) -> f32 {
+    let dead_zone = dead_zone.clamp(0., 0.99);
+    if value.abs() <= dead_zone {
+        return 0.;
+    }
+    value.signum() * (value.abs() - dead_zone) / (1. - dead_zone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether `a` and `b` are equal but for rounding.
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn sticks_are_rescaled_past_the_dead_zone() {
+        for value in [0., 0.1, -0.2, 0.25, -0.25] {
+            assert_eq!(outside_dead_zone(value, 0.25), 0.);
+        }
+        assert!(close(outside_dead_zone(0.625, 0.25), 0.5));
+        assert!(close(outside_dead_zone(-0.625, 0.25), -0.5));
+        assert!(close(outside_dead_zone(1., 0.25), 1.));
+        assert!(close(outside_dead_zone(-1., 0.25), -1.));
+        // Without a dead zone, the stick is passed through
+        assert!(close(outside_dead_zone(0.3, 0.), 0.3));
+        assert!(close(outside_dead_zone(0.3, -1.), 0.3));
+    }
+
+    #[test]
+    fn dead_zones_stop_short_of_the_whole_stick() {
+        // A dead zone of 1 or more would never move the paddle, or divide by zero
+        for dead_zone in [0.99, 1., 5.] {
+            assert_eq!(outside_dead_zone(0.99, dead_zone), 0.);
+            assert!(close(outside_dead_zone(1., dead_zone), 1.));
+            assert!(close(outside_dead_zone(-0.995, dead_zone), -0.5));
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Levels kept in their own files under `assets/levels/`, loaded through the asset server.
+//!
+//! Every `*.level.toml` file of the folder holds one `Level`, written like an entry of
+//! `Parameters.levels`. They are played after the levels of the parameters file, ordered by file
+//! name. The folder is watched: saving a level file while it is played restarts it with the
+//! changes.
+
+use std::any::TypeId;
+
+use bevy::app::AppExit;
+use bevy::asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext, LoadedFolder};
+use bevy::asset::{LoadState, RecursiveDependencyLoadState};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+
+use crate::parameters::{unknown_keys, validate_level, ConfigError, ConfigProblem};
+use crate::types::parameters::{Level, Parameters};
+use crate::types::resources::{CurrentLevel, LevelName};
+use crate::types::states::AppStates;
+
+/// Folder of the level files, relative to `assets/`.
+pub const LEVELS_FOLDER: &str = "levels";
+
+/// End of the names of level files, see `LevelLoader::extensions`.
+const LEVEL_EXTENSION: &str = ".level.toml";
+
+/// A level file, as read from disk.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct LevelAsset {
+    pub text: String,
+    /// The level, or what kept it from being read.
+    pub level: Result<Level, ConfigError>,
+}
+
+impl LevelAsset {
+    /// The level, if it is also playable by `n_players`.
+    pub fn validated(&self, file: &str, n_players: usize) -> Result<Level, ConfigError> {
+        let level = self.level.clone()?;
+        let problems = validate_level("", &level, n_players);
+        if !problems.is_empty() {
+            return Err(ConfigError::new(file, problems).locate(&self.text));
+        }
+        Ok(level)
+    }
+}
+
+#[derive(Default)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    type Asset = LevelAsset;
+    type Settings = ();
+    type Error = std::io::Error;
+
+    // A file that isn't a level still loads, carrying its problems, so they can be shown in game
+    // like those of the parameters file
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<LevelAsset, std::io::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let file = load_context.path().display().to_string();
+            let text = String::from_utf8_lossy(&bytes).into_owned();
+            let level = read_level(&file, &text);
+            Ok(LevelAsset { text, level })
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.toml"]
+    }
+}
+
+fn read_level(file: &str, text: &str) -> Result<Level, ConfigError> {
+    let value: toml::Value =
+        toml::from_str(text).map_err(|error| ConfigError::from_toml(file, text, error))?;
+    let level: Level =
+        toml::from_str(text).map_err(|error| ConfigError::from_toml(file, text, error))?;
+    let problems = unknown_keys(&value, &level);
+    if !problems.is_empty() {
+        return Err(ConfigError::new(file, problems).locate(text));
+    }
+    Ok(level)
+}
+
+/// The level folder, while it is being loaded.
+#[derive(Resource)]
+pub struct LevelFolder(pub Handle<LoadedFolder>);
+
+/// Level files found in the folder, ordered by file name.
+#[derive(Resource, Default)]
+pub struct FolderLevels {
+    pub files: Vec<(String, Handle<LevelAsset>)>,
+}
+
+impl FolderLevels {
+    /// How many levels there are to play in all.
+    pub fn count(&self, parameters: &Parameters) -> usize {
+        parameters.levels.len() + self.files.len()
+    }
+
+    /// The level file played as level `index` of the run, if that level comes from a file.
+    pub fn file(
+        &self,
+        parameters: &Parameters,
+        index: usize,
+    ) -> Option<&(String, Handle<LevelAsset>)> {
+        index
+            .checked_sub(parameters.levels.len())
+            .and_then(|index| self.files.get(index))
+    }
+
+    /// Index of the level played from the file named `name`, without its folder and extension.
+    pub fn index_of(&self, parameters: &Parameters, name: &str) -> Option<usize> {
+        let position = self
+            .files
+            .iter()
+            .position(|(file, _)| level_name(file) == name)?;
+        Some(parameters.levels.len() + position)
+    }
+
+    /// Level `index` of the run: from the parameters file first, then from the level files.
+    pub fn level(
+        &self,
+        parameters: &Parameters,
+        assets: &Assets<LevelAsset>,
+        index: usize,
+    ) -> Option<Result<Level, ConfigError>> {
+        if let Some(level) = parameters.levels.get(index) {
+            return Some(Ok(level.clone()));
+        }
+        let (file, handle) = self.file(parameters, index)?;
+        let level = match assets.get(handle) {
+            Some(asset) => asset.validated(file, parameters.players.len()),
+            None => Err(ConfigError::single(
+                file,
+                ConfigProblem::new("", "the level file isn't loaded"),
+            )),
+        };
+        Some(level)
+    }
+}
+
+/// A level file's name, without its folder and extension.
+fn level_name(file: &str) -> &str {
+    let name = file.rsplit('/').next().unwrap_or(file);
+    name.strip_suffix(LEVEL_EXTENSION).unwrap_or(name)
+}
+
+pub fn load_level_folder(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(LevelFolder(asset_server.load_folder(LEVELS_FOLDER)));
+    commands.init_resource::<FolderLevels>();
+}
+
+/// Holds `AppStates::Loading` until every level file has been read. A match started on a named
+/// level then starts there; a name no file has quits the game, as the command line was wrong.
+#[allow(clippy::too_many_arguments)]
+pub fn wait_for_level_files(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    folder: Res<LevelFolder>,
+    folders: Res<Assets<LoadedFolder>>,
+    parameters: Res<Parameters>,
+    named: Option<Res<LevelName>>,
+    mut folder_levels: ResMut<FolderLevels>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut next_state: ResMut<NextState<AppStates>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let start = |folder_levels: &FolderLevels| {
+        if let Some(name) = named {
+            commands.remove_resource::<LevelName>();
+            match folder_levels.index_of(&parameters, &name.0) {
+                Some(index) => **current_level = index,
+                None => {
+                    let names: Vec<_> = folder_levels
+                        .files
+                        .iter()
+                        .map(|(file, _)| level_name(file))
+                        .collect();
+                    eprintln!(
+                        "No level file is named {:?}; there are: {}",
+                        name.0,
+                        names.join(", ")
+                    );
+                    exit.send(AppExit);
+                    return;
+                }
+            }
+        }
+        next_state.set(AppStates::Level);
+    };
+
+    // Without a folder there are only the levels of the parameters file
+    if let Some(LoadState::Failed) = asset_server.get_load_state(&folder.0) {
+        folder_levels.files.clear();
+        start(&folder_levels);
+        return;
+    }
+    match asset_server.get_recursive_dependency_load_state(&folder.0) {
+        Some(RecursiveDependencyLoadState::Loaded) => {}
+        Some(RecursiveDependencyLoadState::Failed) => {
+            warn!("some files in assets/{LEVELS_FOLDER} could not be read");
+        }
+        _ => return,
+    }
+
+    let Some(loaded) = folders.get(&folder.0) else {
+        return;
+    };
+    let mut files: Vec<_> = loaded
+        .handles
+        .iter()
+        .filter_map(|handle| {
+            // The folder may hold other files than levels; only those loaded as levels count
+            if handle.type_id() != TypeId::of::<LevelAsset>() {
+                return None;
+            }
+            let path = handle.path()?.to_string();
+            Some((path, handle.clone().typed::<LevelAsset>()))
+        })
+        .collect();
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    folder_levels.files = files;
+    start(&folder_levels);
+}
+
+/// Restarts the level being played when its file changes on disk.
+pub fn restart_on_level_change(
+    mut events: EventReader<AssetEvent<LevelAsset>>,
+    parameters: Res<Parameters>,
+    folder_levels: Res<FolderLevels>,
+    current_level: Res<CurrentLevel>,
+    mut next_state: ResMut<NextState<AppStates>>,
+) {
+    let Some((file, handle)) = folder_levels.file(&parameters, **current_level) else {
+        events.clear();
+        return;
+    };
+    for event in events.read() {
+        if event.is_modified(handle) {
+            info!("[{file}] changed, restarting the level");
+            next_state.set(AppStates::Loading);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::simulation::Simulation;
+
+    fn levels_folder() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join(LEVELS_FOLDER)
+    }
+
+    fn read_file(path: PathBuf) -> (String, String) {
+        let file = path.display().to_string();
+        let text = fs::read_to_string(&path).unwrap_or_else(|error| panic!("{file}: {error}"));
+        (file, text)
+    }
+
+    #[test]
+    fn level_files_are_playable_with_the_default_players() {
+        let n_players = Parameters::default().players.len();
+        let mut files = 0;
+        for entry in fs::read_dir(levels_folder()).unwrap() {
+            let (file, text) = read_file(entry.unwrap().path());
+            if !file.ends_with(LEVEL_EXTENSION) {
+                continue;
+            }
+            let asset = LevelAsset {
+                level: read_level(&file, &text),
+                text,
+            };
+            if let Err(error) = asset.validated(&file, n_players) {
+                panic!("{error}");
+            }
+            files += 1;
+        }
+        assert!(files > 0);
+    }
+
+    #[test]
+    fn goal_bricks_are_a_level_of_their_own() {
+        let parameters = Parameters::default();
+        let standard = Simulation::new(&parameters, &parameters.levels[0], 0);
+        assert!(standard.bricks.is_empty());
+
+        let (file, text) =
+            read_file(levels_folder().join(format!("02-goal-bricks{LEVEL_EXTENSION}")));
+        let level = read_level(&file, &text).unwrap();
+        let guarded = Simulation::new(&parameters, &level, 0);
+        assert!(!guarded.bricks.is_empty());
+        assert!(guarded.bricks.iter().all(|brick| brick.guards.is_some()));
+    }
+}
@@ -1,85 +1,343 @@
 //! A simplified implementation of the classic game "Breakout".
 
-pub mod types;
+pub mod ai;
+pub mod arena;
+pub mod cli;
+pub mod codec;
+pub mod geometry;
+pub mod input;
+pub mod levels;
+pub mod migration;
+pub mod net;
 pub mod parameters;
+pub mod replay;
+pub mod rollback;
+pub mod simulation;
+pub mod types;
 
+use std::time::Duration;
+
+use bevy::app::{AppExit, ScheduleRunnerPlugin};
+use bevy::asset::AssetPlugin;
 use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
-use bevy::{
-    prelude::*,
-    sprite::collide_aabb::{collide, Collision},
-    sprite::MaterialMesh2dBundle,
-};
+use bevy::render::{settings::WgpuSettings, RenderPlugin};
+use bevy::window::ExitCondition;
+use bevy::winit::WinitPlugin;
+use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
+use ai::AiController;
+use cli::{LevelChoice, Options, USAGE};
+use input::Devices;
+use levels::{FolderLevels, LevelAsset, LevelLoader};
+use net::{NetClient, NetMode, NetServer};
+use parameters::{validate_level, ConfigError};
+use replay::{Playback, Recorder, Replay};
+use rollback::Rollback;
+use simulation::{PlayerInput, Rally, Simulation, SimulationEvent, TIMESTEP};
 use types::bundles::{PlayerBundle, WallBundle};
-use types::components::{Ball, Brick, Collider, Paddle, Player, Velocity, Wall};
+use types::components::{
+    BallId, Brick, BrickId, ClockText, CountdownText, LevelEntity, PaddleIndex, PickupId, Player,
+    PowerUpText, ReplayText, ScoreboardIndex, ShieldIndex,
+};
 use types::events::CollisionEvent;
-use types::parameters::{parameters_from_toml, Effect};
-use types::resources::{CollisionSound, Scoreboards};
+use types::parameters::{
+    parameters_for_level, parameters_from_toml, Arena, Level, NetRole, Parameters, ParametersColors,
+};
+use types::resources::{
+    AiControllers, BallAssets, CollisionSound, CommandLine, ConfigErrors, CurrentLevel, GameRng,
+    GameRollback, GameSimulation, Hosting, Joined, LevelName, LevelResult, MatchState,
+    PickupAssets, Playing, Recording, Scoreboards,
+};
 use types::states::AppStates;
 
+/// Players whose scoreboards fit along the top at the configured font size; with more, the text
+/// shrinks.
+const SCOREBOARDS_AT_FULL_SIZE: f32 = 3.;
+
+/// How far back or forward the arrow keys move a replay.
+const SEEK_SECONDS: f32 = 5.;
+
 fn main() {
     println!("debug_assertions is {:?}", cfg!(debug_assertions));
 
-    let parameters = parameters_from_toml();
-    
-    let mut dyn_list: Vec<Box<dyn _>> = vec![];
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{error}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{USAGE}");
+        return;
+    }
 
-    dyn_list.push(Box::new( 0u16 ));
-    dyn_list.push(Box::new( "hello" ));
+    // A broken parameters file shouldn't crash the game: fall back to the defaults so the window
+    // can open, and list what's wrong instead of the menu
+    let (parameters, config_error) = match parameters_from_toml(&options) {
+        Ok(parameters) => (parameters, None),
+        Err(error) => {
+            eprintln!("{error}");
+            // Without a window, there is nowhere to list it
+            if options.headless {
+                std::process::exit(1);
+            }
+            let mut parameters = Parameters::default();
+            options.apply(&mut parameters);
+            (parameters, Some(error))
+        }
+    };
 
-    println!("{:?}", dyn_list);
+    let seed = parameters.seed.unwrap_or_else(|| {
+        // Within TOML's integers, so it can go in the parameters file
+        rand::thread_rng().gen_range(0..=i64::MAX as u64)
+    });
+    println!("Seed {seed} (play it again with --seed {seed})");
 
-    App::new()
-        .add_plugins(DefaultPlugins)
-        .add_plugins(FrameTimeDiagnosticsPlugin)
+    let mut app = App::new();
+    let plugins = DefaultPlugins.set(AssetPlugin {
+        // Level files are reloaded as they are saved
+        watch_for_changes_override: Some(true),
+        ..default()
+    });
+    if options.headless {
+        // Nothing is drawn and no window opens, but the schedules run as they would
+        app.add_plugins((
+            plugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    ..default()
+                })
+                .set(RenderPlugin {
+                    render_creation: WgpuSettings {
+                        backends: None,
+                        ..default()
+                    }
+                    .into(),
+                })
+                .disable::<WinitPlugin>(),
+            ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(TIMESTEP as f64)),
+        ));
+    } else {
+        app.add_plugins(plugins);
+    }
+    app.add_plugins(FrameTimeDiagnosticsPlugin)
         .add_state::<AppStates>()
+        .add_state::<menu::AppState>()
         .add_event::<CollisionEvent>()
+        .init_asset::<LevelAsset>()
+        .init_asset_loader::<LevelLoader>()
+        .insert_resource(Time::<Fixed>::from_seconds(TIMESTEP as f64))
         .insert_resource(Scoreboards {
             scores: vec![0.; parameters.players.len()],
         })
         .insert_resource(ClearColor(parameters.colors.background))
-        .add_systems(OnEnter(menu::AppState::Menu), menu::setup_menu)
+        .insert_resource(GameRng(StdRng::seed_from_u64(seed)))
+        .init_resource::<CurrentLevel>()
+        .init_resource::<MatchState>()
+        .add_systems(Startup, (menu::setup, levels::load_level_folder))
+        .add_systems(
+            OnEnter(menu::AppState::Menu),
+            (menu::setup_menu, stop_networking, stop_replays),
+        )
         .add_systems(OnExit(menu::AppState::Menu), menu::cleanup_menu)
-        .add_systems(OnEnter(menu::AppState::InGame), setup_level)
+        .add_systems(
+            OnEnter(menu::AppState::Controls),
+            menu::controls::setup_controls,
+        )
+        .add_systems(
+            OnExit(menu::AppState::Controls),
+            menu::controls::cleanup_controls,
+        )
+        .add_systems(
+            OnEnter(menu::AppState::ConfigError),
+            menu::setup_config_error,
+        )
+        .add_systems(OnEnter(menu::AppState::InGame), start_levels)
+        .add_systems(OnEnter(AppStates::Level), setup_level)
+        .add_systems(OnExit(AppStates::Level), despawn_level)
+        .add_systems(
+            Update,
+            (
+                levels::restart_on_level_change.run_if(in_state(AppStates::Level)),
+                control_playback.run_if(
+                    in_state(AppStates::Level)
+                        .and_then(resource_exists::<Playing>())
+                        .and_then(resource_exists::<GameSimulation>()),
+                ),
+                // Presses are read every frame, so none is missed between fixed steps
+                (
+                    menu::controls::run_controls,
+                    menu::controls::capture_binding,
+                    menu::controls::update_controls_labels,
+                )
+                    .chain()
+                    .run_if(in_state(menu::AppState::Controls)),
+            ),
+        )
+        .add_systems(OnEnter(AppStates::Intermission), menu::setup_intermission)
+        .add_systems(OnExit(AppStates::Intermission), menu::cleanup_intermission)
+        .add_systems(OnEnter(AppStates::GameOver), menu::setup_game_over)
+        .add_systems(OnExit(AppStates::GameOver), menu::cleanup_game_over)
         .add_systems(
             FixedUpdate,
             (
                 menu::run_menu.run_if(in_state(menu::AppState::Menu)),
+                // Once there is nothing left to play
+                quit_headless.after(broadcast_snapshots).run_if(
+                    headless.and_then(
+                        in_state(menu::AppState::Menu)
+                            .or_else(in_state(menu::AppState::ConfigError))
+                            .or_else(in_state(AppStates::GameOver)),
+                    ),
+                ),
+                levels::wait_for_level_files.run_if(in_state(AppStates::Loading)),
+                bevy::window::close_on_esc.run_if(in_state(menu::AppState::ConfigError)),
+                // Clients are served in every state, so they follow the host between sets too
+                poll_clients
+                    .before(step_simulation)
+                    .run_if(resource_exists::<Hosting>()),
+                broadcast_snapshots
+                    .after(check_level_over)
+                    .run_if(resource_exists::<Hosting>()),
+                run_client
+                    .before(step_simulation)
+                    .run_if(resource_exists::<Joined>()),
+                send_frames
+                    .after(check_level_over)
+                    .run_if(resource_exists::<Joined>()),
+                receive_frames
+                    .after(poll_clients)
+                    .after(run_client)
+                    .before(step_simulation)
+                    .run_if(resource_exists::<GameRollback>()),
                 (
-                    menu::movement,
-                    menu::change_color,
-                    apply_velocity,
-                    move_players,
-                    check_for_collisions,
+                    // A client only draws the host's snapshots, unless it simulates too with rollback
+                    step_simulation.run_if(
+                        not(resource_exists::<Joined>())
+                            .or_else(resource_exists::<GameRollback>())
+                            .and_then(not(resource_exists::<Playing>())),
+                    ),
+                    run_playback.run_if(resource_exists::<Playing>()),
+                    sync_paddles,
+                    sync_balls,
+                    sync_bricks,
+                    sync_pickups,
+                    update_shields,
                     play_collision_sound,
                     update_scoreboards,
+                    update_countdown,
+                    update_clock,
+                    update_power_ups,
+                    update_replay_text.run_if(resource_exists::<Playing>()),
+                    // A replay stays on its last step rather than moving on
+                    check_level_over.run_if(not(resource_exists::<Playing>())),
                     bevy::window::close_on_esc,
                 )
                     .chain()
-                    .run_if(in_state(menu::AppState::InGame)),
+                    // The level's resources are only there from the frame after it is entered
+                    .run_if(
+                        in_state(AppStates::Level).and_then(resource_exists::<GameSimulation>()),
+                    ),
+                (
+                    menu::run_intermission.run_if(not(resource_exists::<Joined>())),
+                    bevy::window::close_on_esc,
+                )
+                    .run_if(in_state(AppStates::Intermission)),
+                (
+                    menu::run_game_over.run_if(not(resource_exists::<Joined>())),
+                    bevy::window::close_on_esc,
+                )
+                    .run_if(in_state(AppStates::GameOver)),
             ),
-        )
+        );
+
+    if let Some(error) = config_error {
+        app.insert_resource(ConfigErrors(error))
+            .insert_resource(NextState(Some(menu::AppState::ConfigError)));
+    } else if options.starts_match() {
+        app.insert_resource(NextState(Some(menu::AppState::InGame)));
+    }
+
+    app.insert_resource(CommandLine(options))
+        .insert_resource(parameters)
         .run();
 }
 
+fn headless(command_line: Res<CommandLine>) -> bool {
+    command_line.headless
+}
+
+/// Ends a run without a window, as no one is there to press a key.
+fn quit_headless(mut exit: EventWriter<AppExit>) {
+    exit.send(AppExit);
+}
+
+/// Starts the match on the level chosen on the command line, or else on the first one.
+fn start_on_chosen_level(
+    options: &Options,
+    commands: &mut Commands,
+    current_level: &mut CurrentLevel,
+) {
+    **current_level = 0;
+    match &options.level {
+        Some(LevelChoice::Index(index)) => **current_level = *index,
+        // Found once the level files are loaded
+        Some(LevelChoice::Name(name)) => commands.insert_resource(LevelName(name.clone())),
+        None => {}
+    }
+}
+
 mod menu {
     use bevy::prelude::*;
 
+    use crate::levels::FolderLevels;
+    use crate::types::parameters::Parameters;
+    use crate::types::resources::{
+        CommandLine, ConfigErrors, CurrentLevel, Joined, LevelResult, MatchState,
+    };
+    use crate::types::states::AppStates;
+
+    pub(super) mod controls;
+
     #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
     pub(super) enum AppState {
         #[default]
         Menu,
         InGame,
+        /// The parameters file has problems; they are listed instead of the menu.
+        ConfigError,
+        /// Rebinding the players' controls.
+        Controls,
     }
 
     #[derive(Resource)]
-    struct MenuData {
+    pub(super) struct MenuData {
         button_entity: Entity,
     }
 
+    /// What a button of the main menu leads to.
+    #[derive(Component, Clone, Copy)]
+    pub(super) enum MenuButton {
+        Play,
+        Controls,
+    }
+
+    /// Root of the screen shown between levels.
+    #[derive(Component)]
+    pub(super) struct IntermissionScreen;
+
+    /// Root of the screen shown once the match is decided.
+    #[derive(Component)]
+    pub(super) struct GameOverScreen;
+
     const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
     const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
     const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.75, 0.35);
+    const ERROR_TEXT: Color = Color::rgb(1.0, 0.6, 0.6);
+    const BUTTON_TEXT: Color = Color::rgb(0.9, 0.9, 0.9);
 
     pub(super) fn setup(mut commands: Commands) {
         commands.spawn(Camera2dBundle::default());
@@ -89,57 +347,72 @@ mod menu {
         let button_entity = commands
             .spawn(NodeBundle {
                 style: Style {
-                    // center button
+                    // center buttons
                     width: Val::Percent(100.),
                     height: Val::Percent(100.),
+                    flex_direction: FlexDirection::Column,
                     justify_content: JustifyContent::Center,
                     align_items: AlignItems::Center,
+                    row_gap: Val::Px(10.),
                     ..default()
                 },
                 ..default()
             })
             .with_children(|parent| {
-                parent
-                    .spawn(ButtonBundle {
-                        style: Style {
-                            width: Val::Px(150.),
-                            height: Val::Px(65.),
-                            // horizontally center child text
-                            justify_content: JustifyContent::Center,
-                            // vertically center child text
-                            align_items: AlignItems::Center,
-                            ..default()
-                        },
-                        background_color: NORMAL_BUTTON.into(),
-                        ..default()
-                    })
-                    .with_children(|parent| {
-                        parent.spawn(TextBundle::from_section(
-                            "Play",
-                            TextStyle {
-                                font_size: 40.0,
-                                color: Color::rgb(0.9, 0.9, 0.9),
-                                ..default()
-                            },
-                        ));
-                    });
+                spawn_button(parent, "Play", Val::Px(150.), MenuButton::Play);
+                spawn_button(parent, "Controls", Val::Px(150.), MenuButton::Controls);
             })
             .id();
         commands.insert_resource(MenuData { button_entity });
     }
 
+    /// A button showing `label`, tagged with `marker` so its presses can be told apart.
+    fn spawn_button(parent: &mut ChildBuilder, label: &str, width: Val, marker: impl Bundle) {
+        parent
+            .spawn((
+                ButtonBundle {
+                    style: Style {
+                        width,
+                        height: Val::Px(65.),
+                        // horizontally center child text
+                        justify_content: JustifyContent::Center,
+                        // vertically center child text
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: NORMAL_BUTTON.into(),
+                    ..default()
+                },
+                marker,
+            ))
+            .with_children(|parent| {
+                parent.spawn(TextBundle::from_section(
+                    label,
+                    TextStyle {
+                        font_size: 40.0,
+                        color: BUTTON_TEXT,
+                        ..default()
+                    },
+                ));
+            });
+    }
+
+    #[allow(clippy::type_complexity)]
     pub(super) fn run_menu(
         mut next_state: ResMut<NextState<AppState>>,
         mut interaction_query: Query<
-            (&Interaction, &mut BackgroundColor),
+            (&Interaction, &mut BackgroundColor, &MenuButton),
             (Changed<Interaction>, With<Button>),
         >,
     ) {
-        for (interaction, mut color) in &mut interaction_query {
+        for (interaction, mut color, button) in &mut interaction_query {
             match *interaction {
                 Interaction::Pressed => {
                     *color = PRESSED_BUTTON.into();
-                    next_state.set(AppState::InGame);
+                    next_state.set(match button {
+                        MenuButton::Play => AppState::InGame,
+                        MenuButton::Controls => AppState::Controls,
+                    });
                 }
                 Interaction::Hovered => {
                     *color = HOVERED_BUTTON.into();
@@ -151,413 +424,1240 @@ mod menu {
         }
     }
 
-    pub(super) fn cleanup_menu(mut commands: Commands, menu_data: Res<MenuData>) {
-        commands.entity(menu_data.button_entity).despawn_recursive();
+    pub(super) fn setup_config_error(mut commands: Commands, errors: Res<ConfigErrors>) {
+        let mut lines = vec![errors.0.to_string()];
+        lines.push(String::new());
+        lines.push("Fix the file and restart the game. Press Esc to quit.".to_string());
+
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.),
+                    height: Val::Percent(100.),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    padding: UiRect::all(Val::Px(20.)),
+                    ..default()
+                },
+                background_color: Color::rgb(0.15, 0.15, 0.15).into(),
+                ..default()
+            })
+            .with_children(|parent| {
+                for line in lines {
+                    parent.spawn(TextBundle::from_section(
+                        line,
+                        TextStyle {
+                            font_size: 20.0,
+                            color: ERROR_TEXT,
+                            ..default()
+                        },
+                    ));
+                }
+            });
     }
 
-    pub(super) fn setup_game(mut commands: Commands, asset_server: Res<AssetServer>) {
-        commands.spawn(SpriteBundle {
-            texture: asset_server.load("branding/icon.png"),
-            ..default()
+    pub(super) fn setup_intermission(
+        mut commands: Commands,
+        result: Res<LevelResult>,
+        parameters: Res<Parameters>,
+        match_state: Res<MatchState>,
+        joined: Option<Res<Joined>>,
+        command_line: Res<CommandLine>,
+    ) {
+        // Standings as they will be once this set counts
+        let mut standings = match_state.clone();
+        standings.record(result.outcome.winner);
+
+        let mut lines = vec![format!(
+            "Set {} of {} over",
+            standings.sets_played, parameters.rules.best_of
+        )];
+        lines.push(match result.outcome.winner {
+            Some(winner) => format!("Player {} wins the set", winner + 1),
+            None => "Draw".to_string(),
         });
+        for (i, score) in result.scores.iter().enumerate() {
+            lines.push(format!("Player {}: {score}", i + 1));
+        }
+        lines.push(String::new());
+        lines.push(sets_line(&standings));
+        if command_line.headless {
+            print_lines(&lines);
+            return;
+        }
+        lines.push(String::new());
+        lines.push(if joined.is_some() {
+            "Waiting for the host".to_string()
+        } else if standings.is_over(parameters.rules.best_of) {
+            "Enter: results    R: replay the set".to_string()
+        } else {
+            "Enter: next set    R: replay the set".to_string()
+        });
+
+        spawn_lines(&mut commands, lines, &parameters, IntermissionScreen);
     }
 
-    const SPEED: f32 = 100.0;
-    pub(super) fn movement(
-        time: Res<Time>,
+    /// Without a window, the match moves on by itself.
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn run_intermission(
         input: Res<Input<KeyCode>>,
-        mut query: Query<&mut Transform, With<Sprite>>,
+        command_line: Res<CommandLine>,
+        parameters: Res<Parameters>,
+        folder_levels: Res<FolderLevels>,
+        result: Res<LevelResult>,
+        mut match_state: ResMut<MatchState>,
+        mut current_level: ResMut<CurrentLevel>,
+        mut next_state: ResMut<NextState<AppStates>>,
     ) {
-        for mut transform in &mut query {
-            let mut direction = Vec3::ZERO;
-            if input.pressed(KeyCode::Left) {
-                direction.x -= 1.0;
-            }
-            if input.pressed(KeyCode::Right) {
-                direction.x += 1.0;
+        if input.just_pressed(KeyCode::R) {
+            next_state.set(AppStates::Loading);
+        } else if input.just_pressed(KeyCode::Return) || command_line.headless {
+            match_state.record(result.outcome.winner);
+            if match_state.is_over(parameters.rules.best_of) {
+                next_state.set(AppStates::GameOver);
+            } else {
+                // Sets go through the levels in order, starting over after the last
+                **current_level = (**current_level + 1) % folder_levels.count(&parameters).max(1);
+                next_state.set(AppStates::Loading);
             }
-            if input.pressed(KeyCode::Up) {
-                direction.y += 1.0;
+        }
+    }
+
+    pub(super) fn cleanup_intermission(
+        mut commands: Commands,
+        query: Query<Entity, With<IntermissionScreen>>,
+    ) {
+        for entity in &query {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    pub(super) fn setup_game_over(
+        mut commands: Commands,
+        parameters: Res<Parameters>,
+        match_state: Res<MatchState>,
+        joined: Option<Res<Joined>>,
+        command_line: Res<CommandLine>,
+    ) {
+        let mut lines = vec![match match_state.leader() {
+            Some(winner) => format!("Player {} wins the match", winner + 1),
+            None => "The match is a draw".to_string(),
+        }];
+        lines.push(sets_line(&match_state));
+        if command_line.headless {
+            print_lines(&lines);
+            return;
+        }
+        lines.push(String::new());
+        lines.push(if joined.is_some() {
+            "Waiting for the host".to_string()
+        } else {
+            "Enter: rematch    M: back to the menu".to_string()
+        });
+
+        spawn_lines(&mut commands, lines, &parameters, GameOverScreen);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn run_game_over(
+        mut commands: Commands,
+        input: Res<Input<KeyCode>>,
+        parameters: Res<Parameters>,
+        command_line: Res<CommandLine>,
+        mut match_state: ResMut<MatchState>,
+        mut current_level: ResMut<CurrentLevel>,
+        mut next_state: ResMut<NextState<AppStates>>,
+        mut next_menu_state: ResMut<NextState<AppState>>,
+    ) {
+        if input.just_pressed(KeyCode::Return) {
+            *match_state = MatchState::new(parameters.players.len());
+            super::start_on_chosen_level(&command_line, &mut commands, &mut current_level);
+            next_state.set(AppStates::Loading);
+        } else if input.just_pressed(KeyCode::M) {
+            next_state.set(AppStates::Menu);
+            next_menu_state.set(AppState::Menu);
+        }
+    }
+
+    pub(super) fn cleanup_game_over(
+        mut commands: Commands,
+        query: Query<Entity, With<GameOverScreen>>,
+    ) {
+        for entity in &query {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    /// Sets won by each player, on one line.
+    fn sets_line(match_state: &MatchState) -> String {
+        let sets: Vec<String> = match_state
+            .sets_won
+            .iter()
+            .enumerate()
+            .map(|(i, won)| format!("Player {}: {won}", i + 1))
+            .collect();
+        format!("Sets won    {}", sets.join("    "))
+    }
+
+    /// What a screen would show, for runs without a window.
+    fn print_lines(lines: &[String]) {
+        for line in lines.iter().filter(|line| !line.is_empty()) {
+            println!("{line}");
+        }
+    }
+
+    /// A full screen column of centered text lines, tagged with `marker`.
+    fn spawn_lines(
+        commands: &mut Commands,
+        lines: Vec<String>,
+        parameters: &Parameters,
+        marker: impl Component,
+    ) {
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.),
+                        height: Val::Percent(100.),
+                        flex_direction: FlexDirection::Column,
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    ..default()
+                },
+                marker,
+            ))
+            .with_children(|parent| {
+                for line in lines {
+                    parent.spawn(TextBundle::from_section(
+                        line,
+                        TextStyle {
+                            font_size: parameters.scoreboard.font_size,
+                            color: parameters.colors.text,
+                            ..default()
+                        },
+                    ));
+                }
+            });
+    }
+
+    pub(super) fn cleanup_menu(mut commands: Commands, menu_data: Res<MenuData>) {
+        commands.entity(menu_data.button_entity).despawn_recursive();
+    }
+}
+
+fn start_levels(
+    mut commands: Commands,
+    parameters: Res<Parameters>,
+    command_line: Res<CommandLine>,
+    mut match_state: ResMut<MatchState>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut next_state: ResMut<NextState<AppStates>>,
+    mut next_menu_state: ResMut<NextState<menu::AppState>>,
+) {
+    let replay = &parameters.replay;
+    if let Some(path) = &replay.play {
+        let playback = Replay::load(path.as_ref()).and_then(|replay| {
+            Playback::new(replay).ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "no set was recorded")
+            })
+        });
+        match playback {
+            Ok(playback) => {
+                println!("Playing back {path}");
+                *match_state = MatchState::new(parameters.players.len());
+                **current_level = playback.current().level_index;
+                commands.insert_resource(Playing(playback));
+                next_state.set(AppStates::Loading);
             }
-            if input.pressed(KeyCode::Down) {
-                direction.y -= 1.0;
+            Err(error) => {
+                eprintln!("Couldn't play back {path}: {error}");
+                next_menu_state.set(menu::AppState::Menu);
             }
+        }
+        return;
+    }
+    if let Some(path) = &replay.record {
+        println!("Recording to {path}");
+        commands.insert_resource(Recording(Recorder::new(path)));
+    }
 
-            if direction != Vec3::ZERO {
-                transform.translation += direction.normalize() * SPEED * time.delta_seconds();
+    let net = &parameters.net;
+    let connected = match net.role {
+        NetRole::Local => Ok(()),
+        NetRole::Host => NetServer::bind(net).map(|server| {
+            if let Ok(address) = server.local_address() {
+                println!("Hosting on {address} ({:?}, {:?})", net.transport, net.mode);
             }
-        }
+            commands.insert_resource(Hosting(server));
+        }),
+        NetRole::Join => NetClient::connect(net).map(|client| {
+            println!("Joining {} ({:?})", net.address, net.transport);
+            commands.insert_resource(Joined(client));
+        }),
+    };
+    if let Err(error) = connected {
+        eprintln!("Couldn't reach the network at {}: {error}", net.address);
+        next_menu_state.set(menu::AppState::Menu);
+        return;
     }
 
-    pub(super) fn change_color(time: Res<Time>, mut query: Query<&mut Sprite>) {
-        for mut sprite in &mut query {
-            sprite
-                .color
-                .set_b((time.elapsed_seconds() * 0.5).sin() + 2.0);
-        }
+    *match_state = MatchState::new(parameters.players.len());
+    // A client plays the level the host picks
+    if net.role == NetRole::Join {
+        **current_level = 0;
+    } else {
+        start_on_chosen_level(&command_line, &mut commands, &mut current_level);
     }
+    next_state.set(AppStates::Loading);
+}
+
+/// Stops recording or playing back the last match.
+fn stop_replays(mut commands: Commands) {
+    commands.remove_resource::<Recording>();
+    commands.remove_resource::<Playing>();
+}
+
+/// Closes the connections of the last match, if it was played over the network.
+fn stop_networking(mut commands: Commands) {
+    commands.remove_resource::<Hosting>();
+    commands.remove_resource::<Joined>();
+    commands.remove_resource::<GameRollback>();
 }
 
 // Add the game's entities to our world
+#[allow(clippy::too_many_arguments)]
 fn setup_level(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     asset_server: Res<AssetServer>,
+    command_line: Res<CommandLine>,
+    current_level: Res<CurrentLevel>,
+    folder_levels: Res<FolderLevels>,
+    level_assets: Res<Assets<LevelAsset>>,
+    mut hosting: Option<ResMut<Hosting>>,
+    joined: Option<Res<Joined>>,
+    playing: Option<Res<Playing>>,
+    mut recording: Option<ResMut<Recording>>,
+    mut rng: ResMut<GameRng>,
+    mut next_menu_state: ResMut<NextState<menu::AppState>>,
+    mut next_state: ResMut<NextState<AppStates>>,
 ) {
-    let parameters = parameters_from_toml();
-    let level = parameters.levels.get(0).unwrap();
+    // Read as the level starts, so the level's override file is picked up too. A replay brings
+    // its own
+    let recorded = playing.as_ref().map(|playing| playing.current());
+    // A client builds the set from the host's seed, as it may simulate it too
+    let seed = match (recorded, joined.as_ref().and_then(|joined| joined.info())) {
+        (Some(set), _) => set.seed,
+        (None, Some(info)) => info.seed,
+        (None, None) => rng.gen(),
+    };
+    let level = match recorded {
+        Some(set) => Ok(Some((set.parameters.clone(), set.level.clone()))),
+        None => read_level(
+            &command_line,
+            **current_level,
+            seed,
+            &folder_levels,
+            &level_assets,
+        ),
+    };
+    // Levels read here were checked already, but a replay brings its own. Past this point, every
+    // player has a paddle
+    let level = level.and_then(|level| {
+        level
+            .map(|(parameters, level)| {
+                let problems = validate_level("", &level, parameters.players.len());
+                if problems.is_empty() {
+                    Ok((parameters, level))
+                } else {
+                    let file = format!("level {}", **current_level);
+                    Err(ConfigError::new(&file, problems))
+                }
+            })
+            .transpose()
+    });
+    let (parameters, level) = match level {
+        Ok(Some(level)) => level,
+        Ok(None) => {
+            eprintln!("There is no level {}", **current_level);
+            next_menu_state.set(menu::AppState::Menu);
+            next_state.set(AppStates::Menu);
+            return;
+        }
+        Err(error) => {
+            eprintln!("{error}");
+            commands.insert_resource(ConfigErrors(error));
+            next_menu_state.set(menu::AppState::ConfigError);
+            next_state.set(AppStates::Menu);
+            return;
+        }
+    };
+    let level = &level;
+    let simulation = Simulation::new(&parameters, level, seed);
+    if let Some(hosting) = &mut hosting {
+        hosting.start_round(**current_level, seed);
+    }
 
-    // Camera
-    commands.spawn(Camera2dBundle::default());
+    // With rollback, the host plays every player no client may take, and a client its own
+    let n_players = parameters.players.len();
+    let local_players = match (&hosting, &joined) {
+        (Some(hosting), _) if hosting.mode() == NetMode::Rollback => Some(
+            (0..n_players)
+                .filter(|player| !hosting.remote_players().contains(player))
+                .collect::<Vec<_>>(),
+        ),
+        (_, Some(joined)) if joined.mode == NetMode::Rollback => {
+            joined.player.map(|player| vec![player])
+        }
+        _ => None,
+    };
+    // A client that only draws snapshots has no steps to record
+    if let Some(recording) = recording
+        .as_mut()
+        .filter(|_| joined.is_none() || local_players.is_some())
+    {
+        recording.start_set(**current_level, &parameters, level, seed);
+    }
+    match local_players {
+        Some(local_players) => {
+            commands.insert_resource(GameRollback(Rollback::new(n_players, &local_players)))
+        }
+        None => commands.remove_resource::<GameRollback>(),
+    }
+    commands.insert_resource(AiControllers(
+        parameters
+            .players
+            .iter()
+            .enumerate()
+            .filter_map(|(i, player)| Some(AiController::new(i, player.ai?, rng.gen())))
+            .collect(),
+    ));
 
     // Sound
     let ball_collision_sound = asset_server.load("sounds/breakout_collision.ogg");
     commands.insert_resource(CollisionSound(ball_collision_sound));
 
-    // Paddle
-    for (i, player) in parameters.players.iter().enumerate() {
-        let paddle = level.paddles.get(i).unwrap();
-        commands.spawn(PlayerBundle::new(
-            player,
-            paddle.position(),
-            paddle.size(),
-            paddle.color(),
+    // Paddle, one for each player as the level was validated above
+    for (i, (player, paddle)) in parameters.players.iter().zip(&level.paddles).enumerate() {
+        commands.spawn((
+            PlayerBundle::new(player, i, paddle.position(), paddle.size(), paddle.color()),
+            LevelEntity,
         ));
     }
 
-    // Ball
-    commands.spawn((
-        MaterialMesh2dBundle {
-            mesh: meshes.add(shape::Circle::default().into()).into(),
-            material: materials.add(ColorMaterial::from(parameters.colors.ball)),
-            transform: Transform::from_translation(parameters.ball.starting_position)
-                .with_scale(parameters.ball.size),
-            ..default()
-        },
-        parameters.ball,
-        Velocity(parameters.ball.starting_velocity()),
-    ));
+    // Balls, spawned by `sync_balls` from the simulation and colored after their owner
+    commands.insert_resource(BallAssets {
+        mesh: meshes.add(shape::Circle::default().into()),
+        material: materials.add(ColorMaterial::from(parameters.colors.ball)),
+        owners: level
+            .paddles
+            .iter()
+            .map(|paddle| materials.add(ColorMaterial::from(paddle.color())))
+            .collect(),
+    });
 
-    // Scoreboards
-    commands.spawn(
-        TextBundle::from_sections([
-            TextSection::new(
-                "Player 1: ",
-                TextStyle {
-                    font_size: parameters.scoreboard.font_size,
-                    color: parameters.colors.text,
+    // Pickups, spawned by `sync_pickups` from the simulation
+    commands.insert_resource(PickupAssets {
+        mesh: meshes.add(shape::Circle::default().into()),
+        material: materials.add(ColorMaterial::from(parameters.colors.pickup)),
+    });
+
+    // Scoreboards, in a row along the top, shrinking as there are more players
+    let font_size = parameters.scoreboard.font_size
+        * (SCOREBOARDS_AT_FULL_SIZE / parameters.players.len() as f32).clamp(0.5, 1.);
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: parameters.scoreboard.text_padding,
+                    width: Val::Percent(100.),
+                    justify_content: JustifyContent::SpaceEvenly,
                     ..default()
                 },
-            ),
-            TextSection::from_style(TextStyle {
-                font_size: parameters.scoreboard.font_size,
-                color: parameters.colors.score,
                 ..default()
-            }),
-        ])
+            },
+            LevelEntity,
+        ))
+        .with_children(|parent| {
+            for (i, paddle) in level.paddles.iter().enumerate() {
+                let style = |color| TextStyle {
+                    font_size,
+                    color,
+                    ..default()
+                };
+                parent.spawn((
+                    TextBundle::from_sections([
+                        TextSection::new(format!("Player {}: ", i + 1), style(paddle.color())),
+                        TextSection::from_style(style(parameters.colors.score)),
+                        TextSection::from_style(style(parameters.colors.text)),
+                    ]),
+                    ScoreboardIndex(i),
+                ));
+            }
+        });
+
+    // Time left in the set, at the bottom in the middle, empty without a time limit
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    bottom: parameters.scoreboard.text_padding,
+                    width: Val::Percent(100.),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            LevelEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: parameters.scoreboard.font_size,
+                        color: parameters.colors.text,
+                        ..default()
+                    },
+                ),
+                ClockText,
+            ));
+        });
+
+    // Power-ups in effect, at the bottom on the left
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: parameters.scoreboard.font_size / 2.,
+                color: parameters.colors.text,
+                ..default()
+            },
+        )
         .with_style(Style {
             position_type: PositionType::Absolute,
-            top: parameters.scoreboard.text_padding,
+            bottom: parameters.scoreboard.text_padding,
             left: parameters.scoreboard.text_padding,
             ..default()
         }),
-    );
-    commands.spawn(
-        TextBundle::from_sections([
-            TextSection::new(
-                "Player 2: ",
+        PowerUpText,
+        LevelEntity,
+    ));
+
+    // Countdown to the next serve, empty while balls are in play
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_section(
+                "",
                 TextStyle {
-                    font_size: parameters.scoreboard.font_size,
+                    font_size: parameters.scoreboard.font_size * 2.,
                     color: parameters.colors.text,
                     ..default()
                 },
             ),
-            TextSection::from_style(TextStyle {
-                font_size: parameters.scoreboard.font_size,
-                color: parameters.colors.score,
-                ..default()
-            }),
-        ])
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            top: parameters.scoreboard.text_padding,
-            right: parameters.scoreboard.text_padding,
+            transform: Transform::from_xyz(0., 0., 1.),
             ..default()
-        }),
-    );
+        },
+        CountdownText,
+        LevelEntity,
+    ));
 
     // Walls
-    for wall in level.walls {
-        commands.spawn(WallBundle::new(&wall));
-    }
-
-    /* // Goal Bricks
-    let minimum_gap_between_bricks_and_vertical_walls = parameters
-        .misc
-        .minimum_gap_between_bricks_and_vertical_walls;
-    let minimum_gap_between_bricks_and_horizontal_walls = parameters
-        .misc
-        .minimum_gap_between_bricks_and_horizontal_walls;
-    let minimum_gap_between_bricks_and_paddle =
-        parameters.misc.minimum_gap_between_paddle_and_goal_bricks;
-
-    let y_border_top_wall = parameters.wall.y_up_wall - parameters.wall.thickness / 2;
-    let y_border_down_wall = parameters.wall.y_down_wall + parameters.wall.thickness / 2;
-    let x_border_left_wall = parameters.wall.x_left_wall + parameters.wall.thickness / 2;
-    let x_border_left_paddle = parameters.paddle.left_bound(&parameters);
-    let x_border_right_wall = parameters.wall.x_right_wall + parameters.wall.thickness / 2;
-    let x_border_right_paddle = parameters.paddle.right_bound(&parameters);
-
-    let gap_between_bricks = parameters.misc.gap_between_bricks;
-    let brick_width = parameters.brick.width;
-    let total_width_of_bricks = (x_border_left_paddle - x_border_left_wall)
-        - minimum_gap_between_bricks_and_vertical_walls
-        - minimum_gap_between_bricks_and_paddle;
-    let brick_height = parameters.brick.height;
-    let total_height_of_bricks = (y_border_top_wall - y_border_down_wall)
-        - 2 * minimum_gap_between_bricks_and_horizontal_walls;
-
-    // Goal bricks left
-    // Given the space available, compute how many rows and columns of bricks we can fit
-    let n_columns =
-        (total_width_of_bricks + gap_between_bricks) / (brick_width + gap_between_bricks);
-    let n_rows =
-        (total_height_of_bricks + gap_between_bricks) / (brick_height + gap_between_bricks);
-    let n_vertical_gaps = n_columns - 1;
-    let n_horizontal_gaps = n_rows - 1;
-
-    // Because we need to round the number of columns,
-    // the space on the top and sides of the bricks only captures a lower bound, not an exact value
-    let x_left_center_of_bricks = (x_border_left_wall + x_border_left_paddle) / 2;
-    let left_left_edge_of_bricks = x_left_center_of_bricks
-        // Space taken up by the bricks
-        - (n_columns / 2. * brick_width)
-        // Space taken up by the gaps
-        - (n_vertical_gaps / 2. * gap_between_bricks);
-    let x_right_center_of_bricks = (x_border_right_wall + x_border_right_paddle) / 2;
-    let right_left_edge_of_bricks = x_right_center_of_bricks
-        // Space taken up by the bricks
-        - (n_columns / 2. * brick_width)
-        // Space taken up by the gaps
-        - (n_vertical_gaps / 2. * gap_between_bricks);
-    let y_left_center_of_bricks = (y_border_top_wall + y_border_down_wall) / 2;
-    let bottom_edge_of_bricks = y_left_center_of_bricks
-        // Space taken up by the bricks
-        - (n_rows / 2. * brick_height)
-        // Space taken up by the gaps
-        - (n_horizontal_gaps / 2. * gap_between_bricks);
-
-    // In Bevy, the `translation` of an entity describes the center point,
-    // not its bottom-left corner
-    let left_offset_x = left_left_edge_of_bricks + brick_width / 2;
-    let offset_y = bottom_edge_of_bricks + brick_height / 2;
-    let right_offset_x = right_left_edge_of_bricks + brick_width / 2;
-
-    for row in 0..n_rows {
-        for column in 0..n_columns {
-            let brick_position = Vec2::new(
-                (left_offset_x + column * (brick_width + gap_between_bricks)),
-                (offset_y + row * (brick_height + gap_between_bricks)),
-            );
-
-            // brick
-            commands.spawn((
-                SpriteBundle {
-                    sprite: Sprite {
-                        color: parameters.colors.brick,
-                        ..default()
-                    },
-                    transform: Transform {
-                        translation: brick_position.extend(0.0),
-                        scale: parameters.brick.size(),
-                        ..default()
-                    },
+    for wall in &level.walls {
+        commands.spawn((WallBundle::new(wall), LevelEntity));
+    }
+
+    // Shields over each goal, shown by `update_shields` while one is in effect
+    for i in 0..level.paddles.len() {
+        let Some(goal) = simulation.goal_of(i) else {
+            continue;
+        };
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: parameters.colors.shield,
                     ..default()
                 },
-                Brick,
-                Collider,
-            ));
-        }
+                transform: Transform {
+                    translation: goal.translation().extend(0.5),
+                    rotation: Quat::from_rotation_z(goal.angle()),
+                    scale: (goal.scale() * Vec2::new(1., 2.)).extend(1.),
+                },
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            ShieldIndex(i),
+            LevelEntity,
+        ));
     }
 
-    for row in 0..n_rows {
-        for column in 0..n_columns {
-            let brick_position = Vec2::new(
-                (right_offset_x + column * (brick_width + gap_between_bricks)),
-                (offset_y + row * (brick_height + gap_between_bricks)),
-            );
-
-            // brick
-            commands.spawn((
-                SpriteBundle {
-                    sprite: Sprite {
-                        color: parameters.colors.brick,
-                        ..default()
-                    },
-                    transform: Transform {
-                        translation: brick_position.extend(0.0),
-                        scale: parameters.brick.size(),
-                        ..default()
-                    },
+    // Where a replay is, and how to move through it, at the bottom on the right
+    if playing.is_some() {
+        commands.spawn((
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font_size: parameters.scoreboard.font_size / 2.,
+                    color: parameters.colors.text,
                     ..default()
                 },
-                Brick,
-                Collider,
-            ));
+            )
+            .with_text_alignment(TextAlignment::Right)
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                bottom: parameters.scoreboard.text_padding,
+                right: parameters.scoreboard.text_padding,
+                ..default()
+            }),
+            ReplayText,
+            LevelEntity,
+        ));
+    }
+
+    // Goal bricks are spawned by `sync_bricks` from the simulation
+    commands.insert_resource(GameSimulation(simulation));
+    commands.insert_resource(parameters.clone());
+}
+
+/// Parameters and level for level `index`, or nothing once there are no levels left. A random
+/// arena is drawn from `seed`.
+fn read_level(
+    options: &Options,
+    index: usize,
+    seed: u64,
+    folder_levels: &FolderLevels,
+    level_assets: &Assets<LevelAsset>,
+) -> Result<Option<(Parameters, Level)>, ConfigError> {
+    parameters_for_level(options, index).and_then(|parameters| {
+        let level = match parameters.arena {
+            Arena::Levels => folder_levels.level(&parameters, level_assets, index),
+            Arena::FreeForAll { radius, goal_limit } => Some(Ok(arena::free_for_all(
+                parameters.players.len(),
+                radius,
+                goal_limit,
+            ))),
+            Arena::Generated(ref spec) => Some(Ok(arena::generate(spec, parameters.players.len()))),
+            Arena::Random { goal_limit } => {
+                let n_players = parameters.players.len();
+                let spec =
+                    arena::random_spec(n_players, goal_limit, &mut StdRng::seed_from_u64(seed));
+                Some(Ok(arena::generate(&spec, n_players)))
+            }
+        };
+        match level {
+            Some(level) => level.map(|level| Some((parameters, level))),
+            None => Ok(None),
         }
-    } */
+    })
 }
 
-fn move_players(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(&mut Transform, &Player, &Paddle)>,
-    time: Res<Time>,
+#[allow(clippy::too_many_arguments)]
+fn step_simulation(
+    devices: Devices,
+    players: Query<(&Player, &PaddleIndex)>,
+    mut simulation: ResMut<GameSimulation>,
+    mut ai_controllers: ResMut<AiControllers>,
+    hosting: Option<Res<Hosting>>,
+    rollback: Option<ResMut<GameRollback>>,
+    mut recording: Option<ResMut<Recording>>,
+    mut collision_events: EventWriter<CollisionEvent>,
 ) {
-    for mut entity in query.iter_mut() {
-        let (mut transform, player, paddle) = entity;
-        let mut delta: Option<Vec3> = None;
-        for control in &player.controls {
-            if let Effect::Move(direction) = control.effect {
-                if keyboard_input.pressed(control.key.into()) {
-                    delta = match delta {
-                        Some(delta) => Some(delta + direction),
-                        None => Some(direction),
+    let mut players = players.iter().collect::<Vec<_>>();
+    players.sort_by_key(|(_, index)| index.0);
+    let players = players
+        .into_iter()
+        .map(|(player, _)| player.clone())
+        .collect::<Vec<_>>();
+
+    let mut inputs = Simulation::inputs_from_controls(&players, |i, binding| {
+        let (position, reach) = simulation
+            .paddles
+            .get(i)
+            .map(|paddle| (paddle.position, paddle.paddle.speed() * TIMESTEP))
+            .unwrap_or_default();
+        devices.amount(binding, position, reach)
+    });
+    ai::apply(&mut ai_controllers, &simulation, &mut inputs);
+    let events = match rollback {
+        // Only the inputs of the players played here are taken
+        Some(mut rollback) => match rollback.advance(&mut simulation, &inputs) {
+            Some(events) => {
+                // Only inputs no instance will change are recorded
+                for inputs in rollback.take_confirmed_inputs() {
+                    if let Some(recording) = &mut recording {
+                        recording.record(inputs);
                     }
                 }
+                events
+            }
+            // Too far ahead of the others; wait for their inputs
+            None => return,
+        },
+        None => {
+            if let Some(hosting) = hosting {
+                hosting.apply_inputs(&mut inputs);
             }
+            if let Some(recording) = &mut recording {
+                recording.record(inputs.clone());
+            }
+            simulation.step(&inputs)
+        }
+    };
+    for event in events {
+        if let SimulationEvent::Collision { .. } = event {
+            // Sends a collision event so that other systems can react to the collision
+            collision_events.send_default();
         }
-        if let Some(delta) = delta {
-            // Calculate the new horizontal paddle position based on player input
-            let new_paddle_position = transform.translation
-                + delta.normalize_or_zero() * paddle.speed() * time.delta_seconds();
+    }
+}
 
-            unimplemented!() /* // Update the paddle position,
-                             // making sure it doesn't cause the paddle to leave the arena
-                             transform.translation = new_paddle_position.clamp(
-                                 paddle.neg_bounds(parameters.as_ref()),
-                                 paddle.pos_bounds(parameters.as_ref()),
-                             ); */
+/// Runs the steps of the replay due at its speed, in place of `step_simulation`.
+fn run_playback(
+    mut playing: ResMut<Playing>,
+    mut simulation: ResMut<GameSimulation>,
+    mut collision_events: EventWriter<CollisionEvent>,
+) {
+    for event in playing.advance(&mut simulation) {
+        if let SimulationEvent::Collision { .. } = event {
+            collision_events.send_default();
         }
     }
 }
 
-fn apply_velocity(mut query: Query<(&mut Transform, &Velocity, Option<&Ball>)>, time: Res<Time>) {
-    for (mut transform, velocity, maybe_ball) in &mut query {
-        transform.translation.x += velocity.x * time.delta_seconds();
-        transform.translation.y += velocity.y * time.delta_seconds();
-        unimplemented!() /* if maybe_ball.is_some() {
-                             transform.translation = transform.translation.clamp(
-                                 parameters.ball.neg_bounds(parameters.as_ref()),
-                                 parameters.ball.pos_bounds(parameters.as_ref()),
-                             );
-                         } */
+/// Space pauses the replay, Left and Right go back and forward by `SEEK_SECONDS`, Up and Down
+/// change its speed, Page Up and Page Down show the previous and next sets, and M goes back to
+/// the menu.
+fn control_playback(
+    input: Res<Input<KeyCode>>,
+    mut playing: ResMut<Playing>,
+    mut simulation: ResMut<GameSimulation>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut next_state: ResMut<NextState<AppStates>>,
+    mut next_menu_state: ResMut<NextState<menu::AppState>>,
+) {
+    if input.just_pressed(KeyCode::Space) {
+        playing.paused = !playing.paused;
+    }
+    if input.just_pressed(KeyCode::Left) {
+        playing.seek(&mut simulation, -SEEK_SECONDS);
+    }
+    if input.just_pressed(KeyCode::Right) {
+        playing.seek(&mut simulation, SEEK_SECONDS);
+    }
+    if input.just_pressed(KeyCode::Up) {
+        playing.faster();
+    }
+    if input.just_pressed(KeyCode::Down) {
+        playing.slower();
+    }
+
+    let set = playing.set_index();
+    let shown = if input.just_pressed(KeyCode::PageUp) {
+        set.checked_sub(1).is_some_and(|set| playing.show_set(set))
+    } else if input.just_pressed(KeyCode::PageDown) {
+        playing.show_set(set + 1)
+    } else {
+        false
+    };
+    if shown {
+        // Sets may be on other levels, so the level is built again
+        **current_level = playing.current().level_index;
+        next_state.set(AppStates::Loading);
+    }
+
+    if input.just_pressed(KeyCode::M) {
+        next_state.set(AppStates::Menu);
+        next_menu_state.set(menu::AppState::Menu);
     }
 }
 
-fn update_scoreboards(scoreboard: Res<Scoreboards>, mut query: Query<&mut Text>) {
-    for (i, mut text) in query.iter_mut().enumerate() {
-        if let Some(score) = scoreboard.scores.get(i) {
-            text.sections[1].value = score.to_string();
+fn poll_clients(mut hosting: ResMut<Hosting>) {
+    hosting.poll();
+}
+
+fn broadcast_snapshots(
+    mut hosting: ResMut<Hosting>,
+    simulation: Option<Res<GameSimulation>>,
+    rollback: Option<Res<GameRollback>>,
+    match_state: Res<MatchState>,
+    state: Res<State<AppStates>>,
+) {
+    let over = *state.get() == AppStates::GameOver;
+    hosting.broadcast(
+        simulation.as_deref().map(|simulation| &**simulation),
+        rollback.as_deref().map(|rollback| &**rollback),
+        &match_state,
+        over,
+    );
+}
+
+/// Hands the inputs other instances sent to the set's rollback, and reports when they stop
+/// agreeing on the state.
+fn receive_frames(
+    mut rollback: ResMut<GameRollback>,
+    hosting: Option<ResMut<Hosting>>,
+    joined: Option<ResMut<Joined>>,
+) {
+    let received = match (hosting, joined) {
+        (Some(mut hosting), _) => {
+            for player in hosting.left() {
+                rollback.abandon(*player);
+            }
+            let frame = rollback.frame();
+            rollback.keep_inputs_from(hosting.acknowledged().unwrap_or(frame));
+            hosting.take_frames()
+        }
+        (None, Some(mut joined)) => {
+            let received = joined.take_frames();
+            // Datagrams may arrive out of order; acknowledgements only grow
+            let acknowledged = received.iter().map(|frames| frames.ack).max();
+            if let Some(ack) = acknowledged.filter(|ack| *ack > rollback.kept_from()) {
+                rollback.keep_inputs_from(ack);
+            }
+            received
+        }
+        (None, None) => return,
+    };
+
+    for frames in received {
+        for sent in &frames.players {
+            rollback.receive(sent);
         }
+        if let Some((frame, checksum)) = frames.checksum {
+            rollback.check(frame, checksum);
+        }
+    }
+    if let Some(frame) = rollback.take_desync() {
+        eprintln!("Out of sync with another player since frame {frame}");
     }
 }
 
+/// Plays a match hosted elsewhere: follows the host from set to set, draws its snapshots and
+/// sends it what the local player's controls ask for.
 #[allow(clippy::too_many_arguments)]
-#[allow(clippy::type_complexity)]
-fn check_for_collisions(
-    level: Res<State<AppStates>>,
+fn run_client(
     mut commands: Commands,
-    mut scoreboard: ResMut<Scoreboards>,
-    mut ball_query: Query<(&mut Velocity, &Transform, &Ball)>,
-    collider_query: Query<(Entity, &Transform, Option<&Brick>, Option<&Wall>), With<Collider>>,
-    mut collision_events: EventWriter<CollisionEvent>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    devices: Devices,
+    parameters: Res<Parameters>,
+    mut joined: ResMut<Joined>,
+    simulation: Option<ResMut<GameSimulation>>,
+    mut match_state: ResMut<MatchState>,
+    mut current_level: ResMut<CurrentLevel>,
+    state: Res<State<AppStates>>,
+    mut next_state: ResMut<NextState<AppStates>>,
+    mut next_menu_state: ResMut<NextState<menu::AppState>>,
 ) {
-    let level = match level.into_inner().get() {
-        AppStates::Menu => return,
-        AppStates::Level1(level) => level,
+    let lost = match joined.poll() {
+        Err(error) => Some(format!("Lost the host: {error}")),
+        Ok(()) if joined.refused => Some("The host has no player left".to_string()),
+        Ok(()) => None,
     };
-    for (mut ball_velocity, ball_transform, ball) in ball_query.iter_mut() {
-        let ball_size = ball_transform.scale.truncate();
-
-        // check collision with walls
-        for (collider_entity, transform, maybe_brick, maybe_wall) in &collider_query {
-            let collision = collide(
-                ball_transform.translation,
-                ball_size,
-                transform.translation,
-                transform.scale.truncate(),
-            );
-            if let Some(collision) = collision {
-                // Sends a collision event so that other systems can react to the collision
-                collision_events.send_default();
-                if let Some(wall) = maybe_wall {
-                    for (i, paddle) in level.paddles {
-                        if wall == paddle.
-                    }
-                    for (i, _wall) in players_query
-                        .iter()
-                        .map(|e| e.wall_that_gives_points)
-                        .enumerate()
-                    {
-                        if wall_hit == _wall {
-                            if let Some(scoreboard) = scoreboard.scores.get_mut(i) {
-                                *scoreboard += 1;
-                                break;
-                            }
-                        }
-                    }
-                }
+    if let Some(reason) = lost {
+        eprintln!("{reason}");
+        commands.remove_resource::<Joined>();
+        next_state.set(AppStates::Menu);
+        next_menu_state.set(menu::AppState::Menu);
+        return;
+    }
 
-                // Bricks should be despawned and increment the scoreboard on collision
-                if maybe_brick.is_some() {
-                    commands.entity(collider_entity).despawn();
-                }
+    let mut simulation = simulation.filter(|_| *state.get() == AppStates::Level);
+    // Followed once welcomed, as datagrams about the set may overtake the welcome
+    let info = joined.info().filter(|_| joined.player.is_some()).cloned();
+    if let Some(info) = info {
+        if joined.round != Some(info.round) {
+            // The host started a set: load its level, then follow it
+            joined.round = Some(info.round);
+            *match_state = info.match_state();
+            **current_level = info.level as usize;
+            commands.remove_resource::<GameRollback>();
+            next_state.set(AppStates::Loading);
+            return;
+        } else if info.over && *state.get() != AppStates::GameOver {
+            *match_state = info.match_state();
+            next_state.set(AppStates::GameOver);
+            return;
+        }
+    }
+    if let (Some(snapshot), Some(simulation)) = (joined.take_snapshot(), &mut simulation) {
+        if joined.round == Some(snapshot.info.round) {
+            snapshot.apply(simulation);
+        }
+    }
 
-                // reflect the ball when it collides
-                let mut reflect_x = false;
-                let mut reflect_y = false;
-
-                // only reflect if the ball's velocity is going in the opposite direction of the
-                // collision
-                match collision {
-                    Collision::Left => reflect_x = ball_velocity.x > 0.0,
-                    Collision::Right => reflect_x = ball_velocity.x < 0.0,
-                    Collision::Top => reflect_y = ball_velocity.y < 0.0,
-                    Collision::Bottom => reflect_y = ball_velocity.y > 0.0,
-                    Collision::Inside => { /* do nothing */ }
-                }
+    // Sent between sets too, so the host keeps hearing from the client and keeps its player.
+    // With rollback, `send_frames` sends the inputs instead
+    let Some(player) = joined.player.filter(|_| joined.mode == NetMode::Snapshots) else {
+        return;
+    };
+    let input = match &simulation {
+        Some(simulation) => {
+            let controls = parameters.players.get(player..=player).unwrap_or_default();
+            Simulation::inputs_from_controls(controls, |_, binding| {
+                let (position, reach) = simulation
+                    .paddles
+                    .get(player)
+                    .map(|paddle| (paddle.position, paddle.paddle.speed() * TIMESTEP))
+                    .unwrap_or_default();
+                devices.amount(binding, position, reach)
+            })
+            .pop()
+            .unwrap_or_default()
+        }
+        None => PlayerInput::default(),
+    };
+    if let Err(error) = joined.send_input(input) {
+        eprintln!("Lost the host: {error}");
+        commands.remove_resource::<Joined>();
+        next_state.set(AppStates::Menu);
+        next_menu_state.set(menu::AppState::Menu);
+    }
+}
 
-                // reflect velocity on the x-axis if we hit something on the x-axis
-                if reflect_x {
-                    ball_velocity.x = -ball_velocity.x;
-                }
+/// Sends the host the inputs of the local player, when playing with rollback.
+fn send_frames(
+    mut commands: Commands,
+    mut joined: ResMut<Joined>,
+    rollback: Option<Res<GameRollback>>,
+    mut next_state: ResMut<NextState<AppStates>>,
+    mut next_menu_state: ResMut<NextState<menu::AppState>>,
+) {
+    if joined.player.is_none() || joined.mode != NetMode::Rollback {
+        return;
+    }
+    if let Err(error) = joined.send_frames(rollback.as_deref().map(|rollback| &**rollback)) {
+        eprintln!("Lost the host: {error}");
+        commands.remove_resource::<Joined>();
+        next_state.set(AppStates::Menu);
+        next_menu_state.set(menu::AppState::Menu);
+    }
+}
 
-                // reflect velocity on the y-axis if we hit something on the y-axis
-                if reflect_y {
-                    ball_velocity.y = -ball_velocity.y;
-                }
+fn sync_paddles(
+    simulation: Res<GameSimulation>,
+    mut query: Query<(&mut Transform, &mut Visibility, &PaddleIndex)>,
+) {
+    for (mut transform, mut visibility, index) in &mut query {
+        if let Some(paddle) = simulation.paddles.get(index.0) {
+            transform.translation = paddle.position;
+            transform.rotation = Quat::from_rotation_z(paddle.facing());
+            // Both ways, as a replay going back brings eliminated players back
+            *visibility = if paddle.eliminated {
+                Visibility::Hidden
+            } else {
+                Visibility::Inherited
+            };
+        }
+    }
+}
 
-                if maybe_brick.is_some() && rand::random::<f32>() < ball.probability_to_duplicate {
-                    commands.spawn((
-                        MaterialMesh2dBundle {
-                            mesh: meshes.add(shape::Circle::default().into()).into(),
-                            material: materials.add(ColorMaterial::from(parameters.colors.ball)),
-                            transform: Transform::from_translation(
-                                parameters.ball.starting_position,
-                            )
-                            .with_scale(parameters.ball.size),
-                            ..default()
-                        },
-                        Ball,
-                        Velocity(ball_velocity.as_ref().0),
-                    ));
+fn sync_balls(
+    mut commands: Commands,
+    simulation: Res<GameSimulation>,
+    ball_assets: Res<BallAssets>,
+    mut query: Query<(Entity, &mut Transform, &mut Handle<ColorMaterial>, &BallId)>,
+) {
+    let material = |owner: Option<usize>| {
+        owner
+            .and_then(|owner| ball_assets.owners.get(owner))
+            .unwrap_or(&ball_assets.material)
+            .clone()
+    };
+
+    let mut drawn = Vec::new();
+    for (entity, mut transform, mut handle, id) in &mut query {
+        match simulation.balls.iter().find(|ball| ball.id == id.0) {
+            Some(ball) => {
+                transform.translation = ball.position;
+                let owned = material(ball.owner);
+                if *handle != owned {
+                    *handle = owned;
                 }
+                drawn.push(id.0);
+            }
+            None => commands.entity(entity).despawn(),
+        }
+    }
+
+    for ball in simulation
+        .balls
+        .iter()
+        .filter(|ball| !drawn.contains(&ball.id))
+    {
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: ball_assets.mesh.clone().into(),
+                material: material(ball.owner),
+                transform: Transform::from_translation(ball.position).with_scale(ball.size),
+                ..default()
+            },
+            simulation.ball.clone(),
+            BallId(ball.id),
+            LevelEntity,
+        ));
+    }
+}
+
+fn sync_bricks(
+    mut commands: Commands,
+    simulation: Res<GameSimulation>,
+    parameters: Res<Parameters>,
+    mut query: Query<(Entity, &BrickId, &mut Sprite), With<Brick>>,
+) {
+    let color =
+        |hit_points| brick_color(&parameters.colors, hit_points, simulation.strongest_brick);
+
+    let mut drawn = Vec::new();
+    for (entity, id, mut sprite) in &mut query {
+        match simulation.bricks.iter().find(|brick| brick.id == id.0) {
+            Some(brick) => {
+                sprite.color = color(brick.hit_points);
+                drawn.push(id.0);
             }
+            None => commands.entity(entity).despawn(),
         }
     }
+
+    // Bricks come back when a replay goes back to before they broke
+    for brick in simulation
+        .bricks
+        .iter()
+        .filter(|brick| !drawn.contains(&brick.id))
+    {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: color(brick.hit_points),
+                    ..default()
+                },
+                transform: Transform {
+                    translation: brick.position.extend(0.0),
+                    rotation: Quat::from_rotation_z(brick.angle),
+                    scale: brick.size.extend(1.),
+                },
+                ..default()
+            },
+            Brick,
+            BrickId(brick.id),
+            LevelEntity,
+        ));
+    }
+}
+
+fn sync_pickups(
+    mut commands: Commands,
+    simulation: Res<GameSimulation>,
+    pickup_assets: Res<PickupAssets>,
+    query: Query<(Entity, &PickupId)>,
+) {
+    let mut drawn = Vec::new();
+    for (entity, id) in &query {
+        match simulation.pickups.iter().find(|pickup| pickup.id == id.0) {
+            Some(_) => drawn.push(id.0),
+            None => commands.entity(entity).despawn(),
+        }
+    }
+
+    for pickup in simulation
+        .pickups
+        .iter()
+        .filter(|pickup| !drawn.contains(&pickup.id))
+    {
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: pickup_assets.mesh.clone().into(),
+                material: pickup_assets.material.clone(),
+                transform: Transform::from_translation(pickup.position.extend(-1.))
+                    .with_scale(Vec3::new(pickup.radius * 2., pickup.radius * 2., 1.)),
+                ..default()
+            },
+            PickupId(pickup.id),
+            LevelEntity,
+        ));
+    }
+}
+
+fn update_shields(
+    simulation: Res<GameSimulation>,
+    mut query: Query<(&mut Visibility, &ShieldIndex)>,
+) {
+    for (mut visibility, index) in &mut query {
+        *visibility = if simulation.shielded(index.0) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+/// Shade of a brick with `hit_points` left, from `colors.brick` for the weakest to
+/// `colors.strongest_brick` for bricks as strong as the `strongest` of the level.
+fn brick_color(colors: &ParametersColors, hit_points: u32, strongest: u32) -> Color {
+    if strongest <= 1 {
+        return colors.brick;
+    }
+    let strength = (hit_points.saturating_sub(1) as f32 / (strongest - 1) as f32).min(1.);
+    let weakest = Vec4::from(colors.brick.as_rgba_f32());
+    Color::from(weakest.lerp(Vec4::from(colors.strongest_brick.as_rgba_f32()), strength))
+}
+
+fn update_scoreboards(
+    simulation: Res<GameSimulation>,
+    mut scoreboard: ResMut<Scoreboards>,
+    mut query: Query<(&mut Text, &ScoreboardIndex)>,
+) {
+    scoreboard.scores = simulation.scores.clone();
+    for (mut text, index) in &mut query {
+        let i = index.0;
+        if let Some(score) = scoreboard.scores.get(i) {
+            text.sections[1].value = score.to_string();
+        }
+        // With a goal limit, also how many goals the player may still let in
+        text.sections[2].value = match (simulation.goal_limit, simulation.paddles.get(i)) {
+            (Some(_), Some(paddle)) if paddle.eliminated => " (out)".to_string(),
+            (Some(limit), Some(_)) => {
+                let conceded = simulation.conceded.get(i).copied().unwrap_or(0);
+                format!(" ({} left)", limit.saturating_sub(conceded))
+            }
+            _ => String::new(),
+        };
+    }
+}
+
+fn update_countdown(
+    simulation: Res<GameSimulation>,
+    mut query: Query<&mut Text, With<CountdownText>>,
+) {
+    let value = match simulation.rally {
+        Rally::Countdown { ticks_left, .. } => {
+            let seconds = (ticks_left as f32 * TIMESTEP).ceil().max(1.);
+            seconds.to_string()
+        }
+        Rally::Playing => String::new(),
+    };
+    for mut text in &mut query {
+        text.sections[0].value = value.clone();
+    }
+}
+
+/// Shows the set of the replay shown, how far in it is and how fast it plays.
+fn update_replay_text(
+    playing: Res<Playing>,
+    simulation: Res<GameSimulation>,
+    mut query: Query<&mut Text, With<ReplayText>>,
+) {
+    let clock = |steps: u64| {
+        let seconds = (steps as f32 * TIMESTEP) as u32;
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    };
+    let length = playing.current().length();
+    let state = if simulation.tick >= length {
+        "    End"
+    } else if playing.paused {
+        "    Paused"
+    } else {
+        ""
+    };
+    let value = format!(
+        "Replay: set {} of {}    {} / {}    x{}{state}\n\
+         Space: pause    Left/Right: seek    Up/Down: speed    PgUp/PgDn: set    M: menu",
+        playing.set_index() + 1,
+        playing.set_count(),
+        clock(simulation.tick),
+        clock(length),
+        playing.speed(),
+    );
+    for mut text in &mut query {
+        text.sections[0].value = value.clone();
+    }
+}
+
+fn update_clock(simulation: Res<GameSimulation>, mut query: Query<&mut Text, With<ClockText>>) {
+    let value = match simulation.time_left() {
+        _ if simulation.sudden_death() => "Sudden death".to_string(),
+        Some(left) => {
+            let seconds = left.ceil() as u32;
+            format!("{}:{:02}", seconds / 60, seconds % 60)
+        }
+        None => String::new(),
+    };
+    for mut text in &mut query {
+        text.sections[0].value = value.clone();
+    }
+}
+
+/// Lists each power-up in effect with who it applies to and the seconds it has left.
+fn update_power_ups(
+    simulation: Res<GameSimulation>,
+    mut query: Query<&mut Text, With<PowerUpText>>,
+) {
+    let value = simulation
+        .power_ups
+        .iter()
+        .map(|power_up| {
+            let players = power_up
+                .players
+                .iter()
+                .map(|player| format!("P{}", player + 1))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                "{} ({players}): {}s",
+                power_up.effect,
+                power_up.seconds_left().ceil() as u32
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    for mut text in &mut query {
+        text.sections[0].value = value.clone();
+    }
+}
+
+fn check_level_over(
+    mut commands: Commands,
+    simulation: Res<GameSimulation>,
+    rollback: Option<Res<GameRollback>>,
+    recording: Option<Res<Recording>>,
+    current_level: Res<CurrentLevel>,
+    mut next_state: ResMut<NextState<AppStates>>,
+) {
+    // With rollback, only a state no late input can change ends the set
+    let simulation = match &rollback {
+        Some(rollback) => rollback.confirmed_state(&simulation),
+        None => &simulation,
+    };
+    if let Some(outcome) = simulation.outcome() {
+        commands.insert_resource(LevelResult {
+            level: **current_level,
+            outcome,
+            scores: simulation.scores.clone(),
+        });
+        if let Some(recording) = recording {
+            if let Err(error) = recording.save() {
+                eprintln!(
+                    "Couldn't save the replay to {}: {error}",
+                    recording.path().display()
+                );
+            }
+        }
+        next_state.set(AppStates::Intermission);
+    }
+}
+
+/// Removes everything the level spawned, so the next one starts from an empty world.
+fn despawn_level(mut commands: Commands, query: Query<Entity, With<LevelEntity>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<GameSimulation>();
+    commands.remove_resource::<AiControllers>();
+    commands.remove_resource::<BallAssets>();
+    commands.remove_resource::<PickupAssets>();
 }
 
 fn play_collision_sound(
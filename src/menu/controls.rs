@@ -0,0 +1,308 @@
+//! The controls screen: every player's controls, each rebound by clicking it and then pressing
+//! a key or gamepad button. Bindings shared by two controls are shown as conflicts, and saving is
+//! refused until they are resolved.
+
+use bevy::prelude::*;
+
+use super::{
+    spawn_button, AppState, BUTTON_TEXT, ERROR_TEXT, HOVERED_BUTTON, NORMAL_BUTTON, PRESSED_BUTTON,
+};
+use crate::types::components::Player;
+use crate::types::parameters::{save_controls, Binding, Parameters};
+use crate::types::resources::CommandLine;
+
+/// Root of the controls screen.
+#[derive(Component)]
+pub(crate) struct ControlsScreen;
+
+/// A button showing control `control` of player `player`.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct ControlButton {
+    player: usize,
+    control: usize,
+}
+
+#[derive(Component, Clone, Copy)]
+pub(crate) enum ControlsAction {
+    Save,
+    Back,
+}
+
+/// Line under the buttons, telling what happened.
+#[derive(Component)]
+pub(crate) struct ControlsStatus;
+
+/// The controls being edited. They only replace the parameters once saved.
+#[derive(Resource)]
+pub(crate) struct ControlsDraft {
+    players: Vec<Player>,
+    /// The control waiting for a key or button.
+    waiting: Option<ControlButton>,
+    status: String,
+}
+
+pub(crate) fn setup_controls(mut commands: Commands, parameters: Res<Parameters>) {
+    let text = |value: String| {
+        TextBundle::from_section(
+            value,
+            TextStyle {
+                font_size: 30.0,
+                color: BUTTON_TEXT,
+                ..default()
+            },
+        )
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.),
+                    height: Val::Percent(100.),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(5.),
+                    ..default()
+                },
+                ..default()
+            },
+            ControlsScreen,
+        ))
+        .with_children(|parent| {
+            for (player, controls) in parameters.players.iter().enumerate() {
+                parent.spawn(text(format!("Player {}", player + 1)));
+                for control in 0..controls.controls.len() {
+                    spawn_button(parent, "", Val::Px(500.), ControlButton { player, control });
+                }
+            }
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(10.),
+                        margin: UiRect::top(Val::Px(20.)),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    spawn_button(parent, "Save", Val::Px(150.), ControlsAction::Save);
+                    spawn_button(parent, "Back", Val::Px(150.), ControlsAction::Back);
+                });
+            parent.spawn((text(String::new()), ControlsStatus));
+        });
+
+    commands.insert_resource(ControlsDraft {
+        players: parameters.players.clone(),
+        waiting: None,
+        status: "Click a control, then press a key or gamepad button".to_string(),
+    });
+}
+
+pub(crate) fn cleanup_controls(mut commands: Commands, query: Query<Entity, With<ControlsScreen>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<ControlsDraft>();
+}
+
+pub(crate) fn run_controls(
+    mut draft: ResMut<ControlsDraft>,
+    mut parameters: ResMut<Parameters>,
+    command_line: Res<CommandLine>,
+    mut next_state: ResMut<NextState<AppState>>,
+    controls: Query<(&Interaction, &ControlButton), Changed<Interaction>>,
+    mut actions: Query<(&Interaction, &ControlsAction, &mut BackgroundColor), Changed<Interaction>>,
+) {
+    for (interaction, button) in &controls {
+        if *interaction == Interaction::Pressed {
+            draft.waiting = Some(*button);
+            draft.status = "Press a key or gamepad button, or Escape to cancel".to_string();
+        }
+    }
+
+    for (interaction, action, mut color) in &mut actions {
+        *color = match interaction {
+            Interaction::Pressed => PRESSED_BUTTON,
+            Interaction::Hovered => HOVERED_BUTTON,
+            Interaction::None => NORMAL_BUTTON,
+        }
+        .into();
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match action {
+            ControlsAction::Save => {
+                if !conflicts(&draft.players).is_empty() {
+                    draft.status = "Resolve the conflicts before saving".to_string();
+                    continue;
+                }
+                match save_controls(command_line.config_path(), &draft.players) {
+                    Ok(()) => {
+                        for (player, saved) in parameters.players.iter_mut().zip(&draft.players) {
+                            player.controls = saved.controls.clone();
+                        }
+                        draft.status = "Saved".to_string();
+                    }
+                    Err(error) => {
+                        eprintln!("{error}");
+                        draft.status = format!("Couldn't save: {error}");
+                    }
+                }
+            }
+            ControlsAction::Back => next_state.set(AppState::Menu),
+        }
+    }
+}
+
+/// Binds the control waiting for input to the first key or gamepad button pressed.
+pub(crate) fn capture_binding(
+    mut draft: ResMut<ControlsDraft>,
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    gamepads: Res<Gamepads>,
+) {
+    let Some(waiting) = draft.waiting else {
+        return;
+    };
+
+    let binding = if let Some(key) = keys.get_just_pressed().next() {
+        if *key == KeyCode::Escape {
+            draft.waiting = None;
+            draft.status = "Cancelled".to_string();
+            return;
+        }
+        Binding::Key((*key).into())
+    } else if let Some(button) = buttons.get_just_pressed().next() {
+        // Gamepads are numbered like `input::Devices` numbers them: by id among those connected
+        let mut connected: Vec<_> = gamepads.iter().collect();
+        connected.sort_by_key(|gamepad| gamepad.id);
+        let Some(gamepad) = connected.iter().position(|g| *g == button.gamepad) else {
+            return;
+        };
+        Binding::GamepadButton {
+            gamepad,
+            button: button.button_type.into(),
+        }
+    } else {
+        return;
+    };
+
+    if let Some(control) = draft
+        .players
+        .get_mut(waiting.player)
+        .and_then(|player| player.controls.get_mut(waiting.control))
+    {
+        let status = format!("Bound to {binding}");
+        control.input = binding;
+        draft.status = status;
+    }
+    draft.waiting = None;
+}
+
+pub(crate) fn update_controls_labels(
+    draft: Res<ControlsDraft>,
+    mut buttons: Query<(
+        &ControlButton,
+        &Interaction,
+        &mut BackgroundColor,
+        &Children,
+    )>,
+    mut texts: Query<&mut Text>,
+    status: Query<Entity, With<ControlsStatus>>,
+) {
+    let conflicts = conflicts(&draft.players);
+
+    for (button, interaction, mut color, children) in &mut buttons {
+        let Some(control) = draft
+            .players
+            .get(button.player)
+            .and_then(|player| player.controls.get(button.control))
+        else {
+            continue;
+        };
+        let waiting = draft.waiting == Some(*button);
+        let conflicting = conflicts.contains(button);
+
+        *color = if waiting {
+            PRESSED_BUTTON.into()
+        } else if *interaction == Interaction::Hovered {
+            HOVERED_BUTTON.into()
+        } else {
+            NORMAL_BUTTON.into()
+        };
+        for child in children {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                text.sections[0].value = if waiting {
+                    format!("{}: ...", control.effect)
+                } else {
+                    format!("{}: {}", control.effect, control.input)
+                };
+                text.sections[0].style.color = if conflicting { ERROR_TEXT } else { BUTTON_TEXT };
+            }
+        }
+    }
+
+    for entity in &status {
+        if let Ok(mut text) = texts.get_mut(entity) {
+            text.sections[0].value = if conflicts.is_empty() || draft.waiting.is_some() {
+                draft.status.clone()
+            } else {
+                format!("{} (red controls share a binding)", draft.status)
+            };
+        }
+    }
+}
+
+/// Every control whose binding is also used by another control, of the same player or not.
+fn conflicts(players: &[Player]) -> Vec<ControlButton> {
+    let controls: Vec<(ControlButton, &Binding)> = players
+        .iter()
+        .enumerate()
+        .flat_map(|(player, controls)| {
+            controls
+                .controls
+                .iter()
+                .enumerate()
+                .map(move |(control, bound)| (ControlButton { player, control }, &bound.input))
+        })
+        .collect();
+    controls
+        .iter()
+        .filter(|(button, binding)| {
+            controls
+                .iter()
+                .any(|(other, other_binding)| other != button && other_binding == binding)
+        })
+        .map(|(button, _)| *button)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::parameters::MyKeyCode;
+
+    #[test]
+    fn bindings_shared_between_players_are_conflicts() {
+        let mut players = Parameters::default().players;
+        assert_eq!(conflicts(&players), vec![]);
+
+        // Player 1's down key is now player 0's up key too
+        players[1].controls[1].input = players[0].controls[0].input.clone();
+        let conflicting = vec![
+            ControlButton {
+                player: 0,
+                control: 0,
+            },
+            ControlButton {
+                player: 1,
+                control: 1,
+            },
+        ];
+        assert_eq!(conflicts(&players), conflicting);
+
+        players[1].controls[1].input = Binding::Key(MyKeyCode::Down);
+        assert_eq!(conflicts(&players), vec![]);
+    }
+}
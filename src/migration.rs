@@ -0,0 +1,527 @@
+//! Upgrading parameters files written for older layouts of `Parameters`.
+//!
+//! Files carry a `schema_version`; files from before it existed count as version 0. Each step
+//! rewrites the raw TOML of one version into the next, so a file of any age is brought up to
+//! `SCHEMA_VERSION` before it is merged over the defaults and deserialized. The file on disk is
+//! never touched.
+
+use toml::{value::Table, Value};
+
+use crate::parameters::ConfigProblem;
+
+/// Layout of `Parameters` this build reads and writes.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Brings `file` up to `SCHEMA_VERSION`, returning the version it was written for.
+pub fn migrate(file: &mut Value) -> Result<u32, Vec<ConfigProblem>> {
+    let Some(table) = file.as_table_mut() else {
+        return Err(vec![ConfigProblem::new("", "must be a table")]);
+    };
+
+    let version = match table.get("schema_version") {
+        None => 0,
+        Some(Value::Integer(version)) if *version >= 0 => *version as u32,
+        Some(_) => {
+            return Err(vec![ConfigProblem::new(
+                "schema_version",
+                "must be a positive integer",
+            )])
+        }
+    };
+    if version > SCHEMA_VERSION {
+        return Err(vec![ConfigProblem::new(
+            "schema_version",
+            format!("is {version}, but this game only understands up to {SCHEMA_VERSION}"),
+        )]);
+    }
+
+    let steps: [fn(&mut Table) -> Vec<ConfigProblem>; SCHEMA_VERSION as usize] =
+        [v0_to_v1, v1_to_v2];
+    for step in &steps[version as usize..] {
+        let problems = step(table);
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+    }
+    table.insert(
+        "schema_version".to_string(),
+        Value::Integer(SCHEMA_VERSION as i64),
+    );
+
+    Ok(version)
+}
+
+/// Version 0 described a single rectangular arena: `[wall]` gave its borders, `[paddle]` and
+/// `[distribution]` where the paddles went, and each player's `moves` and scoring side. Version 1
+/// lists the walls and paddles of each level explicitly.
+fn v0_to_v1(file: &mut Table) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+
+    let wall = file.remove("wall");
+    let paddle = file.remove("paddle");
+    let distribution = file.remove("distribution");
+    let mut colors = file.remove("colors");
+    let wall_color = colors
+        .as_mut()
+        .and_then(Value::as_table_mut)
+        .and_then(|colors| colors.remove("wall"));
+    let paddle_color = colors
+        .as_mut()
+        .and_then(Value::as_table_mut)
+        .and_then(|colors| colors.remove("paddle"));
+    if let Some(colors) = colors {
+        file.insert("colors".to_string(), colors);
+    }
+
+    // Players: `moves` became `controls`, the scoring side moved to the level's paddles
+    let mut scoring_sides = Vec::new();
+    if let Some(Value::Array(players)) = file.get_mut("players") {
+        for (i, player) in players.iter_mut().enumerate() {
+            let Some(player) = player.as_table_mut() else {
+                continue;
+            };
+            if let Some(moves) = player.remove("moves") {
+                player.insert("controls".to_string(), moves);
+            }
+            match player.remove("wall_that_gives_points") {
+                Some(Value::String(side)) => match Side::from_name(&side) {
+                    Some(side) => scoring_sides.push(side),
+                    None => problems.push(ConfigProblem::new(
+                        format!("players[{i}].wall_that_gives_points"),
+                        format!("unknown side {side:?}, expected Left, Up, Right or Down"),
+                    )),
+                },
+                Some(_) => problems.push(ConfigProblem::new(
+                    format!("players[{i}].wall_that_gives_points"),
+                    "must be Left, Up, Right or Down",
+                )),
+                None => {}
+            }
+        }
+    }
+
+    // Misc: the brick gaps lived in `[distribution]`
+    if let Some(distribution) = distribution.as_ref().and_then(Value::as_table) {
+        let mut misc = Table::new();
+        for key in [
+            "minimum_gap_between_paddle_and_goal_bricks",
+            "gap_between_bricks",
+            "minimum_gap_between_bricks_and_horizontal_walls",
+            "minimum_gap_between_bricks_and_vertical_walls",
+        ] {
+            if let Some(value) = distribution.get(key) {
+                misc.insert(key.to_string(), value.clone());
+            }
+        }
+        file.insert("misc".to_string(), Value::Table(misc));
+    }
+
+    // Levels: only build one if the file described the arena, otherwise the defaults apply
+    if let Some(wall) = wall.as_ref().and_then(Value::as_table) {
+        let number = |table: Option<&Table>, path: &str, key: &str, problems: &mut Vec<_>| {
+            let value = table.and_then(|table| table.get(key));
+            match value {
+                Some(Value::Float(value)) => Some(*value),
+                Some(Value::Integer(value)) => Some(*value as f64),
+                Some(_) => {
+                    problems.push(ConfigProblem::new(
+                        format!("{path}.{key}"),
+                        "must be a number",
+                    ));
+                    None
+                }
+                None => {
+                    problems.push(ConfigProblem::new(
+                        format!("{path}.{key}"),
+                        "is missing, needed to build the level",
+                    ));
+                    None
+                }
+            }
+        };
+        let paddle = paddle.as_ref().and_then(Value::as_table);
+        let distribution = distribution.as_ref().and_then(Value::as_table);
+
+        let (
+            Some(thickness),
+            Some(left),
+            Some(right),
+            Some(down),
+            Some(up),
+            Some(width),
+            Some(height),
+            Some(speed),
+            Some(side_gap),
+            Some(horizontal_gap),
+        ) = (
+            number(Some(wall), "wall", "thickness", &mut problems),
+            number(Some(wall), "wall", "x_left_wall", &mut problems),
+            number(Some(wall), "wall", "x_right_wall", &mut problems),
+            number(Some(wall), "wall", "y_down_wall", &mut problems),
+            number(Some(wall), "wall", "y_up_wall", &mut problems),
+            number(paddle, "paddle", "width", &mut problems),
+            number(paddle, "paddle", "height", &mut problems),
+            number(paddle, "paddle", "speed", &mut problems),
+            number(
+                distribution,
+                "distribution",
+                "gap_between_paddle_and_side_wall",
+                &mut problems,
+            ),
+            number(
+                distribution,
+                "distribution",
+                "gap_between_paddle_and_horizontal_wall",
+                &mut problems,
+            ),
+        )
+        else {
+            return problems;
+        };
+
+        let wall_color = rgba_tuple(wall_color.as_ref(), [0.8, 0.8, 0.8, 1.]);
+        let paddle_color = rgba_tuple(paddle_color.as_ref(), [0.3, 0.3, 0.7, 1.]);
+
+        // Same ring as the defaults: left, up, right and down walls get ids 0 to 3
+        let corners = [(left, down), (left, up), (right, up), (right, down)];
+        let walls = (0..corners.len())
+            .map(|id| {
+                let mut wall = Table::new();
+                wall.insert("id".to_string(), Value::Integer(id as i64));
+                wall.insert(
+                    "ends".to_string(),
+                    Value::Array(vec![
+                        point(corners[id]),
+                        point(corners[(id + 1) % corners.len()]),
+                    ]),
+                );
+                wall.insert("thickness".to_string(), Value::Float(thickness));
+                wall.insert("color".to_string(), wall_color.clone());
+                Value::Table(wall)
+            })
+            .collect();
+
+        // A player scores on one side, so their paddle defends the opposite one
+        let lowest = down + thickness / 2. + horizontal_gap + height / 2.;
+        let highest = up - thickness / 2. - horizontal_gap - height / 2.;
+        let paddles = scoring_sides
+            .iter()
+            .map(|side| {
+                let x = match side.opposite() {
+                    Side::Right => right - side_gap,
+                    _ => left + side_gap,
+                };
+                let mut paddle = Table::new();
+                paddle.insert("width".to_string(), Value::Float(width));
+                paddle.insert("height".to_string(), Value::Float(height));
+                paddle.insert("x".to_string(), Value::Float(x));
+                paddle.insert("y".to_string(), Value::Float(0.));
+                paddle.insert("z".to_string(), Value::Float(0.));
+                paddle.insert(
+                    "bounds".to_string(),
+                    Value::Array(vec![point((x, lowest)), point((x, highest))]),
+                );
+                paddle.insert("speed".to_string(), Value::Float(speed));
+                paddle.insert("color_rgba".to_string(), paddle_color.clone());
+                paddle.insert(
+                    "wall_that_gives_points".to_string(),
+                    Value::Integer(side.wall_id()),
+                );
+                Value::Table(paddle)
+            })
+            .collect();
+
+        let mut level = Table::new();
+        level.insert("walls".to_string(), Value::Array(walls));
+        level.insert("paddles".to_string(), Value::Array(paddles));
+        file.insert(
+            "levels".to_string(),
+            Value::Array(vec![Value::Table(level)]),
+        );
+    }
+
+    problems
+}
+
+/// Version 1 bound controls to keyboard keys only, as `key`. Version 2 binds them to any device,
+/// as `input`, so a key becomes `input = { Key = ... }`.
+fn v1_to_v2(file: &mut Table) -> Vec<ConfigProblem> {
+    let Some(Value::Array(players)) = file.get_mut("players") else {
+        return Vec::new();
+    };
+    let controls = players
+        .iter_mut()
+        .filter_map(Value::as_table_mut)
+        .filter_map(|player| player.get_mut("controls"))
+        .filter_map(Value::as_array_mut)
+        .flatten()
+        .filter_map(Value::as_table_mut);
+    for control in controls {
+        if let Some(key) = control.remove("key") {
+            let mut input = Table::new();
+            input.insert("Key".to_string(), key);
+            control.insert("input".to_string(), Value::Table(input));
+        }
+    }
+    Vec::new()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Left,
+    Up,
+    Right,
+    Down,
+}
+
+impl Side {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Left" => Some(Side::Left),
+            "Up" => Some(Side::Up),
+            "Right" => Some(Side::Right),
+            "Down" => Some(Side::Down),
+            _ => None,
+        }
+    }
+
+    fn opposite(self) -> Self {
+        match self {
+            Side::Left => Side::Right,
+            Side::Up => Side::Down,
+            Side::Right => Side::Left,
+            Side::Down => Side::Up,
+        }
+    }
+
+    fn wall_id(self) -> i64 {
+        match self {
+            Side::Left => 0,
+            Side::Up => 1,
+            Side::Right => 2,
+            Side::Down => 3,
+        }
+    }
+}
+
+fn point((x, y): (f64, f64)) -> Value {
+    Value::Array(vec![Value::Float(x), Value::Float(y)])
+}
+
+/// Turns a serialized `Color::Rgba` into the `(r, g, b, a)` tuple levels use.
+fn rgba_tuple(color: Option<&Value>, fallback: [f64; 4]) -> Value {
+    let rgba = color.and_then(|color| color.get("Rgba"));
+    let channels = ["red", "green", "blue", "alpha"]
+        .iter()
+        .zip(fallback)
+        .map(|(channel, fallback)| {
+            let value = match rgba.and_then(|rgba| rgba.get(channel)) {
+                Some(Value::Float(value)) => *value,
+                Some(Value::Integer(value)) => *value as f64,
+                _ => fallback,
+            };
+            Value::Float(value)
+        })
+        .collect();
+    Value::Array(channels)
+}
+
+/// Lays `overlay` over `base`: tables are merged key by key, anything else is replaced whole.
+pub fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::parameters::validate;
+    use crate::types::parameters::{Binding, MyKeyCode, Parameters};
+
+    /// A parameters file from before `schema_version`, for a 1000 by 400 arena.
+    const V0_FILE: &str = r#"
+[[players]]
+wall_that_gives_points = "Right"
+
+[[players.moves]]
+key = "W"
+effect = { Move = [0.0, 1.0, 0.0] }
+
+[[players]]
+wall_that_gives_points = "Left"
+
+[[players.moves]]
+key = "Up"
+effect = { Move = [0.0, 1.0, 0.0] }
+
+[paddle]
+width = 20
+height = 100
+speed = 400.0
+
+[distribution]
+gap_between_paddle_and_side_wall = 50
+gap_between_paddle_and_horizontal_wall = 10
+gap_between_bricks = 2
+
+[wall]
+thickness = 10
+x_left_wall = -500
+x_right_wall = 500
+y_down_wall = -200
+y_up_wall = 200
+"#;
+
+    /// Reads `file` over the defaults, as the parameters files are.
+    fn over_defaults(file: Value) -> Parameters {
+        let mut merged = Value::try_from(Parameters::default()).unwrap();
+        merge(&mut merged, file);
+        Parameters::deserialize(merged).unwrap()
+    }
+
+    #[test]
+    fn version_0_files_get_a_level_and_controls() {
+        let mut file: Value = toml::from_str(V0_FILE).unwrap();
+        assert_eq!(migrate(&mut file), Ok(0));
+        assert_eq!(
+            file["schema_version"].as_integer(),
+            Some(SCHEMA_VERSION as i64)
+        );
+        for key in ["wall", "paddle", "distribution"] {
+            assert!(file.get(key).is_none(), "{key} is still there");
+        }
+        assert_eq!(file["misc"]["gap_between_bricks"].as_integer(), Some(2));
+
+        let parameters = over_defaults(file);
+        assert!(validate(&parameters).is_empty());
+        assert_eq!(
+            parameters.players[1].controls[0].input,
+            Binding::Key(MyKeyCode::Up)
+        );
+
+        let level = &parameters.levels[0];
+        let corners: Vec<_> = level
+            .walls
+            .iter()
+            .map(|wall| (wall.id, wall.end_a().to_array()))
+            .collect();
+        assert_eq!(
+            corners,
+            [
+                (0, [-500., -200.]),
+                (1, [-500., 200.]),
+                (2, [500., 200.]),
+                (3, [500., -200.]),
+            ]
+        );
+        // Scoring on the right means defending the left, 50 in from its wall
+        let paddles: Vec<_> = level
+            .paddles
+            .iter()
+            .map(|paddle| (paddle.x.into_inner(), paddle.wall_that_gives_points))
+            .collect();
+        assert_eq!(paddles, [(-450., 2), (450., 0)]);
+        // Between the horizontal walls, less their half thickness, the gap and half a paddle
+        let bounds = &level.paddles[0].bounds;
+        assert_eq!(bounds[0].1.into_inner(), -135.);
+        assert_eq!(bounds[1].1.into_inner(), 135.);
+    }
+
+    #[test]
+    fn version_1_files_bind_keys_as_inputs() {
+        let mut file: Value = toml::from_str(
+            r#"
+schema_version = 1
+
+[[players]]
+
+[[players.controls]]
+key = "Q"
+effect = { Move = [0.0, 1.0, 0.0] }
+"#,
+        )
+        .unwrap();
+        assert_eq!(migrate(&mut file), Ok(1));
+        let control = &file["players"][0]["controls"][0];
+        assert!(control.get("key").is_none());
+        assert_eq!(control["input"]["Key"].as_str(), Some("Q"));
+        assert_eq!(over_defaults(file).players[0].controls.len(), 1);
+    }
+
+    #[test]
+    fn the_shipped_parameters_file_is_current() {
+        let text = std::fs::read_to_string("parameters.toml").unwrap();
+        let mut file: Value = toml::from_str(&text).unwrap();
+        assert_eq!(migrate(&mut file), Ok(SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn current_and_future_versions() {
+        let mut current: Value = toml::from_str("schema_version = 2").unwrap();
+        assert_eq!(migrate(&mut current), Ok(SCHEMA_VERSION));
+        let mut future: Value = toml::from_str("schema_version = 3").unwrap();
+        let problems = migrate(&mut future).unwrap_err();
+        assert_eq!(problems[0].path, "schema_version");
+    }
+
+    #[test]
+    fn layers_only_replace_what_they_set() {
+        let mut base: Value = toml::from_str(
+            r#"
+name = "base"
+list = [1, 2, 3]
+
+[ball]
+speed = 400.0
+size = [30.0, 30.0]
+
+[ball.color]
+red = 1.0
+"#,
+        )
+        .unwrap();
+        let layer: Value = toml::from_str(
+            r#"
+list = [4]
+
+[ball]
+speed = 600.0
+
+[ball.color]
+green = 0.5
+"#,
+        )
+        .unwrap();
+        merge(&mut base, layer);
+
+        let expected: Value = toml::from_str(
+            r#"
+name = "base"
+list = [4]
+
+[ball]
+speed = 600.0
+size = [30.0, 30.0]
+
+[ball.color]
+red = 1.0
+green = 0.5
+"#,
+        )
+        .unwrap();
+        assert_eq!(base, expected);
+    }
+}
@@ -0,0 +1,1284 @@
+//! Playing over the network, with the host as the authority.
+//!
+//! One instance hosts: it runs the `Simulation` as usual, takes the inputs of the players that
+//! clients joined as in place of local controls, and sends every client a `Snapshot` of the
+//! balls, paddles, bricks and scores after each step. Clients never simulate; they send the
+//! `PlayerInput` their own controls ask for, and draw the last snapshot they received.
+//!
+//! In `NetMode::Rollback` every instance runs the match instead, see `rollback`, and inputs
+//! travel rather than snapshots: each instance sends the inputs of the players it plays as
+//! `Frames`, and the host passes those of every player on to every client. A set then only runs
+//! once every remote player has joined, and a player whose client leaves stands still until the
+//! set ends.
+//!
+//! Messages travel over UDP, one per datagram, or over TCP, each prefixed by its length. Both
+//! use the encoding of `codec`, starting with `PROTOCOL_VERSION`. Nothing here depends on Bevy,
+//! so a host and clients can be run from a test over loopback, e.g. by binding a `NetServer` to
+//! `127.0.0.1:0` and connecting `NetClient`s to its `local_address`. Two copies of the game on
+//! one machine work the same way: run one with `--host 127.0.0.1:7777` and the other with
+//! `--join 127.0.0.1:7777`, or set `net.role` and `net.address` in their parameters files.
+//!
+//! `LinkConditions` make such a link as bad as a distant one: every datagram sent is held back
+//! by the configured latency and jitter, and some are dropped, on both sides.
+//!
+//! Clients build each level from their own files, so they must have the same levels and players
+//! as the host. Random arenas differ from one machine to the other and aren't supported.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+use bevy::math::{Vec2, Vec3};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::codec::{Reader, Writer};
+use crate::rollback::{PlayerFrames, Rollback};
+use crate::simulation::{BallState, PlayerInput, Rally, Simulation};
+use crate::types::parameters::ParametersNet;
+use crate::types::resources::MatchState;
+
+/// First byte of every message. Peers speaking another version are ignored.
+const PROTOCOL_VERSION: u8 = 3;
+
+/// Clients not heard from for this long are dropped, and their player is free again. Clients
+/// give up on a host as quiet for as long.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a client repeats its request to join until the host answers, as UDP may lose it.
+const JOIN_RETRY: Duration = Duration::from_secs(1);
+
+/// How long a client waits for a TCP connection to the host.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Largest message, in bytes. Datagrams and frames beyond it are dropped.
+const MAX_MESSAGE: usize = 64 * 1024;
+
+/// Bytes waiting to be sent to a TCP peer beyond which snapshots are skipped, so a stalled peer
+/// doesn't make the host buffer without end.
+const MAX_BACKLOG: usize = 4 * MAX_MESSAGE;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Transport {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+/// What the host sends clients.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum NetMode {
+    /// Snapshots of the host's simulation; clients wait a round trip to see their moves.
+    #[default]
+    Snapshots,
+    /// Inputs, which every instance simulates, predicting those that are late.
+    Rollback,
+}
+
+/// A worse link than the actual one, for trying the game over loopback as if over the internet.
+/// Applies to UDP only.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct LinkConditions {
+    /// Milliseconds every datagram is held back.
+    pub latency: f32,
+    /// Up to this many more milliseconds, drawn for each datagram, which may reorder them.
+    pub jitter: f32,
+    /// Share of datagrams dropped, from 0 to 1.
+    pub loss: f32,
+}
+
+/// Where the match stands, sent with every message of the host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchInfo {
+    /// Counts the sets the host started, replays included, so clients notice a new one even on
+    /// the same level.
+    pub round: u32,
+    /// Index of the level being played, like `CurrentLevel`.
+    pub level: u32,
+    /// Seed the set was started with, so clients simulating it too build the same one.
+    pub seed: u64,
+    /// Standings of the match, as in `MatchState`.
+    pub sets_won: Vec<u32>,
+    pub sets_played: u32,
+    /// The match is decided, and the host shows the results.
+    pub over: bool,
+}
+
+impl MatchInfo {
+    /// The standings of the match as the host has them.
+    pub fn match_state(&self) -> MatchState {
+        MatchState {
+            sets_won: self.sets_won.clone(),
+            sets_played: self.sets_played,
+        }
+    }
+}
+
+/// The state clients draw, as of one step of the host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub info: MatchInfo,
+    pub tick: u64,
+    /// Steps before the next serve, while no ball is in play.
+    pub countdown: Option<u32>,
+    pub balls: Vec<BallSnapshot>,
+    pub paddles: Vec<PaddleSnapshot>,
+    pub scores: Vec<f32>,
+    pub conceded: Vec<u32>,
+    /// Id and hit points of every brick left.
+    pub bricks: Vec<(u64, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BallSnapshot {
+    pub id: u64,
+    pub position: Vec3,
+    pub velocity: Vec2,
+    pub owner: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaddleSnapshot {
+    pub position: Vec3,
+    pub size: Vec3,
+    pub tilt: f32,
+    pub eliminated: bool,
+}
+
+impl Snapshot {
+    pub fn capture(simulation: &Simulation, info: MatchInfo) -> Self {
+        Self {
+            info,
+            tick: simulation.tick,
+            countdown: match simulation.rally {
+                Rally::Countdown { ticks_left, .. } => Some(ticks_left),
+                Rally::Playing => None,
+            },
+            balls: simulation
+                .balls
+                .iter()
+                .map(|ball| BallSnapshot {
+                    id: ball.id,
+                    position: ball.position,
+                    velocity: ball.velocity,
+                    owner: ball.owner,
+                })
+                .collect(),
+            paddles: simulation
+                .paddles
+                .iter()
+                .map(|paddle| PaddleSnapshot {
+                    position: paddle.position,
+                    size: paddle.size,
+                    tilt: paddle.tilt,
+                    eliminated: paddle.eliminated,
+                })
+                .collect(),
+            scores: simulation.scores.clone(),
+            conceded: simulation.conceded.clone(),
+            bricks: simulation
+                .bricks
+                .iter()
+                .map(|brick| (brick.id, brick.hit_points))
+                .collect(),
+        }
+    }
+
+    /// Makes `simulation`, built from the same level as the host's, show this snapshot.
+    pub fn apply(&self, simulation: &mut Simulation) {
+        simulation.tick = self.tick;
+        simulation.rally = match self.countdown {
+            Some(ticks_left) => Rally::Countdown {
+                ticks_left,
+                receiver: None,
+            },
+            None => Rally::Playing,
+        };
+        let size = simulation.ball.size;
+        simulation.balls = self
+            .balls
+            .iter()
+            .map(|ball| BallState {
+                id: ball.id,
+                position: ball.position,
+                velocity: ball.velocity,
+                size,
+                owner: ball.owner,
+            })
+            .collect();
+        for (paddle, snapshot) in simulation.paddles.iter_mut().zip(&self.paddles) {
+            paddle.position = snapshot.position;
+            paddle.size = snapshot.size;
+            paddle.tilt = snapshot.tilt;
+            paddle.eliminated = snapshot.eliminated;
+        }
+        for (score, snapshot) in simulation.scores.iter_mut().zip(&self.scores) {
+            *score = *snapshot;
+        }
+        for (conceded, snapshot) in simulation.conceded.iter_mut().zip(&self.conceded) {
+            *conceded = *snapshot;
+        }
+        simulation.bricks.retain_mut(|brick| {
+            match self.bricks.iter().find(|(id, _)| *id == brick.id) {
+                Some((_, hit_points)) => {
+                    brick.hit_points = *hit_points;
+                    true
+                }
+                None => false,
+            }
+        });
+    }
+}
+
+/// Inputs sent in `NetMode::Rollback`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frames {
+    /// From the host, the first frame it lacks the client's input for; from a client, the first
+    /// frame it lacks some player's input for. Inputs from there on are sent.
+    pub ack: u64,
+    pub players: Vec<PlayerFrames>,
+    /// The sender's latest confirmed frame and the checksum of the state before it, see
+    /// `Rollback::check`.
+    pub checksum: Option<(u64, u64)>,
+}
+
+/// What a client tells the host.
+#[derive(Debug, Clone, PartialEq)]
+enum ClientMessage {
+    /// Asks for a player to control. Repeated until answered.
+    Join,
+    /// What the client's controls ask for. `sequence` grows with every input sent, so older ones
+    /// arriving late over UDP are ignored.
+    Input {
+        sequence: u64,
+        input: PlayerInput,
+    },
+    /// Inputs of the client's player during set `round`. Sent every step, between sets too.
+    Frames {
+        round: u32,
+        frames: Frames,
+    },
+    Leave,
+}
+
+/// What the host tells a client.
+#[derive(Debug, Clone, PartialEq)]
+enum ServerMessage {
+    Welcome {
+        player: usize,
+        mode: NetMode,
+    },
+    /// Every remote player is taken.
+    Full,
+    Snapshot(Snapshot),
+    /// Inputs of the other players, or none between sets.
+    Frames {
+        info: MatchInfo,
+        frames: Frames,
+    },
+}
+
+/// The host's side: accepts clients, gives each one of the remote players, and keeps the last
+/// input each of them sent.
+pub struct NetServer {
+    listener: Listener,
+    outbox: Outbox,
+    mode: NetMode,
+    peers: Vec<Peer>,
+    /// Players clients may take, in the order they are handed out.
+    remote_players: Vec<usize>,
+    /// Players whose client left during the set. In `NetMode::Rollback` they aren't handed out
+    /// again before the next one.
+    left: Vec<usize>,
+    level: u32,
+    seed: u64,
+    round: u32,
+    /// Sent again between sets, when there is no simulation to take a snapshot of.
+    last: Option<Snapshot>,
+    /// Inputs clients sent for their player since the last `take_frames`.
+    frames: Vec<Frames>,
+}
+
+enum Listener {
+    Udp(UdpSocket),
+    Tcp(TcpListener),
+}
+
+struct Peer {
+    link: PeerLink,
+    player: Option<usize>,
+    input: PlayerInput,
+    sequence: u64,
+    /// First frame of the set the peer lacks some player's input for, see `Frames.ack`.
+    ack: u64,
+    last_heard: Instant,
+    /// The peer left or was refused, and goes at the next `poll`.
+    closed: bool,
+}
+
+enum PeerLink {
+    Udp(SocketAddr),
+    Tcp(Framed),
+}
+
+impl NetServer {
+    pub fn bind(net: &ParametersNet) -> io::Result<Self> {
+        let listener = match net.transport {
+            Transport::Udp => {
+                let socket = UdpSocket::bind(&net.address)?;
+                socket.set_nonblocking(true)?;
+                Listener::Udp(socket)
+            }
+            Transport::Tcp => {
+                let listener = TcpListener::bind(&net.address)?;
+                listener.set_nonblocking(true)?;
+                Listener::Tcp(listener)
+            }
+        };
+        Ok(Self {
+            listener,
+            outbox: Outbox::new(net.conditions),
+            mode: net.mode,
+            peers: Vec::new(),
+            remote_players: net.remote_players.clone(),
+            left: Vec::new(),
+            level: 0,
+            seed: 0,
+            round: 0,
+            last: None,
+            frames: Vec::new(),
+        })
+    }
+
+    pub fn mode(&self) -> NetMode {
+        self.mode
+    }
+
+    pub fn remote_players(&self) -> &[usize] {
+        &self.remote_players
+    }
+
+    /// Players whose client left during the set, who now stand still.
+    pub fn left(&self) -> &[usize] {
+        &self.left
+    }
+
+    /// Where clients reach the server, with the actual port when bound to port 0.
+    pub fn local_address(&self) -> io::Result<SocketAddr> {
+        match &self.listener {
+            Listener::Udp(socket) => socket.local_addr(),
+            Listener::Tcp(listener) => listener.local_addr(),
+        }
+    }
+
+    /// Marks the start of a set on level `level`, with seed `seed`, which clients then load.
+    pub fn start_round(&mut self, level: usize, seed: u64) {
+        self.level = level as u32;
+        self.seed = seed;
+        self.round += 1;
+        self.left.clear();
+        self.frames.clear();
+        for peer in &mut self.peers {
+            peer.ack = 0;
+        }
+    }
+
+    /// Reads everything clients sent since the last call, accepting new ones and dropping those
+    /// that left or went quiet.
+    pub fn poll(&mut self) {
+        if let Listener::Udp(socket) = &self.listener {
+            self.outbox.flush(socket);
+        }
+        let mut received = Vec::new();
+        match &self.listener {
+            Listener::Udp(socket) => {
+                let mut buffer = vec![0; MAX_MESSAGE];
+                loop {
+                    let (length, from) = match socket.recv_from(&mut buffer) {
+                        Ok(received) => received,
+                        Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                        // A client that went away makes the next read report it; skip it
+                        Err(_) => continue,
+                    };
+                    let Some(message) = decode_client(&buffer[..length]) else {
+                        continue;
+                    };
+                    let index = match self.peers.iter().position(
+                        |peer| matches!(peer.link, PeerLink::Udp(address) if address == from),
+                    ) {
+                        Some(index) => index,
+                        None => {
+                            self.peers.push(Peer::new(PeerLink::Udp(from)));
+                            self.peers.len() - 1
+                        }
+                    };
+                    received.push((index, message));
+                }
+            }
+            Listener::Tcp(listener) => {
+                while let Ok((stream, _)) = listener.accept() {
+                    if let Ok(framed) = Framed::new(stream) {
+                        self.peers.push(Peer::new(PeerLink::Tcp(framed)));
+                    }
+                }
+                for (index, peer) in self.peers.iter_mut().enumerate() {
+                    let PeerLink::Tcp(framed) = &mut peer.link else {
+                        continue;
+                    };
+                    received.extend(
+                        framed
+                            .receive()
+                            .iter()
+                            .filter_map(|frame| decode_client(frame))
+                            .map(|message| (index, message)),
+                    );
+                }
+            }
+        }
+        for (index, message) in received {
+            self.handle(index, message);
+        }
+
+        // Peers that left, were refused or went quiet
+        let now = Instant::now();
+        for index in 0..self.peers.len() {
+            if self.peers[index].gone(now) {
+                self.close(index);
+            }
+        }
+        self.peers.retain(|peer| !peer.gone(now));
+    }
+
+    fn handle(&mut self, index: usize, message: ClientMessage) {
+        self.peers[index].last_heard = Instant::now();
+        match message {
+            ClientMessage::Join => {
+                let rollback = self.mode == NetMode::Rollback;
+                let taken = |player: &usize| {
+                    self.peers.iter().any(|peer| peer.player == Some(*player))
+                        || (rollback && self.left.contains(player))
+                };
+                let player = self.peers[index]
+                    .player
+                    .or_else(|| self.remote_players.iter().copied().find(|p| !taken(p)));
+                let peer = &mut self.peers[index];
+                peer.player = player;
+                let reply = match player {
+                    Some(player) => ServerMessage::Welcome {
+                        player,
+                        mode: self.mode,
+                    },
+                    None => ServerMessage::Full,
+                };
+                self.send(index, &reply);
+                if player.is_none() {
+                    self.close(index);
+                }
+            }
+            ClientMessage::Input { sequence, input } => {
+                let peer = &mut self.peers[index];
+                if peer.player.is_some() && sequence >= peer.sequence {
+                    peer.sequence = sequence;
+                    peer.input = input;
+                }
+            }
+            ClientMessage::Frames { round, mut frames } => {
+                let peer = &mut self.peers[index];
+                let Some(player) = peer.player.filter(|_| round == self.round) else {
+                    return;
+                };
+                // Datagrams may arrive out of order; acknowledgements only grow
+                peer.ack = peer.ack.max(frames.ack);
+                frames.players.retain(|sent| sent.player == player);
+                self.frames.push(frames);
+            }
+            ClientMessage::Leave => self.close(index),
+        }
+    }
+
+    /// Drops a peer at the next `poll`, freeing its player.
+    fn close(&mut self, index: usize) {
+        let peer = &mut self.peers[index];
+        if let Some(player) = peer.player.take() {
+            self.left.push(player);
+        }
+        peer.closed = true;
+        if let PeerLink::Tcp(framed) = &mut peer.link {
+            framed.closed = true;
+        }
+    }
+
+    /// The inputs clients sent for their own player since the last call, in `NetMode::Rollback`.
+    pub fn take_frames(&mut self) -> Vec<Frames> {
+        std::mem::take(&mut self.frames)
+    }
+
+    /// First frame of the set some client lacks some player's input for, if any client plays.
+    /// The inputs from there on are still to be sent.
+    pub fn acknowledged(&self) -> Option<u64> {
+        self.peers
+            .iter()
+            .filter(|peer| peer.player.is_some())
+            .map(|peer| peer.ack)
+            .min()
+    }
+
+    /// Replaces the inputs of the remote players by the last ones their clients sent. Remote
+    /// players no client plays yet stand still.
+    pub fn apply_inputs(&self, inputs: &mut [PlayerInput]) {
+        for player in &self.remote_players {
+            if let Some(input) = inputs.get_mut(*player) {
+                *input = PlayerInput::default();
+            }
+        }
+        for peer in &self.peers {
+            if let Some(input) = peer.player.and_then(|player| inputs.get_mut(player)) {
+                *input = peer.input;
+            }
+        }
+    }
+
+    /// Sends every client a snapshot of `simulation`, or between sets, when there is none, the
+    /// last one again with the standings of `match_state`. In `NetMode::Rollback`, sends each
+    /// one instead the inputs of `rollback` it lacks. `over` tells them the match is decided.
+    pub fn broadcast(
+        &mut self,
+        simulation: Option<&Simulation>,
+        rollback: Option<&Rollback>,
+        match_state: &MatchState,
+        over: bool,
+    ) {
+        let info = MatchInfo {
+            round: self.round,
+            level: self.level,
+            seed: self.seed,
+            sets_won: match_state.sets_won.clone(),
+            sets_played: match_state.sets_played,
+            over,
+        };
+        if self.mode == NetMode::Rollback {
+            for index in 0..self.peers.len() {
+                let Some(player) = self.peers[index].player else {
+                    continue;
+                };
+                let frames = rollback
+                    .map(|rollback| Frames {
+                        ack: rollback.known_until(player),
+                        players: (0..rollback.players())
+                            .filter(|other| *other != player)
+                            .filter_map(|other| rollback.frames_from(other, self.peers[index].ack))
+                            .collect(),
+                        checksum: rollback.latest_checksum(),
+                    })
+                    .unwrap_or_default();
+                let message = ServerMessage::Frames {
+                    info: info.clone(),
+                    frames,
+                };
+                self.send_bytes(index, &encode_server(&message), true);
+            }
+            return;
+        }
+
+        let snapshot = match (simulation, self.last.take()) {
+            (Some(simulation), _) => Snapshot::capture(simulation, info),
+            (None, Some(last)) => Snapshot { info, ..last },
+            (None, None) => return,
+        };
+        let bytes = encode_server(&ServerMessage::Snapshot(snapshot.clone()));
+        self.last = Some(snapshot);
+        for index in 0..self.peers.len() {
+            if self.peers[index].player.is_some() {
+                self.send_bytes(index, &bytes, true);
+            }
+        }
+    }
+
+    fn send(&mut self, index: usize, message: &ServerMessage) {
+        let bytes = encode_server(message);
+        self.send_bytes(index, &bytes, false);
+    }
+
+    /// Sends `bytes` to a peer. Snapshots and frames, which the next ones soon replace, are
+    /// `droppable` when the peer is behind.
+    fn send_bytes(&mut self, index: usize, bytes: &[u8], droppable: bool) {
+        let peer = &mut self.peers[index];
+        match (&self.listener, &mut peer.link) {
+            (Listener::Udp(socket), PeerLink::Udp(address)) => {
+                // A lost datagram is as good as a late one
+                let _ = self.outbox.send(socket, Some(*address), bytes);
+            }
+            (_, PeerLink::Tcp(framed)) => {
+                if droppable && framed.backlog() > MAX_BACKLOG {
+                    return;
+                }
+                framed.send(bytes);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A client's side: asks the host for a player, sends it inputs and keeps the latest snapshot.
+pub struct NetClient {
+    link: ClientLink,
+    outbox: Outbox,
+    /// Player the host gave this client, once it answered.
+    pub player: Option<usize>,
+    /// What the host sends, as it told when answering.
+    pub mode: NetMode,
+    /// The host had no player left to give.
+    pub refused: bool,
+    /// Round of the set this client is showing, see `MatchInfo.round`.
+    pub round: Option<u32>,
+    info: Option<MatchInfo>,
+    latest: Option<Snapshot>,
+    /// Inputs the host sent for the set being shown, since the last `take_frames`.
+    frames: Vec<Frames>,
+    sequence: u64,
+    last_join: Instant,
+    last_heard: Instant,
+}
+
+enum ClientLink {
+    Udp(UdpSocket),
+    Tcp(Framed),
+}
+
+impl NetClient {
+    pub fn connect(net: &ParametersNet) -> io::Result<Self> {
+        let host: SocketAddr = net
+            .address
+            .parse()
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "not an address and port"))?;
+        let link = match net.transport {
+            Transport::Udp => {
+                let local = if host.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(host)?;
+                socket.set_nonblocking(true)?;
+                ClientLink::Udp(socket)
+            }
+            Transport::Tcp => ClientLink::Tcp(Framed::new(TcpStream::connect_timeout(
+                &host,
+                CONNECT_TIMEOUT,
+            )?)?),
+        };
+        let mut client = Self {
+            link,
+            outbox: Outbox::new(net.conditions),
+            player: None,
+            mode: NetMode::default(),
+            refused: false,
+            round: None,
+            info: None,
+            latest: None,
+            frames: Vec::new(),
+            sequence: 0,
+            last_join: Instant::now(),
+            last_heard: Instant::now(),
+        };
+        client.send(&ClientMessage::Join)?;
+        Ok(client)
+    }
+
+    /// Reads everything the host sent since the last call. Fails once the connection is lost.
+    pub fn poll(&mut self) -> io::Result<()> {
+        let messages = match &mut self.link {
+            ClientLink::Udp(socket) => {
+                self.outbox.flush(socket);
+                let mut messages = Vec::new();
+                let mut buffer = vec![0; MAX_MESSAGE];
+                loop {
+                    match socket.recv(&mut buffer) {
+                        Ok(length) => messages.extend(decode_server(&buffer[..length])),
+                        Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                        // The host isn't up yet; keep asking
+                        Err(error) if error.kind() == ErrorKind::ConnectionRefused => break,
+                        Err(error) => return Err(error),
+                    }
+                }
+                messages
+            }
+            ClientLink::Tcp(framed) => framed
+                .receive()
+                .iter()
+                .filter_map(|frame| decode_server(frame))
+                .collect(),
+        };
+
+        if !messages.is_empty() {
+            self.last_heard = Instant::now();
+        }
+        for message in messages {
+            match message {
+                ServerMessage::Welcome { player, mode } => {
+                    self.player = Some(player);
+                    self.mode = mode;
+                }
+                ServerMessage::Full => self.refused = true,
+                ServerMessage::Snapshot(snapshot) => {
+                    self.update_info(&snapshot.info);
+                    let older = self.latest.as_ref().is_some_and(|latest| {
+                        (snapshot.info.round, snapshot.tick) < (latest.info.round, latest.tick)
+                    });
+                    if !older {
+                        self.latest = Some(snapshot);
+                    }
+                }
+                ServerMessage::Frames { info, frames } => {
+                    self.update_info(&info);
+                    if self.round == Some(info.round) {
+                        self.frames.push(frames);
+                    }
+                }
+            }
+        }
+
+        if self.player.is_none() && !self.refused && self.last_join.elapsed() >= JOIN_RETRY {
+            self.last_join = Instant::now();
+            self.send(&ClientMessage::Join)?;
+        }
+        if self.player.is_some() && self.last_heard.elapsed() > CLIENT_TIMEOUT {
+            return Err(io::Error::new(ErrorKind::TimedOut, "the host went quiet"));
+        }
+        if matches!(&self.link, ClientLink::Tcp(framed) if framed.closed) && !self.refused {
+            return Err(io::Error::new(
+                ErrorKind::ConnectionReset,
+                "the host closed the connection",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Keeps `info` unless older than what the host already said.
+    fn update_info(&mut self, info: &MatchInfo) {
+        let known = self.info.as_ref().map(|known| known.round);
+        if known <= Some(info.round) {
+            self.info = Some(info.clone());
+        }
+    }
+
+    /// Where the match stands, as of the latest message of the host.
+    pub fn info(&self) -> Option<&MatchInfo> {
+        self.info.as_ref()
+    }
+
+    /// The newest snapshot received since the last call, if any.
+    pub fn take_snapshot(&mut self) -> Option<Snapshot> {
+        self.latest.take()
+    }
+
+    /// The inputs the host sent for the set being shown since the last call.
+    pub fn take_frames(&mut self) -> Vec<Frames> {
+        std::mem::take(&mut self.frames)
+    }
+
+    /// Sends the host the inputs of the client's player it lacks, in `NetMode::Rollback`. Sent
+    /// every step, with no inputs between sets, which also tells the host the client is still
+    /// there.
+    pub fn send_frames(&mut self, rollback: Option<&Rollback>) -> io::Result<()> {
+        let frames = match (rollback, self.player) {
+            (Some(rollback), Some(player)) => Frames {
+                ack: rollback.confirmed(),
+                players: rollback
+                    .frames_from(player, rollback.kept_from())
+                    .into_iter()
+                    .collect(),
+                checksum: rollback.latest_checksum(),
+            },
+            _ => Frames::default(),
+        };
+        self.send(&ClientMessage::Frames {
+            round: self.round.unwrap_or(0),
+            frames,
+        })
+    }
+
+    /// Sends what the client's controls ask for. Sent every step, even with nothing pressed,
+    /// which also tells the host the client is still there.
+    pub fn send_input(&mut self, input: PlayerInput) -> io::Result<()> {
+        self.sequence += 1;
+        self.send(&ClientMessage::Input {
+            sequence: self.sequence,
+            input,
+        })
+    }
+
+    fn send(&mut self, message: &ClientMessage) -> io::Result<()> {
+        let bytes = encode_client(message);
+        match &mut self.link {
+            ClientLink::Udp(socket) => match self.outbox.send(socket, None, &bytes) {
+                Ok(()) => Ok(()),
+                Err(error) if error.kind() == ErrorKind::ConnectionRefused => Ok(()),
+                Err(error) => Err(error),
+            },
+            ClientLink::Tcp(framed) => {
+                framed.send(&bytes);
+                if framed.closed {
+                    return Err(io::Error::new(ErrorKind::ConnectionReset, "host closed"));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for NetClient {
+    fn drop(&mut self) {
+        // Frees the player right away instead of after `CLIENT_TIMEOUT`, if the host hears it
+        let _ = self.send(&ClientMessage::Leave);
+    }
+}
+
+/// A non-blocking TCP stream cut into length-prefixed frames.
+struct Framed {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    closed: bool,
+}
+
+impl Framed {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+            closed: false,
+        })
+    }
+
+    fn backlog(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    /// Queues a frame, and sends as much of the queue as the stream takes.
+    fn send(&mut self, frame: &[u8]) {
+        self.outgoing
+            .extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        self.outgoing.extend_from_slice(frame);
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Every complete frame received so far, including those sent right before the peer closed
+    /// the stream, which then sets `closed`.
+    fn receive(&mut self) -> Vec<Vec<u8>> {
+        let mut buffer = [0; 4096];
+        while !self.closed {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(read) => self.incoming.extend_from_slice(&buffer[..read]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(_) => self.closed = true,
+            }
+        }
+
+        let mut frames = Vec::new();
+        while self.incoming.len() >= 4 {
+            let length = u32::from_le_bytes([
+                self.incoming[0],
+                self.incoming[1],
+                self.incoming[2],
+                self.incoming[3],
+            ]) as usize;
+            if length > MAX_MESSAGE {
+                self.closed = true;
+                self.incoming.clear();
+                break;
+            }
+            if self.incoming.len() < 4 + length {
+                break;
+            }
+            frames.push(self.incoming[4..4 + length].to_vec());
+            self.incoming.drain(..4 + length);
+        }
+        frames
+    }
+}
+
+/// Datagrams held back or dropped as `LinkConditions` ask.
+///
+/// Which ones is left to `rand::thread_rng`, outside `GameRng` on purpose: the conditions stand
+/// in for the network, which a session played again doesn't repeat either, and drawing from
+/// `GameRng` would make the game's own decisions depend on how many datagrams were sent.
+struct Outbox {
+    conditions: LinkConditions,
+    /// When each one is due, where to, and what.
+    queued: Vec<(Instant, Option<SocketAddr>, Vec<u8>)>,
+}
+
+impl Outbox {
+    fn new(conditions: LinkConditions) -> Self {
+        Self {
+            conditions,
+            queued: Vec::new(),
+        }
+    }
+
+    /// Sends `bytes` to `to`, or where `socket` is connected, now or once due.
+    fn send(&mut self, socket: &UdpSocket, to: Option<SocketAddr>, bytes: &[u8]) -> io::Result<()> {
+        if self.conditions == LinkConditions::default() {
+            return send_datagram(socket, to, bytes);
+        }
+        let mut rng = rand::thread_rng();
+        if rng.gen::<f32>() < self.conditions.loss {
+            return Ok(());
+        }
+        let delay = self.conditions.latency + rng.gen::<f32>() * self.conditions.jitter;
+        let due = Instant::now() + Duration::from_secs_f32(delay.max(0.) / 1000.);
+        self.queued.push((due, to, bytes.to_vec()));
+        self.flush(socket);
+        Ok(())
+    }
+
+    /// Sends the datagrams that are due.
+    fn flush(&mut self, socket: &UdpSocket) {
+        let now = Instant::now();
+        self.queued.retain(|(due, to, bytes)| {
+            if *due > now {
+                return true;
+            }
+            // Lost like any datagram if the peer isn't there
+            let _ = send_datagram(socket, *to, bytes);
+            false
+        });
+    }
+}
+
+fn send_datagram(socket: &UdpSocket, to: Option<SocketAddr>, bytes: &[u8]) -> io::Result<()> {
+    match to {
+        Some(address) => socket.send_to(bytes, address),
+        None => socket.send(bytes),
+    }
+    .map(|_| ())
+}
+
+impl Peer {
+    fn new(link: PeerLink) -> Self {
+        Self {
+            link,
+            player: None,
+            input: PlayerInput::default(),
+            sequence: 0,
+            ack: 0,
+            last_heard: Instant::now(),
+            closed: false,
+        }
+    }
+
+    /// The peer left, was refused or went quiet, as of `now`.
+    fn gone(&self, now: Instant) -> bool {
+        let quiet = now.saturating_duration_since(self.last_heard) > CLIENT_TIMEOUT;
+        let closed = matches!(&self.link, PeerLink::Tcp(framed) if framed.closed);
+        quiet || closed || self.closed
+    }
+}
+
+fn encode_client(message: &ClientMessage) -> Vec<u8> {
+    let mut writer = Writer::new(PROTOCOL_VERSION);
+    match message {
+        ClientMessage::Join => writer.u8(0),
+        ClientMessage::Input { sequence, input } => {
+            writer.u8(1);
+            writer.u64(*sequence);
+            writer.input(input);
+        }
+        ClientMessage::Leave => writer.u8(2),
+        ClientMessage::Frames { round, frames } => {
+            writer.u8(3);
+            writer.u32(*round);
+            writer.frames(frames);
+        }
+    }
+    writer.bytes
+}
+
+fn decode_client(bytes: &[u8]) -> Option<ClientMessage> {
+    let mut reader = Reader::new(bytes, PROTOCOL_VERSION)?;
+    let message = match reader.u8()? {
+        0 => ClientMessage::Join,
+        1 => ClientMessage::Input {
+            sequence: reader.u64()?,
+            input: reader.input()?,
+        },
+        2 => ClientMessage::Leave,
+        3 => ClientMessage::Frames {
+            round: reader.u32()?,
+            frames: reader.frames()?,
+        },
+        _ => return None,
+    };
+    reader.finished().then_some(message)
+}
+
+fn encode_server(message: &ServerMessage) -> Vec<u8> {
+    let mut writer = Writer::new(PROTOCOL_VERSION);
+    match message {
+        ServerMessage::Welcome { player, mode } => {
+            writer.u8(0);
+            writer.u32(*player as u32);
+            writer.u8(match mode {
+                NetMode::Snapshots => 0,
+                NetMode::Rollback => 1,
+            });
+        }
+        ServerMessage::Full => writer.u8(1),
+        ServerMessage::Snapshot(snapshot) => {
+            writer.u8(2);
+            writer.snapshot(snapshot);
+        }
+        ServerMessage::Frames { info, frames } => {
+            writer.u8(3);
+            writer.info(info);
+            writer.frames(frames);
+        }
+    }
+    writer.bytes
+}
+
+fn decode_server(bytes: &[u8]) -> Option<ServerMessage> {
+    let mut reader = Reader::new(bytes, PROTOCOL_VERSION)?;
+    let message = match reader.u8()? {
+        0 => ServerMessage::Welcome {
+            player: reader.u32()? as usize,
+            mode: match reader.u8()? {
+                0 => NetMode::Snapshots,
+                1 => NetMode::Rollback,
+                _ => return None,
+            },
+        },
+        1 => ServerMessage::Full,
+        2 => ServerMessage::Snapshot(reader.snapshot()?),
+        3 => ServerMessage::Frames {
+            info: reader.info()?,
+            frames: reader.frames()?,
+        },
+        _ => return None,
+    };
+    reader.finished().then_some(message)
+}
+
+/// Encodings of the messages.
+impl Writer {
+    fn info(&mut self, info: &MatchInfo) {
+        self.u32(info.round);
+        self.u32(info.level);
+        self.u64(info.seed);
+        self.u32(info.sets_won.len() as u32);
+        for won in &info.sets_won {
+            self.u32(*won);
+        }
+        self.u32(info.sets_played);
+        self.u8(info.over as u8);
+    }
+
+    fn frames(&mut self, frames: &Frames) {
+        self.u64(frames.ack);
+        self.u32(frames.players.len() as u32);
+        for sent in &frames.players {
+            self.u32(sent.player as u32);
+            self.u64(sent.first);
+            self.u32(sent.inputs.len() as u32);
+            for input in &sent.inputs {
+                self.input(input);
+            }
+        }
+        self.option(frames.checksum, |writer, (frame, checksum)| {
+            writer.u64(frame);
+            writer.u64(checksum);
+        });
+    }
+
+    fn snapshot(&mut self, snapshot: &Snapshot) {
+        self.info(&snapshot.info);
+        self.u64(snapshot.tick);
+        self.option(snapshot.countdown, Self::u32);
+        self.u32(snapshot.balls.len() as u32);
+        for ball in &snapshot.balls {
+            self.u64(ball.id);
+            self.vec3(ball.position);
+            self.vec2(ball.velocity);
+            self.option(ball.owner, |writer, owner| writer.u32(owner as u32));
+        }
+        self.u32(snapshot.paddles.len() as u32);
+        for paddle in &snapshot.paddles {
+            self.vec3(paddle.position);
+            self.vec3(paddle.size);
+            self.f32(paddle.tilt);
+            self.u8(paddle.eliminated as u8);
+        }
+        self.u32(snapshot.scores.len() as u32);
+        for score in &snapshot.scores {
+            self.f32(*score);
+        }
+        self.u32(snapshot.conceded.len() as u32);
+        for conceded in &snapshot.conceded {
+            self.u32(*conceded);
+        }
+        self.u32(snapshot.bricks.len() as u32);
+        for (id, hit_points) in &snapshot.bricks {
+            self.u64(*id);
+            self.u32(*hit_points);
+        }
+    }
+}
+
+impl Reader<'_> {
+    fn info(&mut self) -> Option<MatchInfo> {
+        Some(MatchInfo {
+            round: self.u32()?,
+            level: self.u32()?,
+            seed: self.u64()?,
+            sets_won: self.list(Self::u32)?,
+            sets_played: self.u32()?,
+            over: self.bool()?,
+        })
+    }
+
+    fn frames(&mut self) -> Option<Frames> {
+        Some(Frames {
+            ack: self.u64()?,
+            players: self.list(|reader| {
+                Some(PlayerFrames {
+                    player: reader.u32()? as usize,
+                    first: reader.u64()?,
+                    inputs: reader.list(Self::input)?,
+                })
+            })?,
+            checksum: self.option(|reader| Some((reader.u64()?, reader.u64()?)))?,
+        })
+    }
+
+    fn snapshot(&mut self) -> Option<Snapshot> {
+        Some(Snapshot {
+            info: self.info()?,
+            tick: self.u64()?,
+            countdown: self.option(Self::u32)?,
+            balls: self.list(|reader| {
+                Some(BallSnapshot {
+                    id: reader.u64()?,
+                    position: reader.vec3()?,
+                    velocity: reader.vec2()?,
+                    owner: reader.option(|reader| Some(reader.u32()? as usize))?,
+                })
+            })?,
+            paddles: self.list(|reader| {
+                Some(PaddleSnapshot {
+                    position: reader.vec3()?,
+                    size: reader.vec3()?,
+                    tilt: reader.f32()?,
+                    eliminated: reader.bool()?,
+                })
+            })?,
+            scores: self.list(Self::f32)?,
+            conceded: self.list(Self::u32)?,
+            bricks: self.list(|reader| Some((reader.u64()?, reader.u32()?)))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::parameters::Parameters;
+
+    /// How long the loopback exchanges may take before the test gives up.
+    const DEADLINE: Duration = Duration::from_secs(5);
+
+    /// Polls both ends until `done` holds.
+    fn exchange(
+        server: &mut NetServer,
+        client: &mut NetClient,
+        mut done: impl FnMut(&mut NetServer, &mut NetClient) -> bool,
+    ) {
+        let start = Instant::now();
+        while !done(server, client) {
+            assert!(start.elapsed() < DEADLINE, "nothing came through in time");
+            server.poll();
+            client.poll().expect("the client lost the host");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn plays_over_loopback(transport: Transport) {
+        let mut net = ParametersNet {
+            address: "127.0.0.1:0".to_string(),
+            transport,
+            ..ParametersNet::default()
+        };
+        let mut server = NetServer::bind(&net).unwrap();
+        net.address = server.local_address().unwrap().to_string();
+        let mut client = NetClient::connect(&net).unwrap();
+        exchange(&mut server, &mut client, |_, client| {
+            client.player.is_some()
+        });
+        assert_eq!(client.player, Some(1));
+        assert_eq!(client.mode, NetMode::Snapshots);
+
+        let sent = PlayerInput {
+            direction: Vec3::Y,
+            ..PlayerInput::default()
+        };
+        client.send_input(sent).unwrap();
+        let mut inputs = vec![PlayerInput::default(); 2];
+        exchange(&mut server, &mut client, |server, _| {
+            server.apply_inputs(&mut inputs);
+            inputs[1] == sent
+        });
+        assert_eq!(inputs[0], PlayerInput::default());
+
+        let parameters = Parameters::default();
+        let mut simulation = Simulation::new(&parameters, &parameters.levels[0], 7);
+        server.start_round(0, 7);
+        simulation.step(&inputs);
+        let match_state = MatchState::new(2);
+        server.broadcast(Some(&simulation), None, &match_state, false);
+        let mut received = None;
+        exchange(&mut server, &mut client, |_, client| {
+            received = client.take_snapshot();
+            received.is_some()
+        });
+        let snapshot = received.unwrap();
+        assert_eq!(snapshot.info.round, 1);
+        assert_eq!(snapshot.info.seed, 7);
+        assert_eq!(
+            snapshot,
+            Snapshot::capture(&simulation, snapshot.info.clone())
+        );
+        assert_eq!(client.info(), Some(&snapshot.info));
+    }
+
+    #[test]
+    fn plays_over_udp_loopback() {
+        plays_over_loopback(Transport::Udp);
+    }
+
+    #[test]
+    fn plays_over_tcp_loopback() {
+        plays_over_loopback(Transport::Tcp);
+    }
+}
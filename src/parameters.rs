@@ -1,154 +1,905 @@
-use decorum::R32;
+//! Checks on everything read from the parameters file, and the per-paddle parameters used to
+//! build the default level.
+//!
+//! Nothing in here panics on a bad file: every problem found is collected, with the TOML path
+//! and line it comes from, so they can all be reported at once.
+
+use std::net::SocketAddr;
+use std::{error::Error, fmt};
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize as DeserializeDerive, Serialize, Serializer};
+use toml::Spanned;
+
+use crate::arena::{ArenaSpec, ARENA_SIDES, FREE_FOR_ALL_PLAYERS};
+use crate::net::{LinkConditions, Transport};
+use crate::types::components::{Paddle, Wall};
+use crate::types::parameters::{
+    Arena, Effect, Level, NetRole, Parameters, ParametersNet, ParametersPowerUps, PowerUpEffect,
+    WinCondition,
+};
 
 const MIN_RGBA_VALUE: f32 = 0.;
 const MAX_RGBA_VALUE: f32 = 1.;
 
-enum VecTypes {
-    VecValue(Vec<R32>),
-    VecVecValue2Tuple(Vec<Vec<(Value, Value)>>),
-    VecValue4Tuple(Vec<(Value, Value, Value, Value)>),
+/// How close two wall ends must be to count as the same corner.
+const WALL_END_TOLERANCE: f32 = 0.01;
+
+/// A finite `f32` that can be compared and hashed, for the numbers of levels and paddles.
+///
+/// It wraps decorum's `R32`, which serde writes as a struct of its fields, so the parameters
+/// and level files could hold neither it nor anything made of it. This one is read and written
+/// as a plain number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct R32(decorum::R32);
+
+impl R32 {
+    pub fn into_inner(self) -> f32 {
+        self.0.into_inner()
+    }
 }
 
-#[derive(Clone)]
-enum Value {
-    Finite(f32),
-    NonZero(f32),
+/// Panics on infinities and `NaN`s, like decorum's `R32`.
+impl From<f32> for R32 {
+    fn from(value: f32) -> Self {
+        Self(decorum::R32::from(value))
+    }
 }
 
-mod defaults {
-    pub mod paddles {
-        use super::super::Value::{self, Finite, NonZero};
-        use tuple_conv::RepeatedTuple as _;
-
-        const N: usize = 2;
-        const WIDTH: Vec<Value> = vec![20.; N];
-        const HEIGHT: Vec<Value> = vec![120.; N];
-        const X: Vec<Value> = (-100., 100.).to_vec();
-        const Y: Vec<Value> = vec![0.; N];
-        const Z: Vec<Value> = vec![0.; N];
-        const BOUNDS: Vec<Vec<(Value, Value)>> = (
-            ((-100., -100.), (-100., 100.)).to_vec(),
-            ((100., -100.), (100., 100.)).to_vec(),
-        )
-            .to_vec();
-        const SPEED: Vec<Value> = vec![500.; N];
-        const COLOR_RGBA: Vec<(Value, Value, Value, Value)> = vec![(0.3, 0.3, 0.7, 1.); N];
-        const WALL_GIVES_POINTS: Vec<usize> = (0, 2).to_vec();
+impl Serialize for R32 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f32(self.into_inner())
+    }
+}
 
-        pub 
+impl<'de> Deserialize<'de> for R32 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = f32::deserialize(deserializer)?;
+        if !value.is_finite() {
+            return Err(de::Error::custom(format!("{value} isn't a finite number")));
+        }
+        Ok(Self::from(value))
     }
 }
 
-// PADDLES: parameters
-//  Bounds: Collection of points, will compute area inside resulting polygon(s). No need to repeat the first point at the end; list will cycle
-impl ParametersPaddles {
-    fn new_verified(s: Self, from_const: bool) -> Self {
-        let unverified_n;
-        let unverified_width;
-        let unverified_height;
-        let unverified_x;
-        let unverified_y;
-        let unverified_z;
-        let unverified_bounds;
-        let unverified_speed;
-        let unverified_color_rgba;
-        let unverified_wall_gives_points;
-
-        if from_const {
-            unverified_n = defaults::paddles::N;
-            unverified_width = defaults::paddles::WIDTH;
-            unverified_height = defaults::paddles::HEIGHT;
-            unverified_x = defaults::paddles::X;
-            unverified_y = defaults::paddles::Y;
-            unverified_z = defaults::paddles::Z;
-            unverified_bounds = defaults::paddles::BOUNDS;
-            unverified_speed = defaults::paddles::SPEED;
-            unverified_color_rgba = defaults::paddles::COLOR_RGBA;
-            unverified_wall_gives_points = defaults::paddles::WALL_GIVES_POINTS;
+/// One thing wrong with a parameters file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigProblem {
+    /// File the problem was found in; empty until the problem is handed to `ConfigError::new`.
+    pub file: String,
+    /// TOML path of the offending value, like `levels[0].walls[2].thickness`.
+    pub path: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            file: String::new(),
+            path: path.into(),
+            line: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "(file)"
         } else {
-            unverified_n = s.n;
-            unverified_width = s.width;
-            unverified_height = s.height;
-            unverified_x = s.x;
-            unverified_y = s.y;
-            unverified_z = s.z;
-            unverified_bounds = s.bounds;
-            unverified_speed = s.speed;
-            unverified_color_rgba = s.color_rgba;
-            unverified_wall_gives_points = s.wall_gives_points;
-        }
-
-        // n is auto-verified, all other vecs must have .len() == n
-        let n = unverified_n;
-
-        assert!(unverified_width.len() == n);
-        assert!(unverified_height.len() == n);
-        assert!(unverified_x.len() == n);
-        assert!(unverified_y.len() == n);
-        assert!(unverified_z.len() == n);
-        assert!(unverified_bounds.len() == n);
-        assert!(unverified_speed.len() == n);
-        assert!(unverified_color_rgba.len() == n);
-        assert!(unverified_wall_gives_points.len() == n);
-
-        fn verify_non_zero(r: f32) -> f32 {
-            assert!(r != 0);
-            r
-        }
-
-        let width = unverified_width
-            .iter()
-            .map(|e| R32::from(verify_non_zero(e).abs()))
-            .collect();
-        let height = unverified_height
-            .iter()
-            .map(|e| R32::from(verify_non_zero(e).abs()))
-            .collect();
-        let x = unverified_x.iter().map(|e| R32::from(e)).collect();
-        let y = unverified_y.iter().map(|e| R32::from(e)).collect();
-        let z = unverified_z.iter().map(|e| R32::from(e)).collect();
-        let bounds = paddles::BOUNDS
-            .iter()
-            .map(|eee| {
-                eee.iter()
-                    .map(|ee| {
-                        ee.to_vec()
-                            .iter()
-                            .map(|e| R32::from(e))
-                            .collect_tuple()
-                            .unwrap()
-                    })
-                    .collect()
-            })
-            .collect();
-        let speed = unverified_speed.iter().map(|e| R32::from(e)).collect();
-        let color_rgba = unverified_color_rgba
+            &self.path
+        };
+        match self.line {
+            Some(line) => write!(f, "[{}] {path} (line {line}): {}", self.file, self.message),
+            None => write!(f, "[{}] {path}: {}", self.file, self.message),
+        }
+    }
+}
+
+/// Every problem found in the parameters files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigError {
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl ConfigError {
+    /// Problems found in `file`.
+    pub fn new(file: &str, mut problems: Vec<ConfigProblem>) -> Self {
+        for problem in &mut problems {
+            if problem.file.is_empty() {
+                problem.file = file.to_string();
+            }
+        }
+        Self { problems }
+    }
+
+    /// For errors that stop the file from being read at all.
+    pub fn single(file: &str, problem: ConfigProblem) -> Self {
+        Self::new(file, vec![problem])
+    }
+
+    /// A file that isn't valid TOML, or doesn't have the shape of `Parameters`.
+    pub fn from_toml(file: &str, text: &str, error: toml::de::Error) -> Self {
+        let line = error.span().map(|span| line_of_offset(text, span.start));
+        Self::single(
+            file,
+            ConfigProblem {
+                line,
+                ..ConfigProblem::new("", error.message())
+            },
+        )
+    }
+
+    /// Fills in the line of every problem from where its path appears in `text`.
+    pub fn locate(mut self, text: &str) -> Self {
+        let lines = KeyLines::new(text);
+        for problem in &mut self.problems {
+            if problem.line.is_none() {
+                problem.line = lines.line_of(&problem.path);
+            }
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn extend(&mut self, other: ConfigError) {
+        self.problems.extend(other.problems);
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} problem(s) in parameters:", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n  {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Semantic checks on parameters that deserialized fine.
+pub fn validate(parameters: &Parameters) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+
+    check_non_zero(&mut problems, "ball.size[0]", parameters.ball.size.x);
+    check_non_zero(&mut problems, "ball.size[1]", parameters.ball.size.y);
+    if parameters.ball.max_balls == 0 {
+        problems.push(ConfigProblem::new(
+            "ball.max_balls",
+            "must be at least 1, or no ball could be served",
+        ));
+    }
+    check_non_zero(&mut problems, "brick.width", parameters.brick.width);
+    check_non_zero(&mut problems, "brick.height", parameters.brick.height);
+    check_non_zero(
+        &mut problems,
+        "scoreboard.font_size",
+        parameters.scoreboard.font_size,
+    );
+
+    check_non_negative(&mut problems, "rally.countdown", parameters.rally.countdown);
+    let cone = parameters.rally.serve_cone;
+    if !(0. ..=360.).contains(&cone) {
+        problems.push(ConfigProblem::new(
+            "rally.serve_cone",
+            format!("is {cone}, outside [0, 360] degrees"),
+        ));
+    }
+
+    let bounce = &parameters.bounce;
+    if !(0. ..90.).contains(&bounce.max_angle) {
+        problems.push(ConfigProblem::new(
+            "bounce.max_angle",
+            format!(
+                "is {}, outside [0, 90) degrees, so balls could go back into the paddle",
+                bounce.max_angle
+            ),
+        ));
+    }
+    check_non_negative(&mut problems, "bounce.spin", bounce.spin);
+    if bounce.speed_up < 1. {
+        problems.push(ConfigProblem::new(
+            "bounce.speed_up",
+            format!(
+                "is {}, but balls may only speed up on hits",
+                bounce.speed_up
+            ),
+        ));
+    }
+
+    problems.extend(validate_power_ups(&parameters.power_ups));
+    problems.extend(validate_net(&parameters.net, parameters.players.len()));
+    if parameters.replay.play.is_some() && parameters.net.role != NetRole::Local {
+        problems.push(ConfigProblem::new(
+            "replay.play",
+            "is set, but replays can't be played back over the network",
+        ));
+    }
+
+    let rules = &parameters.rules;
+    for (path, value) in [
+        ("rules.points_to_win", rules.points_to_win),
+        ("rules.win_by", rules.win_by),
+        ("rules.best_of", rules.best_of),
+    ] {
+        if value == 0 {
+            problems.push(ConfigProblem::new(path, "must be at least 1"));
+        }
+    }
+    if let Some(limit) = rules.time_limit {
+        if limit <= 0. {
+            problems.push(ConfigProblem::new(
+                "rules.time_limit",
+                format!("is {limit}, but must be positive"),
+            ));
+        }
+    }
+
+    let colors = &parameters.colors;
+    for (name, color) in [
+        ("background", colors.background),
+        ("ball", colors.ball),
+        ("brick", colors.brick),
+        ("strongest_brick", colors.strongest_brick),
+        ("pickup", colors.pickup),
+        ("shield", colors.shield),
+        ("text", colors.text),
+        ("score", colors.score),
+    ] {
+        check_rgba(
+            &mut problems,
+            &format!("colors.{name}"),
+            color.as_rgba_f32(),
+        );
+    }
+
+    for (i, player) in parameters.players.iter().enumerate() {
+        for (j, control) in player.controls.iter().enumerate() {
+            problems.extend(validate_effect(
+                &format!("players[{i}].controls[{j}].effect"),
+                &control.effect,
+            ));
+        }
+    }
+
+    match parameters.arena {
+        Arena::Levels => {
+            if parameters.levels.is_empty() {
+                problems.push(ConfigProblem::new(
+                    "levels",
+                    "there must be at least one level",
+                ));
+            }
+            for (i, level) in parameters.levels.iter().enumerate() {
+                problems.extend(validate_level(
+                    &format!("levels[{i}]"),
+                    level,
+                    parameters.players.len(),
+                ));
+            }
+        }
+        Arena::FreeForAll { radius, goal_limit } => {
+            let n_players = parameters.players.len();
+            if !FREE_FOR_ALL_PLAYERS.contains(&n_players) {
+                problems.push(ConfigProblem::new(
+                    "arena.FreeForAll",
+                    format!(
+                        "needs {} to {} players, there are {n_players}",
+                        FREE_FOR_ALL_PLAYERS.start(),
+                        FREE_FOR_ALL_PLAYERS.end()
+                    ),
+                ));
+            }
+            if radius <= 0. {
+                problems.push(ConfigProblem::new(
+                    "arena.FreeForAll.radius",
+                    format!("is {radius}, but must be positive"),
+                ));
+            }
+            if goal_limit == 0 {
+                problems.push(ConfigProblem::new(
+                    "arena.FreeForAll.goal_limit",
+                    "must be at least 1",
+                ));
+            }
+        }
+        Arena::Generated(ref spec) => problems.extend(validate_arena_spec(
+            "arena.Generated",
+            spec,
+            parameters.players.len(),
+        )),
+        Arena::Random { goal_limit } => {
+            if parameters.players.len() < 2 || parameters.players.len() > *ARENA_SIDES.end() {
+                problems.push(ConfigProblem::new(
+                    "arena.Random",
+                    format!(
+                        "needs 2 to {} players, there are {}",
+                        ARENA_SIDES.end(),
+                        parameters.players.len()
+                    ),
+                ));
+            }
+            if goal_limit == Some(0) {
+                problems.push(ConfigProblem::new(
+                    "arena.Random.goal_limit",
+                    "must be at least 1",
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Checks that `spec` describes an arena `n_players` can play in, reporting problems under `path`.
+pub fn validate_arena_spec(path: &str, spec: &ArenaSpec, n_players: usize) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    if !ARENA_SIDES.contains(&spec.sides) {
+        problems.push(ConfigProblem::new(
+            format!("{path}.sides"),
+            format!(
+                "is {}, outside [{}, {}]",
+                spec.sides,
+                ARENA_SIDES.start(),
+                ARENA_SIDES.end()
+            ),
+        ));
+    } else if n_players < 2 || n_players > spec.sides {
+        problems.push(ConfigProblem::new(
+            format!("{path}.sides"),
+            format!(
+                "there are {n_players} players, but an arena of {} sides holds 2 to {0}",
+                spec.sides
+            ),
+        ));
+    }
+    if spec.radius <= 0. {
+        problems.push(ConfigProblem::new(
+            format!("{path}.radius"),
+            format!("is {}, but must be positive", spec.radius),
+        ));
+    }
+    if spec.goal_width <= 0. || spec.goal_width > 1. {
+        problems.push(ConfigProblem::new(
+            format!("{path}.goal_width"),
+            format!("is {}, outside (0, 1]", spec.goal_width),
+        ));
+    }
+    if spec.goal_limit == Some(0) {
+        problems.push(ConfigProblem::new(
+            format!("{path}.goal_limit"),
+            "must be at least 1",
+        ));
+    }
+    for (i, obstacle) in spec.obstacles.iter().enumerate() {
+        let obstacle_path = format!("{path}.obstacles[{i}]");
+        if obstacle.radius <= 0. {
+            problems.push(ConfigProblem::new(
+                format!("{obstacle_path}.radius"),
+                format!("is {}, but must be positive", obstacle.radius),
+            ));
+        }
+        if obstacle.sides < 3 {
+            problems.push(ConfigProblem::new(
+                format!("{obstacle_path}.sides"),
+                format!("is {}, but a polygon needs at least 3", obstacle.sides),
+            ));
+        }
+    }
+    problems
+}
+
+/// Checks the settings an effect carries, reporting problems under `path`.
+fn validate_effect(path: &str, effect: &Effect) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    match *effect {
+        Effect::Dash {
+            distance, cooldown, ..
+        } => {
+            check_non_negative(&mut problems, &format!("{path}.Dash.distance"), distance);
+            check_non_negative(&mut problems, &format!("{path}.Dash.cooldown"), cooldown);
+        }
+        Effect::Rotate { max_angle, .. } => {
+            if !(0. ..=90.).contains(&max_angle) {
+                problems.push(ConfigProblem::new(
+                    format!("{path}.Rotate.max_angle"),
+                    format!("is {max_angle}, outside [0, 90] degrees"),
+                ));
+            }
+        }
+        Effect::Serve { speed } => {
+            check_non_negative(&mut problems, &format!("{path}.Serve.speed"), speed);
+        }
+        Effect::Power { boost, cooldown } => {
+            if boost <= 0. {
+                problems.push(ConfigProblem::new(
+                    format!("{path}.Power.boost"),
+                    format!("is {boost}, but must be positive"),
+                ));
+            }
+            check_non_negative(&mut problems, &format!("{path}.Power.cooldown"), cooldown);
+        }
+        Effect::Move(_) | Effect::Nothing => {}
+    }
+    problems
+}
+
+fn validate_net(net: &ParametersNet, n_players: usize) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    if net.role == NetRole::Local {
+        return problems;
+    }
+
+    if net.address.parse::<SocketAddr>().is_err() {
+        problems.push(ConfigProblem::new(
+            "net.address",
+            format!(
+                "is \"{}\", but must be an IP address and port, like \"127.0.0.1:7777\"",
+                net.address
+            ),
+        ));
+    }
+    if net.role == NetRole::Host && net.remote_players.is_empty() {
+        problems.push(ConfigProblem::new(
+            "net.remote_players",
+            "must list at least one player for clients to play",
+        ));
+    }
+    let conditions = &net.conditions;
+    for (name, milliseconds) in [
+        ("latency", conditions.latency),
+        ("jitter", conditions.jitter),
+    ] {
+        if milliseconds < 0. {
+            problems.push(ConfigProblem::new(
+                format!("net.conditions.{name}"),
+                format!("is {milliseconds}, but must be 0 or more milliseconds"),
+            ));
+        }
+    }
+    if !(0. ..=1.).contains(&conditions.loss) {
+        problems.push(ConfigProblem::new(
+            "net.conditions.loss",
+            format!("is {}, but must be between 0 and 1", conditions.loss),
+        ));
+    }
+    if net.transport == Transport::Tcp && *conditions != LinkConditions::default() {
+        problems.push(ConfigProblem::new(
+            "net.conditions",
+            "only apply to the Udp transport",
+        ));
+    }
+    for (i, player) in net.remote_players.iter().enumerate() {
+        if *player >= n_players {
+            problems.push(ConfigProblem::new(
+                format!("net.remote_players[{i}]"),
+                format!("is {player}, but there are only {n_players} players"),
+            ));
+        } else if net.remote_players[..i].contains(player) {
+            problems.push(ConfigProblem::new(
+                format!("net.remote_players[{i}]"),
+                format!("lists player {player} again"),
+            ));
+        }
+    }
+    problems
+}
+
+fn validate_power_ups(power_ups: &ParametersPowerUps) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    if power_ups.max_pickups == 0 {
+        return problems;
+    }
+
+    if power_ups.interval <= 0. {
+        problems.push(ConfigProblem::new(
+            "power_ups.interval",
+            "must be more than 0 seconds, or set max_pickups to 0",
+        ));
+    }
+    if power_ups.radius <= 0. {
+        problems.push(ConfigProblem::new(
+            "power_ups.radius",
+            "must be more than 0",
+        ));
+    }
+    for (i, kind) in power_ups.kinds.iter().enumerate() {
+        let path = format!("power_ups.kinds[{i}]");
+        check_non_negative(&mut problems, &format!("{path}.duration"), kind.duration);
+        check_non_negative(&mut problems, &format!("{path}.weight"), kind.weight);
+        if let PowerUpEffect::PaddleSize(factor) | PowerUpEffect::BallSpeed(factor) = kind.effect {
+            if factor <= 0. {
+                problems.push(ConfigProblem::new(
+                    format!("{path}.effect"),
+                    format!("has a factor of {factor}, but it must be more than 0"),
+                ));
+            }
+        }
+    }
+    if !power_ups.kinds.iter().any(|kind| kind.weight > 0.) {
+        problems.push(ConfigProblem::new(
+            "power_ups.kinds",
+            "needs a kind with a weight above 0, or set max_pickups to 0",
+        ));
+    }
+    problems
+}
+
+/// Checks a single level, reporting problems under `path`, which is empty for a level file.
+pub fn validate_level(path: &str, level: &Level, n_players: usize) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+
+    if level.paddles.len() != n_players {
+        problems.push(ConfigProblem::new(
+            join_path(path, "paddles"),
+            format!(
+                "has {} paddles but there are {n_players} players",
+                level.paddles.len()
+            ),
+        ));
+    }
+
+    for (i, paddle) in level.paddles.iter().enumerate() {
+        let paddle_path = join_path(path, &format!("paddles[{i}]"));
+        check_non_zero(
+            &mut problems,
+            &format!("{paddle_path}.width"),
+            paddle.width.into_inner(),
+        );
+        check_non_zero(
+            &mut problems,
+            &format!("{paddle_path}.height"),
+            paddle.height.into_inner(),
+        );
+        check_rgba(
+            &mut problems,
+            &format!("{paddle_path}.color_rgba"),
+            rgba_from_r32(paddle.color_rgba),
+        );
+        if !level
+            .walls
             .iter()
-            .map(|ee| {
-                ee.to_vec()
-                    .iter()
-                    .map(|e| e.clamp(MIN_RGBA_VALUE, MAX_RGBA_VALUE))
-                    .collect_tuple()
-                    .unwrap()
-            })
-            .collect();
-        let wall_gives_points = paddles::WALL_GIVES_POINTS;
+            .any(|wall| wall.id == paddle.wall_that_gives_points)
+        {
+            problems.push(ConfigProblem::new(
+                format!("{paddle_path}.wall_that_gives_points"),
+                format!("there is no wall with id {}", paddle.wall_that_gives_points),
+            ));
+        }
+    }
 
-        Self {
-            n,
-            width,
-            height,
-            x,
-            y,
-            z,
-            bounds,
-            speed,
-            color_rgba,
-            wall_gives_points,
+    for (i, wall) in level.walls.iter().enumerate() {
+        let wall_path = join_path(path, &format!("walls[{i}]"));
+        check_non_zero(
+            &mut problems,
+            &format!("{wall_path}.thickness"),
+            wall.thickness(),
+        );
+        check_rgba(
+            &mut problems,
+            &format!("{wall_path}.color"),
+            rgba_from_r32(wall.color),
+        );
+        if level.walls[..i].iter().any(|other| other.id == wall.id) {
+            problems.push(ConfigProblem::new(
+                format!("{wall_path}.id"),
+                format!("another wall already has id {}", wall.id),
+            ));
+        }
+    }
+    problems.extend(check_walls_close(path, &level.walls));
+
+    match (level.win, level.goal_limit) {
+        (WinCondition::LastStanding, None) => problems.push(ConfigProblem::new(
+            join_path(path, "win"),
+            "LastStanding needs a goal_limit, or nobody is ever eliminated",
+        )),
+        (_, Some(0)) => problems.push(ConfigProblem::new(
+            join_path(path, "goal_limit"),
+            "must be at least 1",
+        )),
+        _ => {}
+    }
+
+    for (i, layout) in level.bricks.iter().enumerate() {
+        if layout.hit_points == 0 {
+            problems.push(ConfigProblem::new(
+                join_path(path, &format!("bricks[{i}].hit_points")),
+                "must be at least 1",
+            ));
+        }
+    }
+    if let Some(goal_bricks) = &level.goal_bricks {
+        if goal_bricks.layers == 0 {
+            problems.push(ConfigProblem::new(
+                join_path(path, "goal_bricks.layers"),
+                "must be at least 1, or leave goal_bricks out",
+            ));
+        }
+        if goal_bricks.hit_points == 0 {
+            problems.push(ConfigProblem::new(
+                join_path(path, "goal_bricks.hit_points"),
+                "must be at least 1",
+            ));
+        }
+    }
+
+    if level.win == WinCondition::Score(0) {
+        problems.push(ConfigProblem::new(
+            join_path(path, "win"),
+            "a level won at 0 points would end before it starts",
+        ));
+    }
+
+    problems
+}
+
+/// The walls of a level must enclose it: every end of a wall has to meet an end of another one.
+fn check_walls_close(path: &str, walls: &[Wall]) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    for (i, wall) in walls.iter().enumerate() {
+        for (end_index, end) in [wall.end_a(), wall.end_b()].into_iter().enumerate() {
+            let meets_another = walls.iter().enumerate().any(|(j, other)| {
+                j != i
+                    && (other.end_a().distance(end) <= WALL_END_TOLERANCE
+                        || other.end_b().distance(end) <= WALL_END_TOLERANCE)
+            });
+            if !meets_another {
+                problems.push(ConfigProblem::new(
+                    join_path(path, &format!("walls[{i}].ends[{end_index}]")),
+                    format!(
+                        "end ({}, {}) doesn't meet any other wall, so the level isn't closed",
+                        end.x, end.y
+                    ),
+                ));
+            }
+        }
+    }
+    problems
+}
+
+fn check_non_zero(problems: &mut Vec<ConfigProblem>, path: &str, value: f32) {
+    if value == 0. {
+        problems.push(ConfigProblem::new(path, "must not be zero"));
+    }
+}
+
+fn check_non_negative(problems: &mut Vec<ConfigProblem>, path: &str, value: f32) {
+    if value < 0. {
+        problems.push(ConfigProblem::new(
+            path,
+            format!("is {value}, but must not be negative"),
+        ));
+    }
+}
+
+fn check_rgba(problems: &mut Vec<ConfigProblem>, path: &str, rgba: [f32; 4]) {
+    for (channel, value) in ["red", "green", "blue", "alpha"].iter().zip(rgba) {
+        if !(MIN_RGBA_VALUE..=MAX_RGBA_VALUE).contains(&value) {
+            problems.push(ConfigProblem::new(
+                path,
+                format!("{channel} is {value}, outside [{MIN_RGBA_VALUE}, {MAX_RGBA_VALUE}]"),
+            ));
+        }
+    }
+}
+
+fn rgba_from_r32(rgba: (R32, R32, R32, R32)) -> [f32; 4] {
+    [
+        rgba.0.into_inner(),
+        rgba.1.into_inner(),
+        rgba.2.into_inner(),
+        rgba.3.into_inner(),
+    ]
+}
+
+/// Keys in `file` that `read` has no field for. `read` must have been read from `file`, possibly
+/// merged with others: anything serde skipped while reading it won't come back when writing it
+/// out again.
+pub fn unknown_keys<T: Serialize>(file: &toml::Value, read: &T) -> Vec<ConfigProblem> {
+    let Ok(known) = toml::Value::try_from(read) else {
+        return Vec::new();
+    };
+    let mut problems = Vec::new();
+    collect_unknown_keys(file, &known, "", &mut problems);
+    problems
+}
+
+fn collect_unknown_keys(
+    file: &toml::Value,
+    known: &toml::Value,
+    path: &str,
+    problems: &mut Vec<ConfigProblem>,
+) {
+    match (file, known) {
+        (toml::Value::Table(entries), toml::Value::Table(known)) => {
+            for (key, value) in entries {
+                let key_path = join_path(path, key);
+                match known.get(key) {
+                    Some(known) => collect_unknown_keys(value, known, &key_path, problems),
+                    None => problems.push(ConfigProblem::new(key_path, "unknown key")),
+                }
+            }
+        }
+        (toml::Value::Array(items), toml::Value::Array(known)) => {
+            for (i, (item, known)) in items.iter().zip(known).enumerate() {
+                collect_unknown_keys(item, known, &format!("{path}[{i}]"), problems);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Hands problems found in parameters merged from several files to the file that set the
+/// offending value: the last one mentioning its path, or else the first one. `layers` holds each
+/// file's name and text, in the order they were merged.
+pub fn attribute(problems: Vec<ConfigProblem>, layers: &[(&str, &str)]) -> ConfigError {
+    let lines: Vec<_> = layers.iter().map(|(_, text)| KeyLines::new(text)).collect();
+    let mut error = ConfigError::default();
+    for problem in problems {
+        let layer = (0..layers.len())
+            .rev()
+            .find(|&i| lines[i].mentions(&problem.path))
+            .unwrap_or(0);
+        let (file, text) = layers[layer];
+        error.extend(ConfigError::single(file, problem).locate(text));
+    }
+    error
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+fn line_of_offset(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+/// Line each TOML path starts on, for pointing problems at the right spot in the file.
+struct KeyLines {
+    lines: Vec<(String, usize)>,
+}
+
+impl KeyLines {
+    fn new(text: &str) -> Self {
+        let mut lines = Vec::new();
+        if let Ok(root) = toml::from_str::<Node>(text) {
+            Self::collect(&root, "", text, &mut lines);
+        }
+        Self { lines }
+    }
+
+    fn collect(node: &Node, path: &str, text: &str, lines: &mut Vec<(String, usize)>) {
+        match node {
+            Node::Table(entries) => {
+                for (key, value) in entries {
+                    let key_path = join_path(path, key.get_ref());
+                    lines.push((key_path.clone(), line_of_offset(text, key.span().start)));
+                    Self::collect(value, &key_path, text, lines);
+                }
+            }
+            Node::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    Self::collect(item, &format!("{path}[{i}]"), text, lines);
+                }
+            }
+            Node::Leaf => {}
+        }
+    }
+
+    /// Whether `path` itself, or something inside it, is written in the file.
+    fn mentions(&self, path: &str) -> bool {
+        self.lines.iter().any(|(key, _)| {
+            key == path
+                || key.starts_with(&format!("{path}["))
+                || key.starts_with(&format!("{path}."))
+        })
+    }
+
+    /// Line of `path`, or of its closest ancestor present in the file. Array items start on the
+    /// line of their first key.
+    fn line_of(&self, path: &str) -> Option<usize> {
+        let mut path = path;
+        loop {
+            let found = self
+                .lines
+                .iter()
+                .filter(|(key, _)| key == path || key.starts_with(&format!("{path}[")))
+                .map(|(_, line)| *line)
+                .min();
+            if found.is_some() {
+                return found;
+            }
+            path = &path[..path.rfind(['.', '['])?];
+        }
+    }
+}
+
+/// Shape of a TOML document, remembering where each key was written.
+enum Node {
+    Table(Vec<(Spanned<String>, Node)>),
+    Array(Vec<Node>),
+    Leaf,
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NodeVisitor;
+
+        impl<'de> Visitor<'de> for NodeVisitor {
+            type Value = Node;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("any TOML value")
+            }
+            fn visit_bool<E: de::Error>(self, _: bool) -> Result<Node, E> {
+                Ok(Node::Leaf)
+            }
+            fn visit_i64<E: de::Error>(self, _: i64) -> Result<Node, E> {
+                Ok(Node::Leaf)
+            }
+            fn visit_u64<E: de::Error>(self, _: u64) -> Result<Node, E> {
+                Ok(Node::Leaf)
+            }
+            fn visit_f64<E: de::Error>(self, _: f64) -> Result<Node, E> {
+                Ok(Node::Leaf)
+            }
+            fn visit_str<E: de::Error>(self, _: &str) -> Result<Node, E> {
+                Ok(Node::Leaf)
+            }
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+                let mut items = Vec::new();
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(Node::Array(items))
+            }
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Node::Table(entries))
+            }
         }
+
+        deserializer.deserialize_any(NodeVisitor)
     }
 }
 
+mod defaults {
+    pub mod paddles {
+        pub const N: usize = 2;
+        pub const WIDTH: [f32; N] = [20.; N];
+        pub const HEIGHT: [f32; N] = [120.; N];
+        pub const X: [f32; N] = [-100., 100.];
+        pub const Y: [f32; N] = [0.; N];
+        pub const Z: [f32; N] = [0.; N];
+        pub const BOUNDS: [[(f32, f32); 2]; N] = [
+            [(-100., -100.), (-100., 100.)],
+            [(100., -100.), (100., 100.)],
+        ];
+        pub const SPEED: [f32; N] = [500.; N];
+        pub const COLOR_RGBA: [(f32, f32, f32, f32); N] = [(0.3, 0.3, 0.7, 1.); N];
+        pub const WALL_GIVES_POINTS: [usize; N] = [2, 0];
+    }
+}
+
+// PADDLES: parameters
+//  Bounds: Collection of points, will compute area inside resulting polygon(s). No need to repeat the first point at the end; list will cycle
+//          Repeating the first point closes that polygon and starts the next one. One or two points make a rail instead of an area
+#[derive(Debug, Clone, Serialize, DeserializeDerive)]
 pub struct ParametersPaddles {
     pub n: usize,
     pub width: Vec<R32>,
@@ -161,3 +912,281 @@ pub struct ParametersPaddles {
     pub color_rgba: Vec<(R32, R32, R32, R32)>,
     pub wall_gives_points: Vec<usize>,
 }
+
+impl Default for ParametersPaddles {
+    fn default() -> Self {
+        use defaults::paddles;
+
+        let r32 = |values: &[f32]| values.iter().map(|e| R32::from(*e)).collect();
+        Self {
+            n: paddles::N,
+            width: r32(&paddles::WIDTH),
+            height: r32(&paddles::HEIGHT),
+            x: r32(&paddles::X),
+            y: r32(&paddles::Y),
+            z: r32(&paddles::Z),
+            bounds: paddles::BOUNDS
+                .iter()
+                .map(|bounds| {
+                    bounds
+                        .iter()
+                        .map(|(x, y)| (R32::from(*x), R32::from(*y)))
+                        .collect()
+                })
+                .collect(),
+            speed: r32(&paddles::SPEED),
+            color_rgba: paddles::COLOR_RGBA
+                .iter()
+                .map(|(r, g, b, a)| (R32::from(*r), R32::from(*g), R32::from(*b), R32::from(*a)))
+                .collect(),
+            wall_gives_points: paddles::WALL_GIVES_POINTS.to_vec(),
+        }
+    }
+}
+
+impl ParametersPaddles {
+    /// `n` is the reference, every other vector must hold exactly `n` values.
+    pub fn verify(&self, path: &str) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        let lengths = [
+            ("width", self.width.len()),
+            ("height", self.height.len()),
+            ("x", self.x.len()),
+            ("y", self.y.len()),
+            ("z", self.z.len()),
+            ("bounds", self.bounds.len()),
+            ("speed", self.speed.len()),
+            ("color_rgba", self.color_rgba.len()),
+            ("wall_gives_points", self.wall_gives_points.len()),
+        ];
+        for (name, len) in lengths {
+            if len != self.n {
+                problems.push(ConfigProblem::new(
+                    join_path(path, name),
+                    format!("has {len} values but n is {}", self.n),
+                ));
+            }
+        }
+
+        for (name, values) in [("width", &self.width), ("height", &self.height)] {
+            for (i, value) in values.iter().enumerate() {
+                check_non_zero(
+                    &mut problems,
+                    &format!("{}[{i}]", join_path(path, name)),
+                    value.into_inner(),
+                );
+            }
+        }
+        for (i, color) in self.color_rgba.iter().enumerate() {
+            check_rgba(
+                &mut problems,
+                &format!("{}[{i}]", join_path(path, "color_rgba")),
+                rgba_from_r32(*color),
+            );
+        }
+
+        problems
+    }
+
+    /// One `Paddle` per index. Only meaningful once `verify` found no problems.
+    pub fn paddles(&self) -> Vec<Paddle> {
+        (0..self.n)
+            .map(|i| Paddle {
+                width: R32::from(self.width[i].into_inner().abs()),
+                height: R32::from(self.height[i].into_inner().abs()),
+                x: self.x[i],
+                y: self.y[i],
+                z: self.z[i],
+                angle: R32::from(0.),
+                bounds: self.bounds[i].clone(),
+                speed: self.speed[i],
+                color_rgba: self.color_rgba[i],
+                wall_that_gives_points: self.wall_gives_points[i],
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::Difficulty;
+    use crate::cli::Options;
+    use crate::migration::SCHEMA_VERSION;
+    use crate::types::components::Player;
+    use crate::types::parameters::{parameters_from_toml, save_controls, Binding, MyKeyCode};
+
+    #[test]
+    fn default_parameters_survive_a_trip_through_toml() {
+        let defaults = Parameters::default();
+        let text = toml::to_string(&defaults).expect("the defaults can be written");
+        let read: Parameters = toml::from_str(&text).expect("and read back");
+        assert!(validate(&read).is_empty());
+        assert_eq!(read.levels, defaults.levels);
+    }
+
+    #[test]
+    fn numbers_are_plain_and_finite() {
+        #[derive(Debug, DeserializeDerive)]
+        struct Holder {
+            number: R32,
+        }
+
+        assert_eq!(
+            toml::Value::try_from(R32::from(1.5)).unwrap(),
+            toml::Value::Float(1.5)
+        );
+        let read: Holder = toml::from_str("number = 10").unwrap();
+        assert_eq!(read.number, R32::from(10.));
+        assert!(toml::from_str::<Holder>("number = nan").is_err());
+        assert!(toml::from_str::<Holder>("number = inf").is_err());
+    }
+
+    /// A path in the temporary directory for the file `name`, apart from other test runs.
+    fn temp_file(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("pong-{}-{name}.toml", std::process::id()))
+            .display()
+            .to_string()
+    }
+
+    /// Reads `text` as the parameters file, named `name`, over the defaults like the game does,
+    /// and returns the file's path with what was wrong with it.
+    fn read_error(name: &str, text: &str) -> (String, ConfigError) {
+        let path = temp_file(name);
+        std::fs::write(&path, text).unwrap();
+        let options = Options {
+            config: Some(path.clone()),
+            ..Options::default()
+        };
+        let read = parameters_from_toml(&options);
+        std::fs::remove_file(&path).unwrap();
+        match read {
+            Ok(_) => panic!("{name} was read without a problem"),
+            Err(error) => (path, error),
+        }
+    }
+
+    /// The one problem reported at `path`, checking it names `file`.
+    fn problem_at<'a>(error: &'a ConfigError, file: &str, path: &str) -> &'a ConfigProblem {
+        let found: Vec<_> = error
+            .problems
+            .iter()
+            .filter(|problem| problem.path == path)
+            .collect();
+        assert_eq!(found.len(), 1, "no single problem at {path} in {error}");
+        assert_eq!(found[0].file, file);
+        found[0]
+    }
+
+    /// Line of the first line of `text` holding `needle`.
+    fn line_with(text: &str, needle: &str) -> usize {
+        text.lines().position(|line| line.contains(needle)).unwrap() + 1
+    }
+
+    /// A parameters file holding only the first level of the defaults, changed by `edit`.
+    fn level_file(edit: impl FnOnce(&mut Level)) -> String {
+        #[derive(Serialize)]
+        struct File {
+            schema_version: u32,
+            levels: Vec<Level>,
+        }
+
+        let mut level = Parameters::default().levels[0].clone();
+        edit(&mut level);
+        let file = File {
+            schema_version: SCHEMA_VERSION,
+            levels: vec![level],
+        };
+        toml::to_string(&file).unwrap()
+    }
+
+    #[test]
+    fn malformed_files_report_the_line() {
+        let text = "schema_version = 2\n\n[ball\nmax_balls = 2\n";
+        let (file, error) = read_error("malformed", text);
+        let problem = problem_at(&error, &file, "");
+        assert_eq!(problem.line, Some(3));
+    }
+
+    #[test]
+    fn unknown_keys_report_their_path_and_line() {
+        let text = "schema_version = 2\n\n[ball]\nmax_balls = 2\nbounciness = 3\n";
+        let (file, error) = read_error("unknown-key", text);
+        assert_eq!(problem_at(&error, &file, "ball.bounciness").line, Some(5));
+        assert_eq!(error.problems.len(), 1);
+    }
+
+    #[test]
+    fn levels_without_a_paddle_per_player_report_the_paddles() {
+        let text = level_file(|level| {
+            level.paddles.pop();
+        });
+        let (file, error) = read_error("paddle-count", &text);
+        let problem = problem_at(&error, &file, "levels[0].paddles");
+        assert_eq!(problem.line, Some(line_with(&text, "[[levels.paddles]]")));
+        assert!(problem
+            .message
+            .contains("1 paddles but there are 2 players"));
+    }
+
+    #[test]
+    fn open_walls_report_the_loose_end() {
+        // The last wall stops 100 short of where the first one starts
+        let text = level_file(|level| level.walls[3].ends.1 .0 = R32::from(-500.));
+        let (file, error) = read_error("open-wall", &text);
+        let loose = problem_at(&error, &file, "levels[0].walls[3].ends[1]");
+        assert_eq!(loose.line, Some(line_with(&text, "[-500.0, -300.0]")));
+        // The wall it no longer meets is left open too
+        let other = problem_at(&error, &file, "levels[0].walls[0].ends[0]");
+        assert_eq!(other.line, Some(line_with(&text, "[[-600.0, -300.0],")));
+        assert_eq!(error.problems.len(), 2);
+    }
+
+    #[test]
+    fn saving_controls_keeps_the_rest_of_the_file() {
+        let path = temp_file("save-controls");
+        let text = "schema_version = 2\n\n[ball]\nspeed = 123.0\n\n[[players]]\nai = \"Easy\"\n";
+        std::fs::write(&path, text).unwrap();
+        let mut players = Parameters::default().players;
+        players[0].controls[0].input = Binding::Key(MyKeyCode::Up);
+        players[1].controls[1].input = Binding::Key(MyKeyCode::Down);
+
+        save_controls(&path, &players).expect("the controls are saved");
+        let options = Options {
+            config: Some(path.clone()),
+            ..Options::default()
+        };
+        let read = parameters_from_toml(&options);
+        std::fs::remove_file(&path).unwrap();
+        let Ok(read) = read else {
+            panic!("the saved file can't be read");
+        };
+        assert_eq!(read.ball.speed, 123.);
+        assert_eq!(read.players.len(), 2);
+        assert_eq!(read.players[0].ai, Some(Difficulty::Easy));
+        for (read, saved) in read.players.iter().zip(&players) {
+            let inputs = |player: &Player| -> Vec<Binding> {
+                player
+                    .controls
+                    .iter()
+                    .map(|control| control.input.clone())
+                    .collect()
+            };
+            assert_eq!(inputs(read), inputs(saved));
+        }
+    }
+
+    #[test]
+    fn unreadable_files_are_not_overwritten_with_controls() {
+        // A directory can't be read as a file, and mustn't be taken for a missing one
+        let path = std::env::temp_dir().display().to_string();
+        let error = match save_controls(&path, &Parameters::default().players) {
+            Ok(()) => panic!("the controls were saved over a directory"),
+            Err(error) => error,
+        };
+        assert!(problem_at(&error, &path, "")
+            .message
+            .contains("couldn't read file"));
+    }
+}
@@ -0,0 +1,402 @@
+//! Recording matches and playing them back exactly as they went.
+//!
+//! A `Simulation` only depends on the parameters, the level, the seed of its random numbers and
+//! the inputs of each step, so a replay keeps those for every set and playing it back runs the
+//! same steps again. Levels are kept whole, so sets on random arenas, or on level files edited
+//! since, play back as they were.
+//!
+//! Files start with `MAGIC`, followed by the encoding of `codec` with `REPLAY_VERSION`. The
+//! parameters and levels are in it as TOML, and parameters recorded for an older layout are
+//! migrated like the parameters file is.
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::codec::{Reader, Writer};
+use crate::migration::{merge, migrate};
+use crate::parameters::{validate, validate_level, ConfigProblem};
+use crate::simulation::{PlayerInput, Simulation, SimulationEvent, TIMESTEP};
+use crate::types::parameters::{Level, Parameters};
+
+/// First bytes of every replay file.
+const MAGIC: &[u8] = b"PONGREPLAY";
+
+const REPLAY_VERSION: u8 = 1;
+
+/// Playback speeds, in steps per `TIMESTEP`, from slow motion to fast-forward.
+const SPEEDS: [f32; 7] = [0.125, 0.25, 0.5, 1., 2., 4., 8.];
+
+/// One set, as played.
+#[derive(Clone)]
+pub struct RecordedSet {
+    /// Index of the level, like `CurrentLevel`.
+    pub level_index: usize,
+    pub parameters: Parameters,
+    pub level: Level,
+    pub seed: u64,
+    /// Every player's input, step after step.
+    pub inputs: Vec<Vec<PlayerInput>>,
+}
+
+impl RecordedSet {
+    /// The simulation as the set started.
+    pub fn start(&self) -> Simulation {
+        Simulation::new(&self.parameters, &self.level, self.seed)
+    }
+
+    /// Steps the set lasted.
+    pub fn length(&self) -> u64 {
+        self.inputs.len() as u64
+    }
+}
+
+/// The sets of a match, in the order they were played.
+#[derive(Clone, Default)]
+pub struct Replay {
+    pub sets: Vec<RecordedSet>,
+}
+
+impl Replay {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let invalid = |error: toml::ser::Error| io::Error::new(ErrorKind::InvalidData, error);
+        let mut writer = Writer::new(REPLAY_VERSION);
+        writer.u32(self.sets.len() as u32);
+        for set in &self.sets {
+            writer.u32(set.level_index as u32);
+            writer.string(&toml::to_string(&set.parameters).map_err(invalid)?);
+            writer.string(&toml::to_string(&set.level).map_err(invalid)?);
+            writer.u64(set.seed);
+            writer.u32(set.inputs.len() as u32);
+            for inputs in &set.inputs {
+                writer.u32(inputs.len() as u32);
+                for input in inputs {
+                    writer.input(input);
+                }
+            }
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&writer.bytes);
+        fs::write(path, bytes)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+        let bytes = fs::read(path)?;
+        let Some(bytes) = bytes.strip_prefix(MAGIC) else {
+            return Err(invalid("not a replay file".to_string()));
+        };
+        let mut reader = Reader::new(bytes, REPLAY_VERSION)
+            .ok_or_else(|| invalid("recorded by another version of the game".to_string()))?;
+
+        let mut sets = Vec::new();
+        let count = reader
+            .u32()
+            .ok_or_else(|| invalid("truncated".to_string()))?;
+        for i in 0..count {
+            let set =
+                read_set(&mut reader).map_err(|error| invalid(format!("set {i}: {error}")))?;
+            sets.push(set);
+        }
+        if !reader.finished() {
+            return Err(invalid("unexpected data after the last set".to_string()));
+        }
+        Ok(Self { sets })
+    }
+}
+
+fn read_set(reader: &mut Reader) -> Result<RecordedSet, String> {
+    let truncated = || "truncated".to_string();
+    let level_index = reader.u32().ok_or_else(truncated)? as usize;
+    let parameters = reader.string().ok_or_else(truncated)?;
+    let level = reader.string().ok_or_else(truncated)?;
+    let seed = reader.u64().ok_or_else(truncated)?;
+    let inputs = reader
+        .list(|reader| reader.list(Reader::input))
+        .ok_or_else(truncated)?;
+
+    // Over the defaults, so fields added since the recording keep their default
+    let mut merged = toml::Value::try_from(Parameters::default()).map_err(|e| e.to_string())?;
+    let mut recorded: toml::Value = toml::from_str(&parameters).map_err(|e| e.to_string())?;
+    migrate(&mut recorded).map_err(|problems| describe(&problems))?;
+    merge(&mut merged, recorded);
+    let parameters = Parameters::deserialize(merged).map_err(|e| e.to_string())?;
+    let level: Level = toml::from_str(&level).map_err(|e| e.to_string())?;
+
+    // Checked like the files they came from, as the simulation relies on it
+    let mut problems = validate(&parameters);
+    problems.extend(validate_level("level", &level, parameters.players.len()));
+    if !problems.is_empty() {
+        return Err(describe(&problems));
+    }
+
+    Ok(RecordedSet {
+        level_index,
+        parameters,
+        level,
+        seed,
+        inputs,
+    })
+}
+
+fn describe(problems: &[ConfigProblem]) -> String {
+    problems
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Records the sets of a match as they are played. The file is written after each set, so a
+/// match cut short keeps the sets it finished.
+pub struct Recorder {
+    path: PathBuf,
+    replay: Replay,
+}
+
+impl Recorder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            replay: Replay::default(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts recording a set, played with a `Simulation` built from these.
+    pub fn start_set(
+        &mut self,
+        level_index: usize,
+        parameters: &Parameters,
+        level: &Level,
+        seed: u64,
+    ) {
+        self.replay.sets.push(RecordedSet {
+            level_index,
+            parameters: parameters.clone(),
+            level: level.clone(),
+            seed,
+            inputs: Vec::new(),
+        });
+    }
+
+    /// Records the inputs of the next step of the set.
+    pub fn record(&mut self, inputs: Vec<PlayerInput>) {
+        if let Some(set) = self.replay.sets.last_mut() {
+            set.inputs.push(inputs);
+        }
+    }
+
+    /// Writes every set recorded so far.
+    pub fn save(&self) -> io::Result<()> {
+        self.replay.save(&self.path)
+    }
+}
+
+/// Playing a replay back: which set is shown, and how fast it goes.
+pub struct Playback {
+    replay: Replay,
+    set: usize,
+    pub paused: bool,
+    /// Index in `SPEEDS`.
+    speed: usize,
+    /// Steps due, as `speed` adds a part of one every `TIMESTEP`.
+    due: f32,
+}
+
+impl Playback {
+    /// Plays `replay`, which must have a set.
+    pub fn new(replay: Replay) -> Option<Self> {
+        if replay.sets.is_empty() {
+            return None;
+        }
+        Some(Self {
+            replay,
+            set: 0,
+            paused: false,
+            speed: SPEEDS.iter().position(|speed| *speed == 1.).unwrap_or(0),
+            due: 0.,
+        })
+    }
+
+    /// The set shown.
+    pub fn current(&self) -> &RecordedSet {
+        &self.replay.sets[self.set]
+    }
+
+    pub fn set_index(&self) -> usize {
+        self.set
+    }
+
+    pub fn set_count(&self) -> usize {
+        self.replay.sets.len()
+    }
+
+    /// Shows set `set` instead, if there is one. Its level must then be built again.
+    pub fn show_set(&mut self, set: usize) -> bool {
+        if set >= self.replay.sets.len() || set == self.set {
+            return false;
+        }
+        self.set = set;
+        self.due = 0.;
+        true
+    }
+
+    /// Steps per `TIMESTEP`.
+    pub fn speed(&self) -> f32 {
+        SPEEDS[self.speed]
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed + 1).min(SPEEDS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = self.speed.saturating_sub(1);
+    }
+
+    /// Runs the steps of the set due after one more `TIMESTEP` at the current speed, until its
+    /// end. Returns their events.
+    pub fn advance(&mut self, simulation: &mut Simulation) -> Vec<SimulationEvent> {
+        let mut events = Vec::new();
+        if self.paused {
+            return events;
+        }
+        self.due += self.speed();
+        while self.due >= 1. && simulation.tick < self.current().length() {
+            self.due -= 1.;
+            events.extend(simulation.step(&self.current().inputs[simulation.tick as usize]));
+        }
+        if simulation.tick >= self.current().length() {
+            self.due = 0.;
+        }
+        events
+    }
+
+    /// Moves `simulation` by `seconds`, back or forward, within the set. Going back runs the set
+    /// again from its start, as steps can't be undone.
+    pub fn seek(&mut self, simulation: &mut Simulation, seconds: f32) {
+        let steps = (seconds.abs() / TIMESTEP).round() as u64;
+        let target = if seconds < 0. {
+            simulation.tick.saturating_sub(steps)
+        } else {
+            (simulation.tick + steps).min(self.current().length())
+        };
+        if target < simulation.tick {
+            *simulation = self.current().start();
+        }
+        while simulation.tick < target {
+            simulation.step(&self.current().inputs[simulation.tick as usize]);
+        }
+        self.due = 0.;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::{self, AiController, Difficulty};
+    use crate::rollback::checksum;
+
+    /// Longest a recorded set may take before the test gives up on it, in steps.
+    const MAX_SET_TICKS: u64 = 64 * 60 * 10;
+
+    /// A path in the temporary directory for the replay `name`, apart from other test runs.
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pong-{}-{name}.replay", std::process::id()))
+    }
+
+    /// Plays a set of the default game to its end with the computer on every paddle, recording
+    /// it with `recorder`. Returns the simulation as the set ended.
+    fn record_set(recorder: &mut Recorder, seed: u64) -> Simulation {
+        let parameters = Parameters::default();
+        let level = &parameters.levels[0];
+        recorder.start_set(0, &parameters, level, seed);
+        let mut simulation = Simulation::new(&parameters, level, seed);
+        let mut controllers = vec![
+            AiController::new(0, Difficulty::Hard, seed),
+            AiController::new(1, Difficulty::Medium, seed + 1),
+        ];
+        while simulation.outcome().is_none() {
+            assert!(simulation.tick < MAX_SET_TICKS, "the set never ended");
+            let mut inputs = Vec::new();
+            ai::apply(&mut controllers, &simulation, &mut inputs);
+            recorder.record(inputs.clone());
+            simulation.step(&inputs);
+        }
+        simulation
+    }
+
+    /// Loads the replay at `path`, then removes the file.
+    fn load(path: &Path) -> io::Result<Replay> {
+        let replay = Replay::load(path);
+        fs::remove_file(path).unwrap();
+        replay
+    }
+
+    #[test]
+    fn recorded_sets_play_back_to_the_same_end() {
+        let path = temp_file("play-back");
+        let mut recorder = Recorder::new(&path);
+        let ended = [record_set(&mut recorder, 3), record_set(&mut recorder, 4)];
+        recorder.save().unwrap();
+
+        let mut playback = Playback::new(load(&path).unwrap()).expect("the sets were recorded");
+        assert_eq!(playback.set_count(), 2);
+        for (set, ended) in ended.iter().enumerate() {
+            playback.show_set(set);
+            assert_eq!(playback.current().length(), ended.tick);
+            let mut simulation = playback.current().start();
+            while simulation.tick < playback.current().length() {
+                playback.advance(&mut simulation);
+            }
+            assert_eq!(checksum(&simulation), checksum(ended));
+            assert_eq!(simulation.scores, ended.scores);
+            assert_eq!(simulation.outcome(), ended.outcome());
+        }
+    }
+
+    #[test]
+    fn seeking_back_gives_the_state_of_playing_from_the_start() {
+        let path = temp_file("seek");
+        let mut recorder = Recorder::new(&path);
+        record_set(&mut recorder, 5);
+        recorder.save().unwrap();
+        let mut playback = Playback::new(load(&path).unwrap()).unwrap();
+
+        let mut simulation = playback.current().start();
+        playback.seek(&mut simulation, 10.);
+        assert_eq!(simulation.tick, 640);
+        playback.seek(&mut simulation, -4.);
+        assert_eq!(simulation.tick, 384);
+
+        let set = playback.current();
+        let mut from_start = set.start();
+        for inputs in &set.inputs[..384] {
+            from_start.step(inputs);
+        }
+        assert_eq!(checksum(&simulation), checksum(&from_start));
+    }
+
+    #[test]
+    fn replays_with_an_invalid_level_are_refused() {
+        let path = temp_file("invalid");
+        let mut recorder = Recorder::new(&path);
+        record_set(&mut recorder, 6);
+        recorder.replay.sets[0].level.paddles.pop();
+        recorder.save().unwrap();
+
+        let error = match load(&path) {
+            Ok(_) => panic!("a level without a paddle per player was played back"),
+            Err(error) => error,
+        };
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("level.paddles"), "{error}");
+    }
+}
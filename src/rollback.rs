@@ -0,0 +1,518 @@
+//! Rollback: every instance runs the match itself, without waiting for the others' inputs.
+//!
+//! The inputs of players played elsewhere arrive late, so each step predicts them: a player is
+//! taken to keep doing what their last known input asked for. The state before every step that
+//! used a prediction is kept, and once the real input arrives and turns out different, the match
+//! is put back to that state and the steps since are run again with it. A `Simulation` holds the
+//! whole state of a match and is deterministic, so saving a state is cloning it, and instances
+//! given the same inputs end up in the same state.
+//!
+//! Nothing here touches the network: `net` carries the inputs, as `PlayerFrames`, and the
+//! checksums instances compare to notice when they no longer agree.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::codec::Writer;
+use crate::simulation::{PlayerInput, Simulation, SimulationEvent};
+
+/// Steps an instance may run past the last one it knows every input of. Beyond, it waits for
+/// the late inputs, so a rollback never runs more steps than this again.
+pub const MAX_ROLLBACK: u64 = 16;
+
+/// Most inputs of one player sent in a single message.
+const MAX_SENT: usize = 2 * MAX_ROLLBACK as usize;
+
+/// Checksums of confirmed states kept to compare with those of other instances.
+const CHECKSUMS_KEPT: usize = 128;
+
+/// Inputs of one player, for the frames from `first` on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerFrames {
+    pub player: usize,
+    pub first: u64,
+    pub inputs: Vec<PlayerInput>,
+}
+
+/// A match run with rollback. Frames count the steps since the level started.
+pub struct Rollback {
+    /// Players whose inputs this instance gives, by index.
+    local: Vec<bool>,
+    /// Frames run so far, which is the number of the next one.
+    frame: u64,
+    /// States before the frames that may still be run again, oldest first.
+    history: VecDeque<SavedFrame>,
+    /// Inputs known for every player, by frame.
+    inputs: Vec<BTreeMap<u64, PlayerInput>>,
+    /// First frame each player's input isn't known for yet.
+    known_until: Vec<u64>,
+    /// Earliest frame run with a prediction that turned out wrong.
+    resimulate_from: Option<u64>,
+    /// Frame from which inputs must be kept, since another instance hasn't received them yet.
+    keep_from: u64,
+    /// Inputs of the frames confirmed since the last `take_confirmed_inputs`.
+    confirmed_inputs: Vec<Vec<PlayerInput>>,
+    /// Checksums of the states before confirmed frames, newest last.
+    checksums: VecDeque<(u64, u64)>,
+    /// Checksums other instances sent for frames not confirmed here yet.
+    reported: VecDeque<(u64, u64)>,
+    /// First frame found to differ from another instance, until taken.
+    desync: Option<u64>,
+    /// A difference was found already; later ones follow from it.
+    desynced: bool,
+}
+
+struct SavedFrame {
+    frame: u64,
+    state: Simulation,
+    inputs: Vec<PlayerInput>,
+}
+
+impl Rollback {
+    pub fn new(n_players: usize, local_players: &[usize]) -> Self {
+        Self {
+            local: (0..n_players)
+                .map(|player| local_players.contains(&player))
+                .collect(),
+            frame: 0,
+            history: VecDeque::new(),
+            inputs: vec![BTreeMap::new(); n_players],
+            known_until: vec![0; n_players],
+            resimulate_from: None,
+            keep_from: 0,
+            confirmed_inputs: Vec::new(),
+            checksums: VecDeque::new(),
+            reported: VecDeque::new(),
+            desync: None,
+            desynced: false,
+        }
+    }
+
+    pub fn players(&self) -> usize {
+        self.local.len()
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// First frame some player's input isn't known for. The state before it is final.
+    pub fn confirmed(&self) -> u64 {
+        self.known_until.iter().copied().min().unwrap_or(self.frame)
+    }
+
+    /// First frame `player`'s input isn't known for.
+    pub fn known_until(&self, player: usize) -> u64 {
+        self.known_until.get(player).copied().unwrap_or(0)
+    }
+
+    /// The state every instance agrees on: the one before the first frame run with a prediction,
+    /// or `current` if none was.
+    pub fn confirmed_state<'a>(&'a self, current: &'a Simulation) -> &'a Simulation {
+        self.history.front().map_or(current, |saved| &saved.state)
+    }
+
+    /// Runs the next frame of `simulation`, with the inputs of the local players taken from
+    /// `inputs`, after running again the frames late inputs changed. Returns the frame's events,
+    /// or nothing when too far ahead of the other instances, which must catch up first.
+    pub fn advance(
+        &mut self,
+        simulation: &mut Simulation,
+        inputs: &[PlayerInput],
+    ) -> Option<Vec<SimulationEvent>> {
+        if self.frame >= self.confirmed() + MAX_ROLLBACK {
+            return None;
+        }
+
+        for player in 0..self.local.len() {
+            if self.local[player] {
+                let input = inputs.get(player).copied().unwrap_or_default();
+                self.record(player, self.frame, input);
+            }
+        }
+        self.resimulate(simulation);
+
+        let inputs = self.inputs_for(self.frame);
+        self.history.push_back(SavedFrame {
+            frame: self.frame,
+            state: simulation.clone(),
+            inputs: inputs.clone(),
+        });
+        let events = simulation.step(&inputs);
+        self.frame += 1;
+        self.prune();
+        Some(events)
+    }
+
+    /// Takes in inputs another instance sent. Those of local players are ignored, and so are
+    /// those known already.
+    pub fn receive(&mut self, frames: &PlayerFrames) {
+        // Only players played elsewhere
+        if !matches!(self.local.get(frames.player), Some(false)) {
+            return;
+        }
+        for (frame, input) in (frames.first..).zip(&frames.inputs) {
+            self.record(frames.player, frame, *input);
+        }
+    }
+
+    /// The inputs of `player` known from frame `from` on, as many as fit in a message.
+    pub fn frames_from(&self, player: usize, from: u64) -> Option<PlayerFrames> {
+        let known = self.inputs.get(player)?;
+        let mut sent = known
+            .range(from..self.known_until(player))
+            .take(MAX_SENT)
+            .peekable();
+        let first = *sent.peek()?.0;
+        Some(PlayerFrames {
+            player,
+            first,
+            inputs: sent.map(|(_, input)| *input).collect(),
+        })
+    }
+
+    /// Keeps the inputs from `frame` on, for the other instances that haven't received them.
+    pub fn keep_inputs_from(&mut self, frame: u64) {
+        self.keep_from = frame;
+    }
+
+    /// First frame whose inputs are kept for the other instances.
+    pub fn kept_from(&self) -> u64 {
+        self.keep_from
+    }
+
+    /// Gives `player` the default input for every frame from now on, as if local. For players
+    /// whose instance left, so the others don't wait for them.
+    pub fn abandon(&mut self, player: usize) {
+        if !matches!(self.local.get(player), Some(false)) {
+            return;
+        }
+        for frame in self.known_until[player]..self.frame {
+            self.record(player, frame, PlayerInput::default());
+        }
+        self.local[player] = true;
+    }
+
+    /// Every player's inputs for each frame confirmed since the last call, in order. These are
+    /// the inputs every instance ran the frames with in the end, as a replay records them.
+    pub fn take_confirmed_inputs(&mut self) -> Vec<Vec<PlayerInput>> {
+        std::mem::take(&mut self.confirmed_inputs)
+    }
+
+    /// The latest confirmed frame and the checksum of the state before it.
+    pub fn latest_checksum(&self) -> Option<(u64, u64)> {
+        self.checksums.back().copied()
+    }
+
+    /// Compares the checksum another instance had before confirmed frame `frame` with this
+    /// one's, now or once this one confirms it too.
+    pub fn check(&mut self, frame: u64, checksum: u64) {
+        match self.checksums.back() {
+            Some((latest, _)) if frame <= *latest => {
+                if let Some((_, ours)) = self.checksums.iter().find(|(known, _)| *known == frame) {
+                    self.compare(frame, *ours, checksum);
+                }
+            }
+            _ => {
+                let latest = self.reported.back().map(|(latest, _)| *latest);
+                if latest < Some(frame) {
+                    self.reported.push_back((frame, checksum));
+                }
+                if self.reported.len() > CHECKSUMS_KEPT {
+                    self.reported.pop_front();
+                }
+            }
+        }
+    }
+
+    /// The first frame found to differ from another instance, once. Instances that differ
+    /// stay apart, since nothing ever corrects them.
+    pub fn take_desync(&mut self) -> Option<u64> {
+        self.desync.take()
+    }
+
+    fn compare(&mut self, frame: u64, ours: u64, theirs: u64) {
+        if ours != theirs && !self.desynced {
+            self.desynced = true;
+            self.desync = Some(frame);
+        }
+    }
+
+    fn record(&mut self, player: usize, frame: u64, input: PlayerInput) {
+        if frame < self.known_until[player] || self.inputs[player].contains_key(&frame) {
+            return;
+        }
+        self.inputs[player].insert(frame, input);
+        while self.inputs[player].contains_key(&self.known_until[player]) {
+            self.known_until[player] += 1;
+        }
+
+        // A frame already run with another input is run again
+        let used = self
+            .history
+            .iter()
+            .find(|saved| saved.frame == frame)
+            .map(|saved| saved.inputs[player]);
+        if used.is_some_and(|used| used != input) {
+            self.resimulate_from = Some(self.resimulate_from.map_or(frame, |from| from.min(frame)));
+        }
+    }
+
+    /// Every player's input for `frame`: the one known, or else the last one known before.
+    fn inputs_for(&self, frame: u64) -> Vec<PlayerInput> {
+        self.inputs
+            .iter()
+            .map(|known| {
+                known
+                    .range(..=frame)
+                    .next_back()
+                    .map(|(_, input)| *input)
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    fn resimulate(&mut self, simulation: &mut Simulation) {
+        let Some(from) = self.resimulate_from.take() else {
+            return;
+        };
+        let Some(start) = self.history.iter().position(|saved| saved.frame == from) else {
+            return;
+        };
+        *simulation = self.history[start].state.clone();
+        for i in start..self.history.len() {
+            let inputs = self.inputs_for(self.history[i].frame);
+            let saved = &mut self.history[i];
+            saved.state = simulation.clone();
+            // Events already happened on screen; those that no longer do can't be taken back
+            simulation.step(&inputs);
+            saved.inputs = inputs;
+        }
+    }
+
+    /// Forgets the states no rollback can go back to, and the inputs no one needs anymore.
+    fn prune(&mut self) {
+        let confirmed = self.confirmed();
+        while self
+            .history
+            .front()
+            .is_some_and(|saved| saved.frame < confirmed)
+        {
+            let Some(saved) = self.history.pop_front() else {
+                break;
+            };
+            let ours = checksum(&saved.state);
+            while let Some((frame, theirs)) = self.reported.front().copied() {
+                if frame > saved.frame {
+                    break;
+                }
+                self.reported.pop_front();
+                if frame == saved.frame {
+                    self.compare(frame, ours, theirs);
+                }
+            }
+            self.checksums.push_back((saved.frame, ours));
+            if self.checksums.len() > CHECKSUMS_KEPT {
+                self.checksums.pop_front();
+            }
+            self.confirmed_inputs.push(saved.inputs);
+        }
+
+        // The last input before the kept ones is what the predictions repeat
+        let floor = confirmed.min(self.keep_from).saturating_sub(1);
+        for known in &mut self.inputs {
+            *known = known.split_off(&floor);
+        }
+    }
+}
+
+/// A digest of what is drawn of a match: instances that agree on the inputs agree on it. The
+/// state is written as `codec` does and hashed with FNV-1a, so builds and platforms agree too.
+pub fn checksum(simulation: &Simulation) -> u64 {
+    let mut writer = Writer::new(0);
+    writer.u64(simulation.tick);
+    for ball in &simulation.balls {
+        writer.u64(ball.id);
+        writer.vec3(ball.position);
+        writer.vec2(ball.velocity);
+    }
+    for paddle in &simulation.paddles {
+        writer.vec3(paddle.position);
+        writer.f32(paddle.tilt);
+        writer.u8(paddle.eliminated as u8);
+    }
+    for score in &simulation.scores {
+        writer.f32(*score);
+    }
+    for conceded in &simulation.conceded {
+        writer.u32(*conceded);
+    }
+    for brick in &simulation.bricks {
+        writer.u64(brick.id);
+        writer.u32(brick.hit_points);
+    }
+    fnv1a(&writer.bytes)
+}
+
+/// The 64-bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy::math::Vec3;
+
+    use super::*;
+    use crate::net::{Frames, LinkConditions, NetClient, NetMode, NetServer, Transport};
+    use crate::types::parameters::{Parameters, ParametersNet};
+    use crate::types::resources::MatchState;
+
+    /// Frames both instances must confirm.
+    const FRAMES: u64 = 240;
+
+    /// How long the exchange may take before the test gives up.
+    const DEADLINE: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn fnv1a_matches_the_reference_values() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    /// Goes up and down, turning every few frames, so the other instance mispredicts it.
+    fn input(frame: u64, period: u64) -> Vec<PlayerInput> {
+        let direction = if (frame / period).is_multiple_of(2) {
+            Vec3::Y
+        } else {
+            Vec3::NEG_Y
+        };
+        vec![
+            PlayerInput {
+                direction,
+                ..PlayerInput::default()
+            };
+            2
+        ]
+    }
+
+    /// Takes in what the other instance sent, as `receive_frames` does in the game.
+    fn receive(rollback: &mut Rollback, received: Vec<Frames>) {
+        for frames in received {
+            for sent in &frames.players {
+                rollback.receive(sent);
+            }
+            if let Some((frame, checksum)) = frames.checksum {
+                rollback.check(frame, checksum);
+            }
+        }
+    }
+
+    /// Runs the next frame, counting it when late inputs make it run earlier ones again.
+    fn advance(
+        rollback: &mut Rollback,
+        simulation: &mut Simulation,
+        period: u64,
+        resimulations: &mut u32,
+        checksums: &mut BTreeMap<u64, u64>,
+    ) {
+        let resimulates = rollback.resimulate_from.is_some();
+        if rollback
+            .advance(simulation, &input(rollback.frame(), period))
+            .is_some()
+            && resimulates
+        {
+            *resimulations += 1;
+        }
+        checksums.extend(rollback.checksums.iter().copied());
+    }
+
+    #[test]
+    fn peers_over_a_bad_link_end_up_in_the_same_state() {
+        let mut net = ParametersNet {
+            address: "127.0.0.1:0".to_string(),
+            transport: Transport::Udp,
+            mode: NetMode::Rollback,
+            conditions: LinkConditions {
+                latency: 20.,
+                jitter: 15.,
+                loss: 0.1,
+            },
+            ..ParametersNet::default()
+        };
+        let mut server = NetServer::bind(&net).unwrap();
+        net.address = server.local_address().unwrap().to_string();
+        let mut client = NetClient::connect(&net).unwrap();
+
+        let parameters = Parameters::default();
+        let level = &parameters.levels[0];
+        let mut host = (
+            Rollback::new(2, &[0]),
+            Simulation::new(&parameters, level, 3),
+        );
+        let mut guest = (
+            Rollback::new(2, &[1]),
+            Simulation::new(&parameters, level, 3),
+        );
+        server.start_round(0, 3);
+        client.round = Some(1);
+        let match_state = MatchState::new(2);
+
+        let mut resimulations = 0;
+        let mut host_checksums = BTreeMap::new();
+        let mut guest_checksums = BTreeMap::new();
+        let start = Instant::now();
+        while !(host_checksums.contains_key(&FRAMES) && guest_checksums.contains_key(&FRAMES)) {
+            assert!(start.elapsed() < DEADLINE, "the instances never caught up");
+
+            server.poll();
+            let (rollback, simulation) = &mut host;
+            rollback.keep_inputs_from(server.acknowledged().unwrap_or(rollback.frame()));
+            receive(rollback, server.take_frames());
+            advance(
+                rollback,
+                simulation,
+                5,
+                &mut resimulations,
+                &mut host_checksums,
+            );
+            server.broadcast(Some(simulation), Some(rollback), &match_state, false);
+
+            client.poll().expect("the client lost the host");
+            let (rollback, simulation) = &mut guest;
+            let received = client.take_frames();
+            let acknowledged = received.iter().map(|frames| frames.ack).max();
+            if let Some(ack) = acknowledged.filter(|ack| *ack > rollback.kept_from()) {
+                rollback.keep_inputs_from(ack);
+            }
+            receive(rollback, received);
+            advance(
+                rollback,
+                simulation,
+                7,
+                &mut resimulations,
+                &mut guest_checksums,
+            );
+            client.send_frames(Some(rollback)).unwrap();
+
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(resimulations > 0, "no prediction was ever wrong");
+        for frame in 0..=FRAMES {
+            assert_eq!(
+                host_checksums.get(&frame),
+                guest_checksums.get(&frame),
+                "the instances differ before frame {frame}"
+            );
+        }
+        assert_eq!(host.0.take_desync(), None);
+        assert_eq!(guest.0.take_desync(), None);
+    }
+}
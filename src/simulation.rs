@@ -0,0 +1,1662 @@
+//! Headless, deterministic simulation of a match.
+//!
+//! Every rule of the game lives here: moving the paddles, moving the balls, bouncing them off
+//! the colliders and keeping the score. Nothing in this module touches the Bevy `App`, so a
+//! match can be advanced without a window, a GPU or the `DefaultPlugins` stack. The Bevy systems
+//! in `main.rs` only feed inputs in and draw whatever state comes out.
+
+use std::f32::consts::TAU;
+
+use bevy::math::{Vec2, Vec3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::arena;
+use crate::geometry::{closest_point_on_segment, point_enclosed, reflect, Region, Shape};
+use crate::types::components::{Ball, Paddle, Player, Wall};
+use crate::types::parameters::{
+    Binding, Effect, Level, Parameters, ParametersBounce, ParametersPowerUps, ParametersRally,
+    ParametersRules, PowerUp, PowerUpEffect, PowerUpTarget, ServeToward, WinCondition,
+};
+
+/// Length of one simulation step, in seconds.
+///
+/// This matches the default rate of Bevy's `FixedUpdate` schedule (64 Hz), so the windowed game
+/// and a headless run advance in exactly the same increments.
+pub const TIMESTEP: f32 = 1. / 64.;
+
+/// How many times a ball may bounce within a single step before the rest of its motion is dropped.
+/// Only reachable when a ball is wedged between colliders.
+const MAX_BOUNCES_PER_STEP: usize = 8;
+
+/// Gap left between a ball and whatever it bounced off.
+const COLLISION_SKIN: f32 = 0.01;
+
+/// Angle between a ball and the one split off it when a brick breaks, in radians, so the two
+/// don't travel on top of each other.
+const SPLIT_ANGLE: f32 = 0.35;
+
+/// Random spots tried for a new pickup before giving up until the next one is due.
+const PICKUP_ATTEMPTS: usize = 20;
+
+/// How far a stick must be pushed to trigger effects that are either on or off, like a dash.
+const TRIGGER_THRESHOLD: f32 = 0.5;
+
+/// What a single player asks their paddle to do during one step.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PlayerInput {
+    pub direction: Vec3,
+    pub dash: Option<Dash>,
+    /// How fast to tilt the paddle, in radians per second, counterclockwise when positive.
+    pub rotation: f32,
+    /// How far the paddle may be tilted either way, in radians.
+    pub max_angle: f32,
+    /// Whether the serve is held, with the speed to serve at once it's released.
+    pub serve: Option<f32>,
+    pub power: Option<PowerShot>,
+}
+
+/// A dash asked for by an `Effect::Dash`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dash {
+    pub direction: Vec3,
+    pub distance: f32,
+    /// Seconds before the paddle may dash again.
+    pub cooldown: f32,
+}
+
+/// A power shot asked for by an `Effect::Power`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerShot {
+    pub boost: f32,
+    /// Seconds before the paddle may store another one.
+    pub cooldown: f32,
+}
+
+impl PlayerInput {
+    /// Adds up the effects of every control the player is currently holding.
+    pub fn from_effects<'a>(effects: impl IntoIterator<Item = &'a Effect>) -> Self {
+        Self::from_scaled_effects(effects.into_iter().map(|effect| (effect, 1.)))
+    }
+
+    /// Adds up effects triggered only partway, like by a half-pushed stick. Each comes with how
+    /// much it is triggered, from -1 to 1.
+    pub fn from_scaled_effects<'a>(effects: impl IntoIterator<Item = (&'a Effect, f32)>) -> Self {
+        let mut input = Self::default();
+        for (effect, amount) in effects {
+            let triggered = amount.abs() >= TRIGGER_THRESHOLD;
+            match *effect {
+                Effect::Move(direction) => input.direction += direction * amount,
+                Effect::Dash {
+                    direction,
+                    distance,
+                    cooldown,
+                } if triggered => {
+                    input.dash = Some(Dash {
+                        direction: direction * amount.signum(),
+                        distance,
+                        cooldown,
+                    });
+                }
+                Effect::Rotate { speed, max_angle } => {
+                    input.rotation += speed.to_radians() * amount;
+                    input.max_angle = input.max_angle.max(max_angle.to_radians());
+                }
+                Effect::Serve { speed } if triggered => {
+                    input.serve = Some(input.serve.map_or(speed, |held| held.max(speed)));
+                }
+                Effect::Power { boost, cooldown } if triggered => {
+                    input.power = Some(PowerShot { boost, cooldown });
+                }
+                _ => {}
+            }
+        }
+        input
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PaddleState {
+    pub paddle: Paddle,
+    pub position: Vec3,
+    pub size: Vec3,
+    /// Where the paddle's center may go.
+    pub region: Region,
+    /// Tilt from `Effect::Rotate`, in radians, counterclockwise, on top of the paddle's own turn.
+    pub tilt: f32,
+    /// How fast the paddle moved during the last step, dashes included, in units per second.
+    pub velocity: Vec2,
+    /// Seconds before the paddle may dash again.
+    pub dash_cooldown: f32,
+    /// Seconds before the paddle may store another power shot.
+    pub power_cooldown: f32,
+    /// Speed factor given to the next ball the paddle hits.
+    pub power: Option<f32>,
+    /// Speed to serve held balls at, while the serve is held.
+    pub serving: Option<f32>,
+    pub held: Vec<HeldBall>,
+    /// Out of the level after letting in `Level.goal_limit` goals.
+    pub eliminated: bool,
+}
+
+/// A ball stuck to a paddle that holds its serve. Offsets and directions are relative to the
+/// paddle, so the ball turns with it.
+#[derive(Debug, Clone)]
+pub struct HeldBall {
+    pub ball: u64,
+    pub offset: Vec2,
+    pub direction: Vec2,
+    pub speed: f32,
+}
+
+impl PaddleState {
+    fn new(paddle: &Paddle) -> Self {
+        Self {
+            paddle: paddle.clone(),
+            position: paddle.position(),
+            size: paddle.size(),
+            region: paddle.region(),
+            tilt: 0.,
+            velocity: Vec2::ZERO,
+            dash_cooldown: 0.,
+            power_cooldown: 0.,
+            power: None,
+            serving: None,
+            held: Vec::new(),
+            eliminated: false,
+        }
+    }
+
+    pub fn shape(&self) -> Shape {
+        Shape::Box {
+            center: self.position.truncate(),
+            half_size: self.size.truncate() / 2.,
+            angle: self.facing(),
+        }
+    }
+
+    /// Where the paddle faces, in radians: its own turn plus its tilt.
+    pub fn facing(&self) -> f32 {
+        self.paddle.angle() + self.tilt
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BallState {
+    /// Stable identifier, so renderers can match balls across steps.
+    pub id: u64,
+    pub position: Vec3,
+    pub velocity: Vec2,
+    pub size: Vec3,
+    /// Paddle that touched the ball last. It is credited with the goal the ball scores.
+    pub owner: Option<usize>,
+}
+
+impl BallState {
+    pub fn radius(&self) -> f32 {
+        self.size.x / 2.
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BrickState {
+    pub id: u64,
+    pub position: Vec2,
+    pub size: Vec2,
+    /// Turn of the brick, in radians.
+    pub angle: f32,
+    /// Hits left before it breaks.
+    pub hit_points: u32,
+    /// Goal wall the brick guards, for bricks laid by `Level.goal_bricks`.
+    pub guards: Option<usize>,
+}
+
+impl BrickState {
+    pub fn shape(&self) -> Shape {
+        Shape::Box {
+            center: self.position,
+            half_size: self.size / 2.,
+            angle: self.angle,
+        }
+    }
+}
+
+/// A power-up waiting to be picked up by a ball.
+#[derive(Debug, Clone)]
+pub struct PickupState {
+    pub id: u64,
+    pub position: Vec2,
+    pub radius: f32,
+    pub power_up: PowerUp,
+}
+
+/// A power-up in effect.
+#[derive(Debug, Clone)]
+pub struct ActivePowerUp {
+    pub effect: PowerUpEffect,
+    /// Players it applies to.
+    pub players: Vec<usize>,
+    pub ticks_left: u32,
+}
+
+impl ActivePowerUp {
+    pub fn seconds_left(&self) -> f32 {
+        self.ticks_left as f32 * TIMESTEP
+    }
+}
+
+/// What a collider is, so the simulation knows what hitting it means.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColliderKind {
+    Wall(usize),
+    Paddle(usize),
+    Brick(u64),
+}
+
+/// Something that happened during a step, for the renderer (sounds, effects) or a test to react to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimulationEvent {
+    Collision {
+        ball: u64,
+    },
+    Goal {
+        wall: usize,
+        player: usize,
+    },
+    BrickBroken {
+        brick: u64,
+    },
+    BallSpawned {
+        ball: u64,
+    },
+    Eliminated {
+        player: usize,
+    },
+    PowerUp {
+        player: usize,
+        effect: PowerUpEffect,
+    },
+}
+
+/// Where the point cycle stands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rally {
+    /// Balls are in play.
+    Playing,
+    /// No ball is left. The next one is served toward `receiver`, or along
+    /// `Ball.starting_direction` without one, once `ticks_left` runs out.
+    Countdown {
+        ticks_left: u32,
+        receiver: Option<usize>,
+    },
+}
+
+/// How a level ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelOutcome {
+    /// Player with the most points, unless several share the lead.
+    pub winner: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    pub tick: u64,
+    pub ball: Ball,
+    pub walls: Vec<Wall>,
+    pub paddles: Vec<PaddleState>,
+    pub balls: Vec<BallState>,
+    pub bricks: Vec<BrickState>,
+    pub scores: Vec<f32>,
+    /// Goals each player has let in.
+    pub conceded: Vec<u32>,
+    pub goal_limit: Option<u32>,
+    pub win: WinCondition,
+    pub rules: ParametersRules,
+    pub bricks_broken: usize,
+    /// Hit points of the strongest brick the level started with.
+    pub strongest_brick: u32,
+    pub pickups: Vec<PickupState>,
+    pub power_ups: Vec<ActivePowerUp>,
+    pub rally: Rally,
+    rally_parameters: ParametersRally,
+    bounce: ParametersBounce,
+    power_up_parameters: ParametersPowerUps,
+    /// Ticks before the next pickup appears.
+    next_pickup: u32,
+    /// Where balls are served from.
+    spawns: Vec<Vec2>,
+    /// Player who received the last serve.
+    receiver: Option<usize>,
+    /// Player who defended the goal the last point was scored on.
+    last_loser: Option<usize>,
+    /// Goal walls guarded by bricks, and those of them with a brick broken.
+    guarded: Vec<usize>,
+    breached: Vec<usize>,
+    next_id: u64,
+    rng: StdRng,
+}
+
+impl Simulation {
+    pub fn new(parameters: &Parameters, level: &Level, seed: u64) -> Self {
+        let mut simulation = Self {
+            tick: 0,
+            ball: parameters.ball.clone(),
+            walls: level.walls.clone(),
+            paddles: level.paddles.iter().map(PaddleState::new).collect(),
+            balls: Vec::new(),
+            bricks: Vec::new(),
+            // One of each for every paddle, which is every player on a level that passed
+            // `validate_level`
+            scores: vec![0.; level.paddles.len()],
+            conceded: vec![0; level.paddles.len()],
+            goal_limit: level.goal_limit,
+            win: level.win,
+            rules: parameters.rules.clone(),
+            bricks_broken: 0,
+            strongest_brick: 0,
+            pickups: Vec::new(),
+            power_ups: Vec::new(),
+            rally: Rally::Playing,
+            rally_parameters: parameters.rally.clone(),
+            bounce: parameters.bounce.clone(),
+            power_up_parameters: parameters.power_ups.clone(),
+            next_pickup: ticks(parameters.power_ups.interval),
+            spawns: level.ball_spawns(),
+            receiver: None,
+            last_loser: None,
+            guarded: Vec::new(),
+            breached: Vec::new(),
+            next_id: 0,
+            rng: StdRng::seed_from_u64(seed),
+        };
+        let brick_size = parameters.brick.size().truncate();
+        for layout in &level.bricks {
+            for position in layout.positions(brick_size) {
+                simulation.spawn_brick(position, brick_size, 0., layout.hit_points, None);
+            }
+        }
+        if let Some(spec) = &level.goal_bricks {
+            let goals: Vec<usize> = simulation
+                .walls
+                .iter()
+                .map(|wall| wall.id)
+                .filter(|id| simulation.is_goal(*id))
+                .collect();
+            for goal in goals {
+                let (Some(wall), Some(defender)) = (
+                    simulation.walls.iter().find(|wall| wall.id == goal),
+                    simulation.defender(goal),
+                ) else {
+                    continue;
+                };
+                let placed = arena::goal_bricks(
+                    wall,
+                    &simulation.paddles[defender].paddle,
+                    brick_size,
+                    &parameters.misc,
+                    spec,
+                );
+                for brick in placed {
+                    simulation.spawn_brick(
+                        brick.position,
+                        brick_size,
+                        brick.angle,
+                        brick.hit_points,
+                        Some(goal),
+                    );
+                }
+                simulation.guarded.push(goal);
+            }
+        }
+        simulation.strongest_brick = simulation
+            .bricks
+            .iter()
+            .map(|brick| brick.hit_points)
+            .max()
+            .unwrap_or(0);
+
+        simulation.serve(None);
+        simulation
+    }
+
+    /// Reads the controls of each player, using `amount` to ask how much a player's binding is
+    /// triggered, from -1 to 1.
+    pub fn inputs_from_controls<F>(players: &[Player], mut amount: F) -> Vec<PlayerInput>
+    where
+        F: FnMut(usize, &Binding) -> f32,
+    {
+        players
+            .iter()
+            .enumerate()
+            .map(|(i, player)| {
+                PlayerInput::from_scaled_effects(
+                    player
+                        .controls
+                        .iter()
+                        .map(|control| (&control.effect, amount(i, &control.input)))
+                        .filter(|(_, amount)| *amount != 0.),
+                )
+            })
+            .collect()
+    }
+
+    /// Advances the match by one `TIMESTEP`. `inputs` is indexed like the level's paddles;
+    /// missing entries are treated as "no input".
+    pub fn step(&mut self, inputs: &[PlayerInput]) -> Vec<SimulationEvent> {
+        let mut events = Vec::new();
+
+        self.advance_power_ups();
+        self.move_paddles(inputs);
+        self.carry_held_balls();
+        self.move_balls(&mut events);
+        self.collect_pickups(&mut events);
+        self.advance_rally(&mut events);
+
+        self.tick += 1;
+        events
+    }
+
+    /// Whether the level's win condition is met, and who won it if so.
+    pub fn outcome(&self) -> Option<LevelOutcome> {
+        if self.win == WinCondition::LastStanding {
+            let left = self.players_left();
+            if left.len() <= 1 {
+                return Some(LevelOutcome {
+                    winner: left.first().copied(),
+                });
+            }
+        }
+
+        let leader = self.lead().map(|(leader, _)| leader);
+        let over = match self.win {
+            WinCondition::Rules => self.won_at(self.rules.points_to_win),
+            WinCondition::Score(points) => self.won_at(points),
+            WinCondition::BricksCleared => self.bricks_broken > 0 && self.bricks.is_empty(),
+            WinCondition::LastStanding | WinCondition::Endless => false,
+        };
+        // Out of time, the leader wins; while the lead is shared, the next point decides
+        let over = over || (self.time_left() == Some(0.) && leader.is_some());
+        over.then_some(LevelOutcome { winner: leader })
+    }
+
+    /// Players not eliminated yet.
+    pub fn players_left(&self) -> Vec<usize> {
+        (0..self.paddles.len())
+            .filter(|&i| !self.paddles[i].eliminated)
+            .collect()
+    }
+
+    /// Seconds since the level started.
+    pub fn elapsed(&self) -> f32 {
+        self.tick as f32 * TIMESTEP
+    }
+
+    /// Seconds left before the time limit, if there is one.
+    pub fn time_left(&self) -> Option<f32> {
+        let limit = self.rules.time_limit?;
+        Some((limit - self.elapsed()).max(0.))
+    }
+
+    /// Whether time is up with the lead shared, so the next point wins.
+    pub fn sudden_death(&self) -> bool {
+        self.time_left() == Some(0.) && self.lead().is_none()
+    }
+
+    /// The player with the most points, unless several share the lead, and how many points ahead
+    /// of the next best they are.
+    fn lead(&self) -> Option<(usize, f32)> {
+        let (leader, best) = self
+            .scores
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|(_, x), (_, y)| x.total_cmp(y))?;
+        let next_best = (0..self.scores.len())
+            .filter(|&i| i != leader)
+            .map(|i| self.scores[i])
+            .fold(f32::MIN, f32::max);
+        (best > next_best).then_some((leader, best - next_best))
+    }
+
+    /// Whether a player has `points`, at least `win_by` more than everyone else.
+    fn won_at(&self, points: u32) -> bool {
+        self.lead().is_some_and(|(leader, margin)| {
+            self.scores[leader] >= points as f32 && margin >= self.rules.win_by as f32
+        })
+    }
+
+    /// Runs the match for `ticks` steps, asking `inputs` for the players' inputs before each one.
+    pub fn run<F>(&mut self, ticks: u64, mut inputs: F) -> Vec<SimulationEvent>
+    where
+        F: FnMut(&Simulation) -> Vec<PlayerInput>,
+    {
+        let mut events = Vec::new();
+        for _ in 0..ticks {
+            let step_inputs = inputs(self);
+            events.extend(self.step(&step_inputs));
+        }
+        events
+    }
+
+    pub fn spawn_ball(&mut self, position: Vec3, velocity: Vec2, owner: Option<usize>) -> u64 {
+        let id = self.next_id();
+        self.balls.push(BallState {
+            id,
+            position,
+            velocity,
+            size: self.ball.size,
+            owner,
+        });
+        id
+    }
+
+    pub fn spawn_brick(
+        &mut self,
+        position: Vec2,
+        size: Vec2,
+        angle: f32,
+        hit_points: u32,
+        guards: Option<usize>,
+    ) -> u64 {
+        let id = self.next_id();
+        self.bricks.push(BrickState {
+            id,
+            position,
+            size,
+            angle,
+            hit_points: hit_points.max(1),
+            guards,
+        });
+        id
+    }
+
+    /// Puts a new ball into play at one of the level's spawns, heading toward `receiver`'s
+    /// paddle, or along `Ball.starting_direction` without one, give or take half the serve cone.
+    fn serve(&mut self, receiver: Option<usize>) -> u64 {
+        let position = if self.spawns.is_empty() {
+            self.ball.starting_position
+        } else {
+            let spawn = self.spawns[self.rng.gen_range(0..self.spawns.len())];
+            spawn.extend(self.ball.starting_position.z)
+        };
+        let direction = receiver
+            .and_then(|i| self.paddles.get(i))
+            .map(|paddle| (paddle.position - position).truncate().normalize_or_zero())
+            .filter(|direction| *direction != Vec2::ZERO)
+            .unwrap_or(self.ball.starting_direction.normalize_or_zero());
+
+        let half_cone = self.rally_parameters.serve_cone.to_radians() / 2.;
+        let angle = if half_cone > 0. {
+            self.rng.gen_range(-half_cone..=half_cone)
+        } else {
+            0.
+        };
+        let velocity = Vec2::from_angle(angle).rotate(direction) * self.ball.speed;
+
+        self.receiver = receiver;
+        self.spawn_ball(position, velocity, None)
+    }
+
+    /// Starts the countdown once no ball is left in play, and serves when it runs out.
+    fn advance_rally(&mut self, events: &mut Vec<SimulationEvent>) {
+        match self.rally {
+            Rally::Playing if self.balls.is_empty() => {
+                let receiver = match self.rally_parameters.serve_toward {
+                    ServeToward::Loser => {
+                        self.last_loser.and_then(|loser| self.in_play_from(loser))
+                    }
+                    ServeToward::Alternate => match self.receiver {
+                        Some(receiver) => self.in_play_from(receiver + 1),
+                        None => self.in_play_from(0),
+                    },
+                };
+                self.rally = Rally::Countdown {
+                    ticks_left: ticks(self.rally_parameters.countdown),
+                    receiver,
+                };
+            }
+            Rally::Playing => {}
+            Rally::Countdown {
+                ticks_left: 0,
+                receiver,
+            } => {
+                let id = self.serve(receiver);
+                events.push(SimulationEvent::BallSpawned { ball: id });
+                self.rally = Rally::Playing;
+            }
+            Rally::Countdown {
+                ticks_left,
+                receiver,
+            } => {
+                self.rally = Rally::Countdown {
+                    ticks_left: ticks_left - 1,
+                    receiver,
+                };
+            }
+        }
+    }
+
+    /// The first player still in play, counting up from `first` and wrapping around.
+    fn in_play_from(&self, first: usize) -> Option<usize> {
+        let n = self.paddles.len();
+        (0..n)
+            .map(|offset| (first + offset) % n)
+            .find(|&i| !self.paddles[i].eliminated)
+    }
+
+    /// The player defending wall `wall`: the one whose paddle starts closest to it.
+    fn defender(&self, wall: usize) -> Option<usize> {
+        let wall = self.walls.iter().find(|other| other.id == wall)?;
+        let distance = |paddle: &PaddleState| {
+            let start = paddle.paddle.position().truncate();
+            closest_point_on_segment(start, wall.end_a(), wall.end_b()).distance(start)
+        };
+        (0..self.paddles.len())
+            .min_by(|&a, &b| distance(&self.paddles[a]).total_cmp(&distance(&self.paddles[b])))
+    }
+
+    /// Whether a point is scored when a ball reaches wall `wall`. The goals of eliminated or
+    /// shielded players are plain walls, and so are goals guarded by bricks until one of them
+    /// breaks.
+    fn is_goal(&self, wall: usize) -> bool {
+        self.paddles
+            .iter()
+            .any(|paddle| paddle.paddle.wall_that_gives_points == wall)
+            && !self.defender(wall).is_some_and(|defender| {
+                self.paddles[defender].eliminated || self.shielded(defender)
+            })
+            && (!self.guarded.contains(&wall) || self.breached.contains(&wall))
+    }
+
+    /// The goal wall `player` defends, if any.
+    pub fn goal_of(&self, player: usize) -> Option<&Wall> {
+        self.walls.iter().find(|wall| {
+            self.paddles
+                .iter()
+                .any(|paddle| paddle.paddle.wall_that_gives_points == wall.id)
+                && self.defender(wall.id) == Some(player)
+        })
+    }
+
+    /// Whether a shield closes the goal of `player`.
+    pub fn shielded(&self, player: usize) -> bool {
+        self.has_power_up(player, |effect| effect == PowerUpEffect::Shield)
+    }
+
+    fn has_power_up(&self, player: usize, matches: impl Fn(PowerUpEffect) -> bool) -> bool {
+        self.power_ups
+            .iter()
+            .any(|power_up| matches(power_up.effect) && power_up.players.contains(&player))
+    }
+
+    /// How much faster than their velocity the balls move, from the ball speed power-ups.
+    fn ball_speed_factor(&self) -> f32 {
+        self.power_ups
+            .iter()
+            .filter_map(|power_up| match power_up.effect {
+                PowerUpEffect::BallSpeed(factor) => Some(factor),
+                _ => None,
+            })
+            .product()
+    }
+
+    /// Counts down the power-ups in effect and the next pickup, and sizes the paddles after
+    /// the power-ups still in effect.
+    fn advance_power_ups(&mut self) {
+        for power_up in &mut self.power_ups {
+            power_up.ticks_left = power_up.ticks_left.saturating_sub(1);
+        }
+        self.power_ups.retain(|power_up| power_up.ticks_left > 0);
+
+        for i in 0..self.paddles.len() {
+            let factor: f32 = self
+                .power_ups
+                .iter()
+                .filter(|power_up| power_up.players.contains(&i))
+                .filter_map(|power_up| match power_up.effect {
+                    PowerUpEffect::PaddleSize(factor) => Some(factor),
+                    _ => None,
+                })
+                .product();
+            let paddle = &mut self.paddles[i];
+            paddle.size = paddle.paddle.size() * Vec3::new(1., factor, 1.);
+        }
+
+        if self.pickups.len() >= self.power_up_parameters.max_pickups {
+            return;
+        }
+        if self.next_pickup > 0 {
+            self.next_pickup -= 1;
+            return;
+        }
+        self.next_pickup = ticks(self.power_up_parameters.interval);
+        self.spawn_pickup();
+    }
+
+    /// Puts a pickup with a power-up drawn by weight on a free spot inside the walls.
+    fn spawn_pickup(&mut self) {
+        let total: f32 = self
+            .power_up_parameters
+            .kinds
+            .iter()
+            .map(|kind| kind.weight.max(0.))
+            .sum();
+        if total <= 0. {
+            return;
+        }
+        let mut draw = self.rng.gen_range(0. ..total);
+        let Some(power_up) = self
+            .power_up_parameters
+            .kinds
+            .iter()
+            .find(|kind| {
+                draw -= kind.weight.max(0.);
+                draw < 0.
+            })
+            .cloned()
+        else {
+            return;
+        };
+
+        let radius = self.power_up_parameters.radius;
+        let Some(position) = self.free_spot(radius) else {
+            return;
+        };
+        let id = self.next_id();
+        self.pickups.push(PickupState {
+            id,
+            position,
+            radius,
+            power_up,
+        });
+    }
+
+    /// A random point inside the walls where a circle of `radius` touches no collider, ball or
+    /// other pickup.
+    fn free_spot(&mut self, radius: f32) -> Option<Vec2> {
+        let ends: Vec<Vec2> = self
+            .walls
+            .iter()
+            .flat_map(|wall| [wall.end_a(), wall.end_b()])
+            .collect();
+        let min = ends.iter().copied().reduce(Vec2::min)?;
+        let max = ends.iter().copied().reduce(Vec2::max)?;
+        let colliders = self.colliders();
+
+        for _ in 0..PICKUP_ATTEMPTS {
+            let point = Vec2::new(
+                self.rng.gen_range(min.x..=max.x),
+                self.rng.gen_range(min.y..=max.y),
+            );
+            let free = point_enclosed(
+                point,
+                self.walls.iter().map(|wall| (wall.end_a(), wall.end_b())),
+            ) && colliders
+                .iter()
+                .all(|(_, shape)| shape.contact_with_circle(point, radius).is_none())
+                && self
+                    .balls
+                    .iter()
+                    .all(|ball| ball.position.truncate().distance(point) > ball.radius() + radius)
+                && self
+                    .pickups
+                    .iter()
+                    .all(|pickup| pickup.position.distance(point) > pickup.radius + radius);
+            if free {
+                return Some(point);
+            }
+        }
+        None
+    }
+
+    /// Gives every pickup a ball ran into to the player who touched that ball last.
+    fn collect_pickups(&mut self, events: &mut Vec<SimulationEvent>) {
+        let mut taken = Vec::new();
+        for (i, pickup) in self.pickups.iter().enumerate() {
+            let taker = self
+                .balls
+                .iter()
+                .filter(|ball| {
+                    ball.position.truncate().distance(pickup.position)
+                        <= ball.radius() + pickup.radius
+                })
+                .find_map(|ball| ball.owner);
+            if let Some(player) = taker {
+                taken.push((i, player));
+            }
+        }
+
+        for (i, player) in taken.into_iter().rev() {
+            let pickup = self.pickups.remove(i);
+            let effect = pickup.power_up.effect;
+            events.push(SimulationEvent::PowerUp { player, effect });
+            if effect == PowerUpEffect::ExtraBall {
+                if self.balls.len() < self.ball.max_balls {
+                    let velocity = Vec2::from_angle(self.rng.gen_range(0. ..TAU)) * self.ball.speed;
+                    let position = pickup.position.extend(self.ball.starting_position.z);
+                    let id = self.spawn_ball(position, velocity, Some(player));
+                    events.push(SimulationEvent::BallSpawned { ball: id });
+                }
+                continue;
+            }
+            let players = match pickup.power_up.target {
+                PowerUpTarget::Taker => vec![player],
+                PowerUpTarget::Opponents => self
+                    .players_left()
+                    .into_iter()
+                    .filter(|other| *other != player)
+                    .collect(),
+            };
+            self.power_ups.push(ActivePowerUp {
+                effect,
+                players,
+                ticks_left: ticks(pickup.power_up.duration),
+            });
+        }
+    }
+
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn move_paddles(&mut self, inputs: &[PlayerInput]) {
+        let inverted: Vec<bool> = (0..self.paddles.len())
+            .map(|i| self.has_power_up(i, |effect| effect == PowerUpEffect::InvertControls))
+            .collect();
+        for (i, paddle) in self.paddles.iter_mut().enumerate() {
+            paddle.velocity = Vec2::ZERO;
+            if paddle.eliminated {
+                // Lets go of any ball it held
+                paddle.serving = None;
+                continue;
+            }
+            paddle.dash_cooldown = (paddle.dash_cooldown - TIMESTEP).max(0.);
+            paddle.power_cooldown = (paddle.power_cooldown - TIMESTEP).max(0.);
+            let mut input = inputs.get(i).copied().unwrap_or_default();
+            if inverted[i] {
+                input.direction = -input.direction;
+                input.rotation = -input.rotation;
+                if let Some(dash) = &mut input.dash {
+                    dash.direction = -dash.direction;
+                }
+            }
+            paddle.serving = input.serve;
+
+            if let Some(power) = input.power {
+                if paddle.power.is_none() && paddle.power_cooldown == 0. {
+                    paddle.power = Some(power.boost);
+                    paddle.power_cooldown = power.cooldown;
+                }
+            }
+            if input.rotation != 0. {
+                paddle.tilt = (paddle.tilt + input.rotation * TIMESTEP)
+                    .clamp(-input.max_angle, input.max_angle);
+            }
+
+            // Analog controls may ask for less than full speed, but never more
+            let mut delta = input.direction.clamp_length_max(1.) * paddle.paddle.speed() * TIMESTEP;
+            if let Some(dash) = input.dash {
+                if paddle.dash_cooldown == 0. {
+                    delta += dash.direction.normalize_or_zero() * dash.distance;
+                    paddle.dash_cooldown = dash.cooldown;
+                }
+            }
+            // Directions are the paddle's own: "up" is along its height, however it is turned
+            let turn = Vec2::from_angle(paddle.paddle.angle());
+            let delta = turn.rotate(delta.truncate()).extend(delta.z);
+            if delta == Vec3::ZERO {
+                continue;
+            }
+
+            // Keep the paddle inside its bounds, sliding along them when pushed against an edge
+            let position = paddle
+                .region
+                .slide(paddle.position.truncate(), delta.truncate());
+            paddle.velocity = (position - paddle.position.truncate()) / TIMESTEP;
+            paddle.position = position.extend(paddle.position.z + delta.z);
+        }
+    }
+
+    /// Keeps the balls held for a serve stuck to their paddle, and serves them once the paddle
+    /// lets go.
+    fn carry_held_balls(&mut self) {
+        for paddle in &mut self.paddles {
+            let rotation = Vec2::from_angle(paddle.facing());
+            for held in &paddle.held {
+                let Some(ball) = self.balls.iter_mut().find(|ball| ball.id == held.ball) else {
+                    continue;
+                };
+                let position = paddle.position.truncate() + rotation.rotate(held.offset);
+                ball.position = position.extend(ball.position.z);
+                if paddle.serving.is_none() {
+                    ball.velocity = rotation.rotate(held.direction) * held.speed;
+                }
+            }
+            if paddle.serving.is_none() {
+                paddle.held.clear();
+            }
+        }
+    }
+
+    /// Sticks `ball` to paddle `index`, keeping where it touched it and where it was bouncing to.
+    fn hold_ball(&mut self, index: usize, ball: &mut BallState) {
+        let Some(paddle) = self.paddles.get_mut(index) else {
+            return;
+        };
+        let unrotation = Vec2::from_angle(-paddle.facing());
+        let speed = match paddle.serving {
+            Some(speed) if speed > 0. => speed,
+            _ => ball.velocity.length(),
+        };
+        paddle.held.push(HeldBall {
+            ball: ball.id,
+            offset: unrotation.rotate(ball.position.truncate() - paddle.position.truncate()),
+            direction: unrotation.rotate(ball.velocity.normalize_or_zero()),
+            speed,
+        });
+        ball.velocity = Vec2::ZERO;
+    }
+
+    /// Everything a ball can bounce off, tagged with what it is.
+    pub fn colliders(&self) -> Vec<(ColliderKind, Shape)> {
+        let walls = self
+            .walls
+            .iter()
+            .map(|wall| (ColliderKind::Wall(wall.id), wall.shape()));
+        let paddles = self
+            .paddles
+            .iter()
+            .enumerate()
+            .filter(|(_, paddle)| !paddle.eliminated)
+            .map(|(i, paddle)| (ColliderKind::Paddle(i), paddle.shape()));
+        let bricks = self
+            .bricks
+            .iter()
+            .map(|brick| (ColliderKind::Brick(brick.id), brick.shape()));
+        walls.chain(paddles).chain(bricks).collect()
+    }
+
+    /// Moves every ball through its whole step, bouncing off whatever it meets on the way.
+    fn move_balls(&mut self, events: &mut Vec<SimulationEvent>) {
+        let colliders = self.colliders();
+        let mut hits = Hits::default();
+        let held: Vec<u64> = self
+            .paddles
+            .iter()
+            .flat_map(|paddle| paddle.held.iter().map(|held| held.ball))
+            .collect();
+
+        for i in 0..self.balls.len() {
+            if held.contains(&self.balls[i].id) {
+                continue;
+            }
+            let mut ball = self.balls[i].clone();
+            ball.velocity = ball.velocity.clamp_length_max(self.ball.max_speed);
+            self.sweep_ball(&mut ball, &colliders, &mut hits, events);
+            self.resolve_overlaps(&mut ball, &colliders, &mut hits, events);
+            if let Some(&(paddle, _)) = hits.caught.iter().find(|(_, id)| *id == ball.id) {
+                self.hold_ball(paddle, &mut ball);
+            }
+            self.balls[i] = ball;
+        }
+
+        self.apply_hits(hits, events);
+    }
+
+    /// Continuous collision: the ball travels along its velocity until the first collider in the
+    /// way, bounces, and carries on with the time left, so it cannot skip over thin colliders
+    /// however fast it goes.
+    fn sweep_ball(
+        &mut self,
+        ball: &mut BallState,
+        colliders: &[(ColliderKind, Shape)],
+        hits: &mut Hits,
+        events: &mut Vec<SimulationEvent>,
+    ) {
+        let mut remaining = TIMESTEP;
+        let speed = self.ball_speed_factor();
+        for _ in 0..MAX_BOUNCES_PER_STEP {
+            let motion = ball.velocity * speed * remaining;
+            let first_hit = colliders
+                .iter()
+                .filter(|(kind, _)| !hits.is_broken(kind))
+                .filter_map(|(kind, shape)| {
+                    shape
+                        .sweep_circle(ball.position.truncate(), motion, ball.radius())
+                        .map(|hit| (*kind, hit))
+                })
+                .min_by(|(_, x), (_, y)| x.time.total_cmp(&y.time));
+
+            let Some((kind, hit)) = first_hit else {
+                ball.position += motion.extend(0.);
+                return;
+            };
+
+            // Stop just short of the collider, so the next sweep starts from outside it
+            ball.position += (motion * hit.time + hit.normal * COLLISION_SKIN).extend(0.);
+            ball.velocity = reflect(ball.velocity, hit.normal);
+            self.on_hit(kind, ball, hits, events);
+            if hits.scored.contains(&ball.id) {
+                return;
+            }
+            remaining *= 1. - hit.time;
+        }
+    }
+
+    /// Discrete pass for balls that end up inside a collider anyway, e.g. because a paddle moved
+    /// into them: push them out and bounce them if they are heading further in.
+    fn resolve_overlaps(
+        &mut self,
+        ball: &mut BallState,
+        colliders: &[(ColliderKind, Shape)],
+        hits: &mut Hits,
+        events: &mut Vec<SimulationEvent>,
+    ) {
+        for (kind, shape) in colliders {
+            if hits.is_broken(kind) {
+                continue;
+            }
+            let Some(contact) = shape.contact_with_circle(ball.position.truncate(), ball.radius())
+            else {
+                continue;
+            };
+
+            // Push the ball back out, so it doesn't stay stuck inside the collider
+            ball.position += (contact.normal * (contact.depth + COLLISION_SKIN)).extend(0.);
+
+            // only reflect if the ball is moving into the collider
+            if ball.velocity.dot(contact.normal) < 0. {
+                ball.velocity = reflect(ball.velocity, contact.normal);
+                self.on_hit(*kind, ball, hits, events);
+                if hits.scored.contains(&ball.id) {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn on_hit(
+        &mut self,
+        kind: ColliderKind,
+        ball: &mut BallState,
+        hits: &mut Hits,
+        events: &mut Vec<SimulationEvent>,
+    ) {
+        events.push(SimulationEvent::Collision { ball: ball.id });
+        match kind {
+            // Goals take the ball out of play
+            ColliderKind::Wall(id) if self.is_goal(id) => {
+                hits.goals.push((id, ball.owner));
+                hits.scored.push(ball.id);
+            }
+            ColliderKind::Wall(_) => {}
+            // Bricks lose a hit point on collision, and break once they have none left
+            ColliderKind::Brick(id) => {
+                let Some(brick) = self.bricks.iter_mut().find(|brick| brick.id == id) else {
+                    return;
+                };
+                brick.hit_points = brick.hit_points.saturating_sub(1);
+                if brick.hit_points > 0 {
+                    return;
+                }
+                if let Some(goal) = brick.guards {
+                    if !self.breached.contains(&goal) {
+                        self.breached.push(goal);
+                    }
+                }
+                hits.broken_bricks.push(id);
+                events.push(SimulationEvent::BrickBroken { brick: id });
+                let room = self.balls.len() + hits.new_balls.len() < self.ball.max_balls;
+                if room && self.rng.gen::<f32>() < self.ball.probability_to_duplicate {
+                    hits.new_balls.push(BallState {
+                        velocity: Vec2::from_angle(SPLIT_ANGLE).rotate(ball.velocity),
+                        ..ball.clone()
+                    });
+                }
+            }
+            ColliderKind::Paddle(i) => {
+                let Some(paddle) = self.paddles.get_mut(i) else {
+                    return;
+                };
+                ball.owner = Some(i);
+                deflect(ball, paddle, &self.bounce, self.ball.max_speed);
+                if let Some(boost) = paddle.power.take() {
+                    ball.velocity *= boost;
+                }
+                if paddle.serving.is_some() {
+                    hits.caught.push((i, ball.id));
+                }
+            }
+        }
+    }
+
+    /// Counts a goal let in by `player`, eliminating them once they reach the goal limit.
+    fn concede(&mut self, player: usize, events: &mut Vec<SimulationEvent>) {
+        if self.paddles[player].eliminated {
+            return;
+        }
+        let Some(conceded) = self.conceded.get_mut(player) else {
+            return;
+        };
+        *conceded += 1;
+        if self.goal_limit.is_some_and(|limit| *conceded >= limit) {
+            self.paddles[player].eliminated = true;
+            events.push(SimulationEvent::Eliminated { player });
+        }
+    }
+
+    fn apply_hits(&mut self, hits: Hits, events: &mut Vec<SimulationEvent>) {
+        for (wall, owner) in hits.goals {
+            for player in self.scorers(wall, owner) {
+                if let Some(score) = self.scores.get_mut(player) {
+                    *score += 1.;
+                }
+                events.push(SimulationEvent::Goal { wall, player });
+            }
+            self.last_loser = self.defender(wall);
+            if let Some(loser) = self.last_loser {
+                self.concede(loser, events);
+            }
+        }
+        self.balls.retain(|ball| !hits.scored.contains(&ball.id));
+        self.bricks_broken += hits.broken_bricks.len();
+        self.bricks
+            .retain(|brick| !hits.broken_bricks.contains(&brick.id));
+        for ball in hits.new_balls {
+            let id = self.spawn_ball(ball.position, ball.velocity, ball.owner);
+            events.push(SimulationEvent::BallSpawned { ball: id });
+        }
+    }
+
+    /// Players credited with a goal on wall `wall` by a ball last touched by `owner`: the owner,
+    /// unless it let the ball into its own goal or is out, and otherwise the players scoring on
+    /// that wall.
+    fn scorers(&self, wall: usize, owner: Option<usize>) -> Vec<usize> {
+        if let Some(owner) = owner {
+            let own_goal = self.defender(wall) == Some(owner);
+            if !own_goal
+                && self
+                    .paddles
+                    .get(owner)
+                    .is_some_and(|paddle| !paddle.eliminated)
+            {
+                return vec![owner];
+            }
+        }
+        (0..self.paddles.len())
+            .filter(|i| self.paddles[*i].paddle.wall_that_gives_points == wall)
+            .collect()
+    }
+}
+
+/// Number of steps lasting `seconds`.
+fn ticks(seconds: f32) -> u32 {
+    (seconds / TIMESTEP).round() as u32
+}
+
+/// Sends `ball`, which just bounced off `paddle`, away from the paddle's face at an angle growing
+/// with how far from the middle it hit, dragged along by the paddle's motion and a little faster.
+/// Balls bouncing off the ends of the paddle keep their plain reflection.
+fn deflect(ball: &mut BallState, paddle: &PaddleState, bounce: &ParametersBounce, max_speed: f32) {
+    let turn = Vec2::from_angle(paddle.facing());
+    let along = turn.rotate(Vec2::Y);
+    let offset = ball.position.truncate() - paddle.position.truncate();
+    let mut normal = turn.rotate(Vec2::X);
+    if offset.dot(normal) < 0. {
+        normal = -normal;
+    }
+    let half_height = paddle.size.y / 2.;
+    let hit_at = offset.dot(along);
+    if hit_at.abs() > half_height || ball.velocity.dot(normal) <= 0. {
+        return;
+    }
+
+    let max_angle = bounce.max_angle.to_radians();
+    let aim = hit_at / half_height * max_angle;
+    let speed = (ball.velocity.length() * bounce.speed_up).min(max_speed);
+    let velocity = (normal * aim.cos() + along * aim.sin()) * speed
+        + along * paddle.velocity.dot(along) * bounce.spin;
+
+    // Spin may bend the ball further, but never past the largest angle
+    let angle = normal.angle_between(velocity).clamp(-max_angle, max_angle);
+    ball.velocity = Vec2::from_angle(angle).rotate(normal) * velocity.length().min(max_speed);
+}
+
+/// What the balls ran into during a step, applied once every ball has moved.
+#[derive(Default)]
+struct Hits {
+    /// Goal walls reached, with the owner of the ball that reached each.
+    goals: Vec<(usize, Option<usize>)>,
+    broken_bricks: Vec<u64>,
+    /// Balls split off others by breaking bricks, spawned with the same position and owner.
+    new_balls: Vec<BallState>,
+    /// Balls that reached a goal, and leave play.
+    scored: Vec<u64>,
+    /// Balls that hit a paddle holding its serve, with the paddle.
+    caught: Vec<(usize, u64)>,
+}
+
+impl Hits {
+    fn is_broken(&self, kind: &ColliderKind) -> bool {
+        matches!(kind, ColliderKind::Brick(id) if self.broken_bricks.contains(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::{self, AiController, Difficulty};
+    use crate::parameters::R32;
+    use crate::types::resources::MatchState;
+
+    /// Longest a set may take before the test gives up on it, in steps.
+    const MAX_SET_TICKS: u64 = 64 * 60 * 10;
+
+    /// The default game on its first level.
+    fn default_game() -> (Parameters, Level) {
+        let parameters = Parameters::default();
+        let level = parameters.levels[0].clone();
+        (parameters, level)
+    }
+
+    /// Plays a set to its end with the computer on every paddle.
+    fn play_set(parameters: &Parameters, level: &Level, seed: u64) -> LevelOutcome {
+        let mut simulation = Simulation::new(parameters, level, seed);
+        let mut controllers = vec![
+            AiController::new(0, Difficulty::Hard, seed),
+            AiController::new(1, Difficulty::Easy, seed + 1),
+        ];
+        while simulation.tick < MAX_SET_TICKS {
+            let mut inputs = Vec::new();
+            ai::apply(&mut controllers, &simulation, &mut inputs);
+            simulation.step(&inputs);
+            if let Some(outcome) = simulation.outcome() {
+                return outcome;
+            }
+        }
+        panic!("the set wasn't over after {MAX_SET_TICKS} steps");
+    }
+
+    #[test]
+    fn plays_a_whole_match_headless() {
+        let (parameters, level) = default_game();
+        let mut match_state = MatchState::new(parameters.players.len());
+        let mut seed = 0;
+        while !match_state.is_over(parameters.rules.best_of) {
+            let outcome = play_set(&parameters, &level, seed);
+            match_state.record(outcome.winner);
+            seed += 1;
+        }
+        assert!(match_state.sets_played <= parameters.rules.best_of);
+        assert!(match_state.leader().is_some());
+    }
+
+    #[test]
+    fn same_seed_and_inputs_give_the_same_match() {
+        let (parameters, level) = default_game();
+        let up = Effect::Move(Vec3::Y);
+        let down = Effect::Move(Vec3::NEG_Y);
+        // Each paddle goes up and down, out of step with the other
+        let inputs = |tick: u64| {
+            vec![
+                PlayerInput::from_effects([if tick % 90 < 45 { &up } else { &down }]),
+                PlayerInput::from_effects([if tick % 70 < 35 { &down } else { &up }]),
+            ]
+        };
+        let run = || {
+            let mut simulation = Simulation::new(&parameters, &level, 7);
+            let mut events = Vec::new();
+            for tick in 0..64 * 60 {
+                events.extend(simulation.step(&inputs(tick)));
+            }
+            (format!("{simulation:?}"), events)
+        };
+
+        let (first, first_events) = run();
+        let (second, second_events) = run();
+        assert!(!first_events.is_empty());
+        assert_eq!(first, second);
+        assert_eq!(first_events, second_events);
+    }
+
+    #[test]
+    fn scores_and_goals_conceded_are_kept_for_every_paddle() {
+        let (mut parameters, level) = default_game();
+        parameters.players.truncate(1);
+        let simulation = Simulation::new(&parameters, &level, 0);
+        assert_eq!(simulation.scores.len(), level.paddles.len());
+        assert_eq!(simulation.conceded.len(), level.paddles.len());
+    }
+
+    fn wall(id: usize, a: (f32, f32), b: (f32, f32), thickness: f32) -> Wall {
+        let point = |(x, y): (f32, f32)| (R32::from(x), R32::from(y));
+        let gray = R32::from(0.8);
+        Wall {
+            id,
+            ends: (point(a), point(b)),
+            thickness: R32::from(thickness),
+            color: (gray, gray, gray, R32::from(1.)),
+        }
+    }
+
+    /// The default game with `walls` in place of its own, and a single ball of radius 2 at
+    /// `position`, going at `velocity`.
+    fn lone_ball(walls: Vec<Wall>, position: Vec2, velocity: Vec2) -> Simulation {
+        let (parameters, level) = default_game();
+        let mut simulation = Simulation::new(&parameters, &level, 0);
+        simulation.walls = walls;
+        simulation.balls.clear();
+        simulation.ball.size = Vec3::new(4., 4., 0.);
+        simulation.spawn_ball(position.extend(0.), velocity, None);
+        simulation
+    }
+
+    fn collisions(events: &[SimulationEvent]) -> usize {
+        events
+            .iter()
+            .filter(|event| matches!(event, SimulationEvent::Collision { .. }))
+            .count()
+    }
+
+    #[test]
+    fn ball_at_max_speed_bounces_off_a_thin_wall() {
+        let (parameters, _) = default_game();
+        let speed = parameters.ball.max_speed;
+        // 10 units thick, not a goal, between the paddles
+        let walls = vec![wall(10, (0., -50.), (0., 50.), 10.)];
+        let mut simulation = lone_ball(walls, Vec2::new(-12., 0.), Vec2::new(speed, 0.));
+
+        let events = simulation.step(&[]);
+        let ball = &simulation.balls[0];
+        assert_eq!(collisions(&events), 1);
+        assert!(
+            ball.position.x < -7.,
+            "the ball went through to {}",
+            ball.position
+        );
+        assert_eq!(ball.velocity, Vec2::new(-speed, 0.));
+    }
+
+    #[test]
+    fn ball_at_max_speed_bounces_off_a_paddle() {
+        let (parameters, level) = default_game();
+        let speed = parameters.ball.max_speed;
+        // The right paddle, 20 units wide, has its face at x = 90
+        let paddle = &level.paddles[1];
+        assert_eq!(paddle.width.into_inner(), 20.);
+        let mut simulation = lone_ball(level.walls, Vec2::new(84., 0.), Vec2::new(speed, 0.));
+
+        let events = simulation.step(&[]);
+        let ball = &simulation.balls[0];
+        assert_eq!(collisions(&events), 1);
+        assert!(
+            ball.position.x < 88.,
+            "the ball went through to {}",
+            ball.position
+        );
+        assert!(ball.velocity.x < 0.);
+        assert_eq!(ball.owner, Some(1));
+    }
+
+    #[test]
+    fn ball_bounces_twice_in_a_corner_within_one_step() {
+        // Two walls meeting at (20, 20); the ball reaches them when its center is 7 away
+        let walls = vec![
+            wall(10, (20., -50.), (20., 50.), 10.),
+            wall(11, (-50., 20.), (50., 20.), 10.),
+        ];
+        let mut simulation = lone_ball(walls, Vec2::new(5., 0.), Vec2::new(1600., 1200.));
+
+        let events = simulation.step(&[]);
+        let ball = &simulation.balls[0];
+        assert_eq!(collisions(&events), 2);
+        assert!(ball.position.x < 13. && ball.position.y < 13.);
+        assert_eq!(ball.velocity, Vec2::new(-1600., -1200.));
+        // Back out of the corner by what was left of the step after the second bounce
+        assert!((ball.position.x - -4.).abs() < 0.1, "{}", ball.position);
+        assert!((ball.position.y - 7.25).abs() < 0.1, "{}", ball.position);
+    }
+
+    #[test]
+    fn serves_leave_at_ball_speed_toward_the_loser_after_the_countdown() {
+        let (parameters, level) = default_game();
+        let countdown = ticks(parameters.rally.countdown) as usize;
+        let half_cone = parameters.rally.serve_cone / 2.;
+        let mut angles = Vec::new();
+        for seed in 0..20 {
+            let mut simulation = Simulation::new(&parameters, &level, seed);
+            simulation.balls.clear();
+            // Behind the left paddle, into the goal it defends
+            simulation.spawn_ball(Vec3::new(-300., 200., 0.), Vec2::new(-400., 0.), None);
+            (0..100)
+                .find(|_| {
+                    simulation
+                        .step(&[])
+                        .contains(&SimulationEvent::Goal { wall: 0, player: 1 })
+                })
+                .expect("the ball reached the goal");
+            assert!(simulation.balls.is_empty());
+
+            let served = (1..=countdown + 10)
+                .find(|_| {
+                    simulation
+                        .step(&[])
+                        .iter()
+                        .any(|event| matches!(event, SimulationEvent::BallSpawned { .. }))
+                })
+                .expect("a ball was served");
+            assert_eq!(served, countdown + 1);
+            let ball = &simulation.balls[0];
+            assert!((ball.velocity.length() - parameters.ball.speed).abs() < 1e-3);
+
+            // Sent at the paddle of the player who let the goal in, give or take half the cone
+            let toward = (simulation.paddles[0].position - ball.position).truncate();
+            let angle = toward.angle_between(ball.velocity).to_degrees();
+            assert!(angle.abs() <= half_cone + 1e-3, "served {angle}° off");
+            angles.push(angle);
+        }
+        assert!(angles.iter().any(|angle| angle.abs() > half_cone / 2.));
+    }
+
+    /// Scores a point for `player` of the default level, by sending a lone ball into the goal
+    /// across from them.
+    fn score(simulation: &mut Simulation, player: usize) {
+        let (wall, x) = [(2, 1.), (0, -1.)][player];
+        simulation.balls.clear();
+        simulation.spawn_ball(Vec3::new(x * 300., 200., 0.), Vec2::new(x * 800., 0.), None);
+        (0..100)
+            .find(|_| {
+                simulation
+                    .step(&[])
+                    .contains(&SimulationEvent::Goal { wall, player })
+            })
+            .expect("the ball reached the goal");
+    }
+
+    #[test]
+    fn sets_are_won_by_the_margin_in_the_rules() {
+        let (mut parameters, level) = default_game();
+        parameters.rules.points_to_win = 5;
+        parameters.rules.win_by = 2;
+        let mut simulation = Simulation::new(&parameters, &level, 0);
+        for _ in 0..4 {
+            score(&mut simulation, 0);
+            score(&mut simulation, 1);
+        }
+        score(&mut simulation, 0);
+        assert_eq!(simulation.scores, vec![5., 4.]);
+        assert_eq!(simulation.outcome(), None);
+        score(&mut simulation, 1);
+        score(&mut simulation, 1);
+        assert_eq!(simulation.scores, vec![5., 6.]);
+        assert_eq!(simulation.outcome(), None);
+        score(&mut simulation, 1);
+        assert_eq!(simulation.outcome(), Some(LevelOutcome { winner: Some(1) }));
+    }
+
+    #[test]
+    fn the_leader_wins_once_time_runs_out() {
+        let (mut parameters, level) = default_game();
+        parameters.rules.time_limit = Some(1.);
+        let mut simulation = Simulation::new(&parameters, &level, 0);
+        score(&mut simulation, 1);
+        // The countdown outlasts the time left, so nobody scores again
+        while simulation.outcome().is_none() {
+            assert!(simulation.tick < MAX_SET_TICKS, "the set never ended");
+            simulation.step(&[]);
+        }
+        assert_eq!(simulation.elapsed(), 1.);
+        assert_eq!(simulation.time_left(), Some(0.));
+        assert!(!simulation.sudden_death());
+        assert_eq!(simulation.outcome(), Some(LevelOutcome { winner: Some(1) }));
+    }
+
+    #[test]
+    fn a_shared_lead_goes_to_sudden_death_when_time_runs_out() {
+        let (mut parameters, level) = default_game();
+        parameters.rules.time_limit = Some(1.);
+        let mut simulation = Simulation::new(&parameters, &level, 0);
+        score(&mut simulation, 0);
+        score(&mut simulation, 1);
+        while simulation.time_left() != Some(0.) {
+            simulation.step(&[]);
+            assert_eq!(simulation.outcome(), None);
+        }
+        assert!(simulation.sudden_death());
+        simulation.run(64, |_| Vec::new());
+        assert_eq!(simulation.outcome(), None);
+
+        // The next point decides the set
+        score(&mut simulation, 0);
+        assert!(!simulation.sudden_death());
+        assert_eq!(simulation.outcome(), Some(LevelOutcome { winner: Some(0) }));
+    }
+
+    #[test]
+    fn balls_leave_the_paddle_at_an_angle_growing_toward_its_ends() {
+        let (parameters, level) = default_game();
+        let bounce = &parameters.bounce;
+        // The right paddle, 120 units long, has its face at x = 90 and its middle at y = 0
+        let leaving_angle = |y: f32, speed: f32| {
+            let mut simulation =
+                lone_ball(level.walls.clone(), Vec2::new(84., y), Vec2::new(speed, 0.));
+            let events = simulation.step(&[]);
+            assert_eq!(collisions(&events), 1);
+            let velocity = simulation.balls[0].velocity;
+            (
+                Vec2::NEG_X.angle_between(velocity).to_degrees(),
+                velocity.length(),
+            )
+        };
+
+        let (angle, speed) = leaving_angle(0., 400.);
+        assert!(angle.abs() < 1e-3);
+        assert!((speed - 400. * bounce.speed_up).abs() < 1e-3);
+
+        // Half way up the paddle, half the largest angle, turned away from its middle
+        let (angle, _) = leaving_angle(30., 400.);
+        assert!((angle - -bounce.max_angle / 2.).abs() < 0.1, "{angle}");
+        let (angle, _) = leaving_angle(-30., 400.);
+        assert!((angle - bounce.max_angle / 2.).abs() < 0.1, "{angle}");
+
+        // Near the end, close to the largest angle, and never faster than the most a ball goes
+        let (angle, speed) = leaving_angle(-58., parameters.ball.max_speed);
+        assert!(
+            (angle - bounce.max_angle * 58. / 60.).abs() < 0.1,
+            "{angle}"
+        );
+        assert!((speed - parameters.ball.max_speed).abs() < 1e-2);
+    }
+
+    #[test]
+    fn pickups_appear_inside_the_walls_at_every_interval() {
+        let (parameters, level) = default_game();
+        let interval = ticks(parameters.power_ups.interval) as u64;
+        let mut simulation = Simulation::new(&parameters, &level, 0);
+        simulation.run(interval, |_| Vec::new());
+        assert!(simulation.pickups.is_empty());
+        simulation.step(&[]);
+        assert_eq!(simulation.pickups.len(), 1);
+
+        // Up to the most there may be at once, each on a spot of its own
+        simulation.run(interval * 3, |_| Vec::new());
+        assert_eq!(simulation.pickups.len(), parameters.power_ups.max_pickups);
+        let walls: Vec<(Vec2, Vec2)> = simulation
+            .walls
+            .iter()
+            .map(|wall| (wall.end_a(), wall.end_b()))
+            .collect();
+        let colliders = simulation.colliders();
+        for pickup in &simulation.pickups {
+            assert!(point_enclosed(pickup.position, walls.iter().copied()));
+            assert!(colliders.iter().all(|(_, shape)| shape
+                .contact_with_circle(pickup.position, pickup.radius)
+                .is_none()));
+        }
+        let (a, b) = (&simulation.pickups[0], &simulation.pickups[1]);
+        assert!(a.position.distance(b.position) > a.radius + b.radius);
+    }
+
+    #[test]
+    fn pickups_go_to_whoever_touched_the_ball_last() {
+        let (parameters, level) = default_game();
+        let mut simulation = Simulation::new(&parameters, &level, 0);
+        simulation.balls.clear();
+        let grow = PowerUp {
+            effect: PowerUpEffect::PaddleSize(1.5),
+            target: PowerUpTarget::Taker,
+            duration: 1.,
+            weight: 1.,
+        };
+        simulation.pickups.push(PickupState {
+            id: 100,
+            position: Vec2::new(0., 200.),
+            radius: 15.,
+            power_up: grow,
+        });
+
+        // Balls no paddle touched yet pass through
+        simulation.spawn_ball(Vec3::new(0., 200., 0.), Vec2::ZERO, None);
+        simulation.step(&[]);
+        assert_eq!(simulation.pickups.len(), 1);
+
+        simulation.balls[0].owner = Some(1);
+        let events = simulation.step(&[]);
+        assert!(events.contains(&SimulationEvent::PowerUp {
+            player: 1,
+            effect: PowerUpEffect::PaddleSize(1.5),
+        }));
+        assert!(simulation.pickups.is_empty());
+        simulation.step(&[]);
+        assert_eq!(simulation.paddles[1].size.y, 180.);
+        assert_eq!(simulation.paddles[0].size.y, 120.);
+
+        // Back to its own size once the power-up runs out
+        simulation.run(64, |_| Vec::new());
+        assert!(simulation.power_ups.is_empty());
+        assert_eq!(simulation.paddles[1].size.y, 120.);
+    }
+
+    #[test]
+    fn inverted_controls_reverse_moves_and_tilts() {
+        let (parameters, level) = default_game();
+        let mut simulation = Simulation::new(&parameters, &level, 0);
+        simulation.power_ups.push(ActivePowerUp {
+            effect: PowerUpEffect::InvertControls,
+            players: vec![0],
+            ticks_left: 100,
+        });
+        let input = PlayerInput {
+            direction: Vec3::Y,
+            rotation: 1.,
+            max_angle: 1.,
+            ..PlayerInput::default()
+        };
+
+        simulation.step(&[input, input]);
+        let (inverted, other) = (&simulation.paddles[0], &simulation.paddles[1]);
+        assert!(inverted.position.y < 0. && inverted.tilt < 0.);
+        assert!(other.position.y > 0. && other.tilt > 0.);
+    }
+}
@@ -1,12 +1,19 @@
 pub mod states {
-    use super::parameters::Level;
     use bevy::prelude::*;
 
-    #[derive(Debug, Clone, Default, Eq, PartialEq, Hash, States)]
+    /// Where the game is in its run of levels. Which level is played is kept in `CurrentLevel`.
+    #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
     pub enum AppStates {
+        /// No level is loaded.
         #[default]
         Menu,
-        Level1(Level),
+        /// Waiting for the level files before the level starts.
+        Loading,
+        Level,
+        /// A level has just ended; its result is shown until the player moves on.
+        Intermission,
+        /// The match is decided; its winner is shown, with the choice of a rematch.
+        GameOver,
     }
 }
 
@@ -18,10 +25,138 @@ pub mod events {
 
 pub mod resources {
     use bevy::prelude::*;
+    use rand::rngs::StdRng;
+
+    use crate::ai::AiController;
+    use crate::cli::Options;
+    use crate::net::{NetClient, NetServer};
+    use crate::parameters::ConfigError;
+    use crate::replay::{Playback, Recorder};
+    use crate::rollback::Rollback;
+    use crate::simulation::{LevelOutcome, Simulation};
 
     #[derive(Resource)]
     pub struct CollisionSound(pub Handle<AudioSource>);
 
+    /// What the game was launched with, see `cli`.
+    #[derive(Resource, Deref)]
+    pub struct CommandLine(pub Options);
+
+    /// Name of the level file the match starts on, found once the level files are loaded.
+    #[derive(Resource)]
+    pub struct LevelName(pub String);
+
+    /// Problems found in the parameters file, shown in place of the menu.
+    #[derive(Resource)]
+    pub struct ConfigErrors(pub ConfigError);
+
+    /// Source of every random decision of the game, seeded once at startup so a session plays
+    /// out the same again. Each set draws the seed of its `Simulation` and random arena from it.
+    #[derive(Resource, Deref, DerefMut)]
+    pub struct GameRng(pub StdRng);
+
+    /// Computer players of the level being played.
+    #[derive(Resource, Default, Deref, DerefMut)]
+    pub struct AiControllers(pub Vec<AiController>);
+
+    /// The match being played; the Bevy systems only feed it inputs and draw its state.
+    #[derive(Resource, Deref, DerefMut)]
+    pub struct GameSimulation(pub Simulation);
+
+    /// This instance hosts the match for clients on the network.
+    #[derive(Resource, Deref, DerefMut)]
+    pub struct Hosting(pub NetServer);
+
+    /// This instance plays one player of a match hosted elsewhere, and only draws what the host
+    /// sends.
+    #[derive(Resource, Deref, DerefMut)]
+    pub struct Joined(pub NetClient);
+
+    /// The set is played with rollback, see `net::NetMode`. Kept past the end of the set, so the
+    /// last inputs still reach the instances that haven't seen it end.
+    #[derive(Resource, Deref, DerefMut)]
+    pub struct GameRollback(pub Rollback);
+
+    /// The match is recorded, see `replay`.
+    #[derive(Resource, Deref, DerefMut)]
+    pub struct Recording(pub Recorder);
+
+    /// A replay is played back instead of a match.
+    #[derive(Resource, Deref, DerefMut)]
+    pub struct Playing(pub Playback);
+
+    /// Mesh and materials shared by every ball drawn on screen: `material` for balls no paddle
+    /// has touched yet, and one per paddle, in its color, for the balls it touched last.
+    #[derive(Resource)]
+    pub struct BallAssets {
+        pub mesh: Handle<Mesh>,
+        pub material: Handle<ColorMaterial>,
+        pub owners: Vec<Handle<ColorMaterial>>,
+    }
+
+    /// Mesh and material shared by every pickup drawn on screen.
+    #[derive(Resource)]
+    pub struct PickupAssets {
+        pub mesh: Handle<Mesh>,
+        pub material: Handle<ColorMaterial>,
+    }
+
+    /// Sets of the match played so far, each one being a level.
+    #[derive(Resource, Debug, Clone, Default)]
+    pub struct MatchState {
+        /// How many sets each player has won.
+        pub sets_won: Vec<u32>,
+        pub sets_played: u32,
+    }
+
+    impl MatchState {
+        pub fn new(n_players: usize) -> Self {
+            Self {
+                sets_won: vec![0; n_players],
+                sets_played: 0,
+            }
+        }
+
+        /// Counts a set won by `winner`, or drawn.
+        pub fn record(&mut self, winner: Option<usize>) {
+            self.sets_played += 1;
+            if let Some(winner) = winner {
+                if self.sets_won.len() <= winner {
+                    self.sets_won.resize(winner + 1, 0);
+                }
+                self.sets_won[winner] += 1;
+            }
+        }
+
+        /// Whether a player has won more than half of `best_of` sets, or all of them have been
+        /// played.
+        pub fn is_over(&self, best_of: u32) -> bool {
+            self.sets_played >= best_of || self.sets_won.iter().any(|won| *won > best_of / 2)
+        }
+
+        /// The player with the most sets, unless several share the lead.
+        pub fn leader(&self) -> Option<usize> {
+            let best = self.sets_won.iter().copied().max()?;
+            let mut leaders = (0..self.sets_won.len()).filter(|&i| self.sets_won[i] == best);
+            match (leaders.next(), leaders.next()) {
+                (Some(leader), None) => Some(leader),
+                _ => None,
+            }
+        }
+    }
+
+    /// Index in `Parameters.levels` of the level being played, or about to be.
+    #[derive(Resource, Debug, Clone, Copy, Default, Deref, DerefMut)]
+    pub struct CurrentLevel(pub usize);
+
+    /// How the last level ended, shown during the intermission.
+    #[derive(Resource, Debug, Clone)]
+    pub struct LevelResult {
+        pub level: usize,
+        pub outcome: LevelOutcome,
+        pub scores: Vec<f32>,
+    }
+
     #[derive(Resource)]
     pub struct Scoreboards {
         pub scores: Vec<f32>,
@@ -29,22 +164,25 @@ pub mod resources {
 }
 
 pub mod components {
-    use std::slice::Iter;
-
     use bevy::prelude::*;
-    use decorum::R32;
     use itertools::Itertools;
     use serde::{Deserialize, Serialize};
     use tuple_conv::RepeatedTuple as _;
 
     use super::parameters::Control;
+    use crate::ai::Difficulty;
+    use crate::geometry::{Region, Shape};
+    use crate::parameters::R32;
 
     #[derive(Clone, Serialize, Deserialize, Component)]
     pub struct Player {
         pub controls: Vec<Control>,
+        /// Hands the paddle to the computer, which then ignores `controls`.
+        #[serde(default)]
+        pub ai: Option<Difficulty>,
     }
 
-    #[derive(Component, Clone, Serialize, Deserialize)]
+    #[derive(Component, Clone, Serialize, Deserialize, Debug)]
     pub struct Ball {
         pub starting_position: Vec3,
         pub starting_direction: Vec2,
@@ -53,6 +191,8 @@ pub mod components {
         pub size: Vec3,
         pub probability_to_duplicate: f32,
         pub padding_for_bounds: f32,
+        /// Most balls in play at once. Breaking a brick no longer splits balls beyond it.
+        pub max_balls: usize,
     }
 
     impl Ball {
@@ -70,6 +210,50 @@ pub mod components {
     #[derive(Component)]
     pub struct Brick;
 
+    /// Belongs to the level being played, and is despawned when it ends.
+    #[derive(Component, Clone, Copy, Debug, Default)]
+    pub struct LevelEntity;
+
+    /// Text showing the score of a player, by index.
+    #[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct ScoreboardIndex(pub usize);
+
+    /// Text showing the time left in the set.
+    #[derive(Component)]
+    pub struct ClockText;
+
+    /// Text counting down to the next serve.
+    #[derive(Component)]
+    pub struct CountdownText;
+
+    /// Text listing the power-ups in effect and the time they have left.
+    #[derive(Component)]
+    pub struct PowerUpText;
+
+    /// Text showing where a replay being played back is, and how fast it goes.
+    #[derive(Component)]
+    pub struct ReplayText;
+
+    /// Identifier of the simulated pickup an entity draws.
+    #[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct PickupId(pub u64);
+
+    /// Drawn over the goal defended by a player, by index, while a shield closes it.
+    #[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct ShieldIndex(pub usize);
+
+    /// Index of the simulated paddle an entity draws.
+    #[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct PaddleIndex(pub usize);
+
+    /// Identifier of the simulated ball an entity draws.
+    #[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct BallId(pub u64);
+
+    /// Identifier of the simulated brick an entity draws.
+    #[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct BrickId(pub u64);
+
     #[derive(Debug, Clone, Serialize, Deserialize, Component, Eq, PartialEq, Hash)]
     pub struct Paddle {
         pub width: R32,
@@ -77,14 +261,22 @@ pub mod components {
         pub x: R32,
         pub y: R32,
         pub z: R32,
+        /// Turn of the paddle, in degrees counterclockwise. Its controls turn with it, so moving
+        /// "up" always moves it along its height.
+        #[serde(default = "no_turn")]
+        pub angle: R32,
+        /// Polygon(s) the paddle's center must stay inside, see `Region::from_points`.
         pub bounds: Vec<(R32, R32)>,
         pub speed: R32,
         pub color_rgba: (R32, R32, R32, R32),
         pub wall_that_gives_points: usize,
     }
 
+    fn no_turn() -> R32 {
+        R32::from(0.)
+    }
+
     fn vec3_from_r32_tuple(r32_tuple: &(R32, R32, R32)) -> Vec3 {
-        let a = r32_tuple.0.into_inner();
         let r32 = r32_tuple
             .to_vec()
             .iter()
@@ -105,6 +297,18 @@ pub mod components {
         pub fn speed(&self) -> f32 {
             self.speed.into_inner()
         }
+        /// Turn of the paddle, in radians.
+        pub fn angle(&self) -> f32 {
+            self.angle.into_inner().to_radians()
+        }
+        pub fn region(&self) -> Region {
+            let points = self
+                .bounds
+                .iter()
+                .map(|(x, y)| Vec2::new(x.into_inner(), y.into_inner()))
+                .collect::<Vec<_>>();
+            Region::from_points(&points)
+        }
         pub fn color(&self) -> Color {
             Color::rgba(
                 self.color_rgba.0.into_inner(),
@@ -136,66 +340,29 @@ pub mod components {
         pub fn translation(&self) -> Vec2 {
             (self.end_a() + self.end_b()) / 2.
         }
+        pub fn length(&self) -> f32 {
+            self.end_a().distance(self.end_b())
+        }
+        /// Angle of the line from `end_a` to `end_b`, counter-clockwise from the x-axis.
+        pub fn angle(&self) -> f32 {
+            let direction = self.end_b() - self.end_a();
+            direction.y.atan2(direction.x)
+        }
+        /// Size of the wall's sprite before it is rotated by `angle`. The ends are extended by
+        /// half the thickness, so walls that share an end meet without a notch.
         pub fn scale(&self) -> Vec2 {
-            let end_a = self.end_a();
-            let end_b = self.end_b();
-            let dir_a_to_b = (end_b - end_a).normalize_or_zero();
-            let left = dir_a_to_b.perp();
-            let right = dir_a_to_b.perp().perp().perp();
-            let bottom_left = end_a + (left * self.thickness());
-            let bottom_right = end_a + (right * self.thickness());
-            let top_left = end_b + (left * self.thickness());
-            let top_right = end_b + (right * self.thickness());
-            let vertices = (bottom_left, bottom_right, top_left, top_right).to_vec();
-
-            #[derive(Copy, Clone)]
-            enum Coordinates {
-                X = 0,
-                Y = 1,
-            }
-
-            fn get_extreme<'a, F>(vertices: Vec<Vec2>, func: F, coord: Coordinates) -> f32
-            where
-                F: Fn(Iter<R32>) -> Option<&'a R32>,
-            {
-                let l = vertices.len();
-                assert!(l > 0);
-                assert!((coord as usize) < l);
-
-                func(
-                    vertices
-                        .iter()
-                        .map(|e| {
-                            R32::from(
-                                *e.iter_fields().next().unwrap().do
-                                    .nth(coord as usize)
-                                    .unwrap()
-                                    .downcast_ref::<f32>()
-                                    .unwrap(),
-                            )
-                        })
-                        .collect::<Vec<R32>>()
-                        .iter(),
-                )
-                .unwrap()
-                .into_inner()
+            Vec2::new(self.length() + self.thickness(), self.thickness())
+        }
+        /// The wall as the ball sees it: every point within half the thickness of its segment.
+        pub fn shape(&self) -> Shape {
+            Shape::Capsule {
+                a: self.end_a(),
+                b: self.end_b(),
+                radius: self.thickness() / 2.,
             }
-
-            let x_min = get_extreme(vertices, Iterator::min, Coordinates::X);
-            let x_max = get_extreme(vertices, Iterator::max, Coordinates::X);
-            let y_min = get_extreme(vertices, Iterator::min, Coordinates::Y);
-            let y_max = get_extreme(vertices, Iterator::max, Coordinates::Y);
-
-            let x_delta = x_max - x_min;
-            let y_delta = y_max - y_min;
-
-            Vec2::new(x_delta, y_delta)
         }
         fn vec2_from_r32_2tuple(r32_tuple: &(R32, R32)) -> Vec2 {
-            let r32: Vec<f32> = r32_tuple.to_vec().iter().map(|e| e.into_inner()).collect();
-            let mut my_array: [f32; 2];
-            my_array.iter_mut().set_from(r32);
-            Vec2::from_array(my_array)
+            Vec2::new(r32_tuple.0.into_inner(), r32_tuple.1.into_inner())
         }
     }
 }
@@ -203,17 +370,24 @@ pub mod components {
 pub mod bundles {
     use bevy::prelude::*;
 
-    use super::components::{Collider, Player, Wall};
+    use super::components::{Collider, PaddleIndex, Player, Wall};
 
     #[derive(Bundle)]
     pub struct PlayerBundle {
         pub sprite_bundle: SpriteBundle,
         pub collider: Collider,
         pub player: Player,
+        pub index: PaddleIndex,
     }
 
     impl PlayerBundle {
-        pub fn new(player: &Player, translation: Vec3, scale: Vec3, color: Color) -> Self {
+        pub fn new(
+            player: &Player,
+            index: usize,
+            translation: Vec3,
+            scale: Vec3,
+            color: Color,
+        ) -> Self {
             Self {
                 sprite_bundle: SpriteBundle {
                     transform: Transform {
@@ -226,6 +400,7 @@ pub mod bundles {
                 },
                 collider: Collider,
                 player: player.clone(),
+                index: PaddleIndex(index),
             }
         }
     }
@@ -244,8 +419,9 @@ pub mod bundles {
         // This "builder method" allows us to reuse logic across our wall entities,
         // making our code easier to read and less prone to bugs when we change the logic
         pub fn new(wall: &Wall) -> WallBundle {
-            let wall = *wall;
+            let wall = wall.clone();
             let translation = wall.translation().extend(0.);
+            let rotation = Quat::from_rotation_z(wall.angle());
             let scale = wall.scale().extend(1.);
             let color = wall.color;
             let color = (
@@ -261,11 +437,12 @@ pub mod bundles {
                         // We need to convert our Vec2 into a Vec3, by giving it a z-coordinate
                         // This is used to determine the order of our sprites
                         translation,
+                        // Walls can run at any angle, not only along the axes
+                        rotation,
                         // The z-scale of 2D objects must always be 1.0,
                         // or their ordering will be affected in surprising ways.
                         // See https://github.com/bevyengine/bevy/issues/4149
                         scale,
-                        ..default()
                     },
                     sprite: Sprite { color, ..default() },
                     ..default()
@@ -279,7 +456,6 @@ pub mod bundles {
 
 pub mod parameters {
     use bevy::prelude::*;
-    use decorum::R32;
     use itertools::{izip, Itertools};
     use serde::{Deserialize, Serialize};
     use std::{fs, io::Write};
@@ -287,51 +463,215 @@ pub mod parameters {
     use toml::to_string;
     use tuple_conv::RepeatedTuple as _;
 
-    use super::super::parameters::*;
+    use super::super::migration::{merge, migrate, SCHEMA_VERSION};
+    use super::super::parameters::{
+        attribute, unknown_keys, validate, ConfigError, ConfigProblem, ParametersPaddles, R32,
+    };
     use super::components::{Ball, Paddle, Player, Wall};
+    use crate::arena::ArenaSpec;
+    use crate::cli::Options;
+    use crate::net::{LinkConditions, NetMode, Transport};
 
-    const PARAMETERS_FILE_PATH: &str = "parameters.toml";
-    const ALWAYS_REWRITE_TOML: bool = cfg!(debug_assertions);
+    /// The parameters file read unless the command line names another.
+    pub const PARAMETERS_FILE_PATH: &str = "parameters.toml";
 
     #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
     pub struct Level {
         pub walls: Vec<Wall>,
         pub paddles: Vec<Paddle>,
+        #[serde(default)]
+        pub win: WinCondition,
+        /// Goals a player may let in before being eliminated. Their paddle then leaves play, and
+        /// the goal they defended becomes a plain wall.
+        #[serde(default)]
+        pub goal_limit: Option<u32>,
+        /// Grids of goal bricks, sized by `Parameters.brick`.
+        #[serde(default)]
+        pub bricks: Vec<BrickLayout>,
+        /// Walls of bricks laid in front of every goal, between it and the paddle defending it.
+        /// A goal guarded this way only counts once one of its bricks has been broken.
+        #[serde(default)]
+        pub goal_bricks: Option<GoalBricks>,
+        /// Where balls may be put into play. Without any, `Ball.starting_position` is used.
+        #[serde(default)]
+        pub ball_spawns: Vec<(R32, R32)>,
+    }
+
+    impl Level {
+        pub fn ball_spawns(&self) -> Vec<Vec2> {
+            self.ball_spawns
+                .iter()
+                .map(|(x, y)| Vec2::new(x.into_inner(), y.into_inner()))
+                .collect()
+        }
+    }
+
+    /// A grid of `rows` by `columns` bricks. The brick in the bottom left corner is centered on
+    /// `origin`, and `gap` is left between neighbouring bricks.
+    #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
+    pub struct BrickLayout {
+        pub origin: (R32, R32),
+        pub rows: usize,
+        pub columns: usize,
+        pub gap: R32,
+        /// Hits each brick of the grid takes before breaking.
+        #[serde(default = "one_hit")]
+        pub hit_points: u32,
+    }
+
+    fn one_hit() -> u32 {
+        1
+    }
+
+    /// Brick walls guarding the goals, fitted with the gaps of `Parameters.misc`.
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash)]
+    pub struct GoalBricks {
+        /// Bricks stacked from the goal toward the paddle, at most as many as fit.
+        pub layers: usize,
+        /// Hits the bricks against the goal take before breaking. Each layer further out takes
+        /// one less, down to one.
+        pub hit_points: u32,
+    }
+
+    impl BrickLayout {
+        /// Center of every brick of the grid, row by row.
+        pub fn positions(&self, brick_size: Vec2) -> Vec<Vec2> {
+            let origin = Vec2::new(self.origin.0.into_inner(), self.origin.1.into_inner());
+            let step = brick_size + Vec2::splat(self.gap.into_inner());
+            (0..self.rows)
+                .flat_map(|row| {
+                    (0..self.columns)
+                        .map(move |column| origin + step * Vec2::new(column as f32, row as f32))
+                })
+                .collect()
+        }
+    }
+
+    /// When a level is over. Whoever has the most points then wins it. The `time_limit` of
+    /// `Parameters.rules` applies on top of any condition.
+    #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, PartialEq, Hash)]
+    pub enum WinCondition {
+        /// A player reaches `Parameters.rules.points_to_win`, far enough ahead of the others.
+        #[default]
+        Rules,
+        /// A player reaches this many points, far enough ahead of the others.
+        Score(u32),
+        /// The last goal brick has been broken.
+        BricksCleared,
+        /// Every player but one has been eliminated, see `Level.goal_limit`. The one left wins,
+        /// whatever the points.
+        LastStanding,
+        /// The level never ends on its own.
+        Endless,
     }
 
     #[derive(Clone, Serialize, Deserialize)]
     pub struct Control {
-        pub key: MyKeyCode,
+        pub input: Binding,
         pub effect: Effect,
     }
 
+    /// What a player does to trigger a `Control`. Buttons trigger it fully or not at all; sticks
+    /// and the mouse scale its `Effect::Move` or `Effect::Rotate` by how far they are pushed, and
+    /// trigger its other effects once pushed halfway.
+    #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+    pub enum Binding {
+        Key(MyKeyCode),
+        GamepadButton {
+            /// Which connected gamepad, counting from 0.
+            gamepad: usize,
+            button: MyGamepadButton,
+        },
+        GamepadAxis {
+            gamepad: usize,
+            axis: MyGamepadAxis,
+            /// Share of the stick's travel, from the center, that is ignored.
+            dead_zone: f32,
+        },
+        /// Moves the paddle toward the mouse cursor's height, slowing down as it gets close.
+        MouseY,
+    }
+
+    impl std::fmt::Display for Binding {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Binding::Key(key) => write!(f, "{key:?}"),
+                Binding::GamepadButton { gamepad, button } => {
+                    write!(f, "Gamepad {} {button:?}", gamepad + 1)
+                }
+                Binding::GamepadAxis { gamepad, axis, .. } => {
+                    write!(f, "Gamepad {} {axis:?}", gamepad + 1)
+                }
+                Binding::MouseY => write!(f, "Mouse Y"),
+            }
+        }
+    }
+
+    /// What a `Control` does while it is triggered. Each effect carries its own settings, so two
+    /// controls can dash or tilt the paddle differently.
     #[derive(Clone, Serialize, Deserialize)]
     pub enum Effect {
         Move(Vec3),
+        /// Throws the paddle `distance` along `direction` at once. It can't dash again for
+        /// `cooldown` seconds.
+        Dash {
+            direction: Vec3,
+            distance: f32,
+            cooldown: f32,
+        },
+        /// Tilts the paddle by `speed` degrees per second, counterclockwise when positive, up to
+        /// `max_angle` degrees either way. Balls bounce off the tilted face.
+        Rotate {
+            speed: f32,
+            max_angle: f32,
+        },
+        /// While held, balls hitting the paddle stick to it and follow it around. Letting go
+        /// serves them the way they were bouncing, turned with the paddle, at `speed`, or at the
+        /// speed they came in with if it is 0.
+        Serve {
+            speed: f32,
+        },
+        /// Stores a power shot in the paddle: the next ball it hits leaves `boost` times faster.
+        /// It can't store another one for `cooldown` seconds.
+        Power {
+            boost: f32,
+            cooldown: f32,
+        },
         Nothing,
     }
 
-    pub fn wrong_toml(reason: &str) {
-        panic!("Unvalid TOML file structure [{path}] ({reason}), delete file and a valid one will be generated.",
-                path=PARAMETERS_FILE_PATH, reason=reason)
+    impl std::fmt::Display for Effect {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Effect::Move(direction) => write!(f, "Move {}", DirectionName(*direction)),
+                Effect::Dash { direction, .. } => write!(f, "Dash {}", DirectionName(*direction)),
+                Effect::Rotate { speed, .. } if *speed >= 0. => write!(f, "Tilt counterclockwise"),
+                Effect::Rotate { .. } => write!(f, "Tilt clockwise"),
+                Effect::Serve { .. } => write!(f, "Hold serve"),
+                Effect::Power { .. } => write!(f, "Power shot"),
+                Effect::Nothing => write!(f, "Nothing"),
+            }
+        }
     }
 
-    pub fn parameters_from_toml() -> Parameters {
-        fn write_config_to_file_if_not_exists(
-            config: &Parameters,
-            file_path: &str,
-        ) -> Result<(), Box<dyn std::error::Error>> {
-            if !ALWAYS_REWRITE_TOML && Path::new(file_path).exists() {
-                return Ok(());
+    /// Shows a direction as a side when it points straight at one.
+    struct DirectionName(Vec3);
+
+    impl std::fmt::Display for DirectionName {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let direction = self.0;
+            match (direction.x, direction.y) {
+                (x, y) if x == 0. && y > 0. => write!(f, "up"),
+                (x, y) if x == 0. && y < 0. => write!(f, "down"),
+                (x, y) if y == 0. && x > 0. => write!(f, "right"),
+                (x, y) if y == 0. && x < 0. => write!(f, "left"),
+                (x, y) => write!(f, "({x}, {y})"),
             }
-            let toml_string = to_string(config)?;
-            let mut file = File::create(file_path)?;
-            file.write_all(toml_string.as_bytes())?;
-            println!("Config file created successfully.");
-            Ok(())
         }
+    }
 
-        let parameters = {
+    impl Default for Parameters {
+        fn default() -> Self {
             let up_direction = Vec3::new(0., 1., 0.);
             let down_direction = Vec3::new(0., -1., 0.);
 
@@ -346,26 +686,110 @@ pub mod parameters {
                 Player {
                     controls: vec![
                         Control {
-                            key: MyKeyCode::Q,
+                            input: Binding::Key(MyKeyCode::Q),
                             effect: Effect::Move(up_direction),
                         },
                         Control {
-                            key: MyKeyCode::A,
+                            input: Binding::Key(MyKeyCode::A),
                             effect: Effect::Move(down_direction),
                         },
+                        Control {
+                            input: Binding::Key(MyKeyCode::W),
+                            effect: Effect::Dash {
+                                direction: up_direction,
+                                distance: 80.,
+                                cooldown: 1.,
+                            },
+                        },
+                        Control {
+                            input: Binding::Key(MyKeyCode::S),
+                            effect: Effect::Dash {
+                                direction: down_direction,
+                                distance: 80.,
+                                cooldown: 1.,
+                            },
+                        },
+                        Control {
+                            input: Binding::Key(MyKeyCode::E),
+                            effect: Effect::Rotate {
+                                speed: 90.,
+                                max_angle: 30.,
+                            },
+                        },
+                        Control {
+                            input: Binding::Key(MyKeyCode::D),
+                            effect: Effect::Rotate {
+                                speed: -90.,
+                                max_angle: 30.,
+                            },
+                        },
+                        Control {
+                            input: Binding::Key(MyKeyCode::Z),
+                            effect: Effect::Serve { speed: 0. },
+                        },
+                        Control {
+                            input: Binding::Key(MyKeyCode::X),
+                            effect: Effect::Power {
+                                boost: 1.5,
+                                cooldown: 5.,
+                            },
+                        },
                     ],
+                    ai: None,
                 },
                 Player {
                     controls: vec![
                         Control {
-                            key: MyKeyCode::O,
+                            input: Binding::Key(MyKeyCode::O),
                             effect: Effect::Move(up_direction),
                         },
                         Control {
-                            key: MyKeyCode::L,
+                            input: Binding::Key(MyKeyCode::L),
                             effect: Effect::Move(down_direction),
                         },
+                        Control {
+                            input: Binding::Key(MyKeyCode::I),
+                            effect: Effect::Dash {
+                                direction: up_direction,
+                                distance: 80.,
+                                cooldown: 1.,
+                            },
+                        },
+                        Control {
+                            input: Binding::Key(MyKeyCode::K),
+                            effect: Effect::Dash {
+                                direction: down_direction,
+                                distance: 80.,
+                                cooldown: 1.,
+                            },
+                        },
+                        Control {
+                            input: Binding::Key(MyKeyCode::U),
+                            effect: Effect::Rotate {
+                                speed: 90.,
+                                max_angle: 30.,
+                            },
+                        },
+                        Control {
+                            input: Binding::Key(MyKeyCode::J),
+                            effect: Effect::Rotate {
+                                speed: -90.,
+                                max_angle: 30.,
+                            },
+                        },
+                        Control {
+                            input: Binding::Key(MyKeyCode::M),
+                            effect: Effect::Serve { speed: 0. },
+                        },
+                        Control {
+                            input: Binding::Key(MyKeyCode::N),
+                            effect: Effect::Power {
+                                boost: 1.5,
+                                cooldown: 5.,
+                            },
+                        },
                     ],
+                    ai: None,
                 },
             ];
 
@@ -377,6 +801,62 @@ pub mod parameters {
                 size: Vec3::new(30.0, 30.0, 0.),
                 probability_to_duplicate: 0.1,
                 padding_for_bounds: 0.1,
+                max_balls: 4,
+            };
+
+            let rally = ParametersRally {
+                countdown: 2.,
+                serve_toward: ServeToward::Loser,
+                serve_cone: 30.,
+            };
+
+            let bounce = ParametersBounce {
+                max_angle: 60.,
+                spin: 0.25,
+                speed_up: 1.05,
+            };
+
+            let power_up = |effect, target, duration, weight| PowerUp {
+                effect,
+                target,
+                duration,
+                weight,
+            };
+            let power_ups = ParametersPowerUps {
+                interval: 8.,
+                max_pickups: 2,
+                radius: 15.,
+                kinds: vec![
+                    power_up(
+                        PowerUpEffect::PaddleSize(1.5),
+                        PowerUpTarget::Taker,
+                        10.,
+                        2.,
+                    ),
+                    power_up(
+                        PowerUpEffect::PaddleSize(0.6),
+                        PowerUpTarget::Opponents,
+                        10.,
+                        1.,
+                    ),
+                    power_up(PowerUpEffect::BallSpeed(0.7), PowerUpTarget::Taker, 8., 2.),
+                    power_up(PowerUpEffect::BallSpeed(1.4), PowerUpTarget::Taker, 8., 1.),
+                    power_up(PowerUpEffect::ExtraBall, PowerUpTarget::Taker, 0., 2.),
+                    power_up(PowerUpEffect::Shield, PowerUpTarget::Taker, 6., 1.),
+                    power_up(
+                        PowerUpEffect::InvertControls,
+                        PowerUpTarget::Opponents,
+                        5.,
+                        1.,
+                    ),
+                ],
+            };
+
+            let rules = ParametersRules {
+                points_to_win: 5,
+                win_by: 2,
+                time_limit: None,
+                best_of: 3,
             };
 
             let levels = {
@@ -435,53 +915,25 @@ pub mod parameters {
                 }
 
                 // PADDLES
-                // Transform into R32
-                let a = Color::rgb();
-                let paddle = |x: f32, bounds: Vec<(f32, f32)>, wall_that_gives_points: usize| {
-                    // Transform into R32, so we can Serialize, Deserialize and have Eq (required by trait States)
-                    let PADDLES_WIDTH = R32::from(PADDLES_WIDTH);
-                    let PADDLES_HEIGHT = R32::from(PADDLES_HEIGHT);
-                    let x = R32::from(x);
-                    let y = R32::from(y);
-                    let z = R32::from(z);
-                    let bounds = bounds
-                        .to_vec()
-                        .iter()
-                        .map(|e| (R32::from(e.0), R32::from(e.1)))
-                        .collect::<Vec<(R32, R32)>>();
-                    let speed = R32::from(speed);
-                    let color_rgba = (
-                        R32::from(color_rgba.0),
-                        R32::from(color_rgba.1),
-                        R32::from(color_rgba.2),
-                        R32::from(color_rgba.3),
-                    );
-
-                    Paddle {
-                        width,
-                        height,
-                        x,
-                        y,
-                        z,
-                        bounds,
-                        speed,
-                        color_rgba,
-                        wall_that_gives_points,
-                    }
-                };
-
-                let paddle_1 = paddle(x_1, bounds_1, wall_that_gives_points_1);
+                let paddles_parameters = ParametersPaddles::default();
+                debug_assert!(paddles_parameters.verify("paddles").is_empty());
+                let paddles = paddles_parameters.paddles();
 
-                let paddle_2 = paddle(x_2, bounds_2, wall_that_gives_points_2);
-
-                let paddles = vec![paddle_1, paddle_2];
                 // Result
-                vec![Level { walls, paddles }]
+                vec![Level {
+                    walls,
+                    paddles,
+                    win: WinCondition::default(),
+                    goal_limit: None,
+                    bricks: Vec::new(),
+                    goal_bricks: None,
+                    ball_spawns: Vec::new(),
+                }]
             };
 
             let brick = ParametersBrick {
-                width: 5.,   // was 20
-                height: 10., // was 100
+                width: 5.,
+                height: 10.,
             };
 
             let scoreboard = ParametersScoreboard {
@@ -493,49 +945,386 @@ pub mod parameters {
                 background: Color::rgb(0.9, 0.9, 0.9),
                 ball: Color::rgb(1.0, 0.5, 0.5),
                 brick: Color::rgb(0.5, 0.5, 1.0),
+                strongest_brick: Color::rgb(0.1, 0.1, 0.6),
+                pickup: Color::rgb(0.3, 0.7, 0.3),
+                shield: Color::rgba(0.3, 0.7, 0.3, 0.6),
                 text: Color::rgb(0.5, 0.5, 1.0),
                 score: Color::rgb(1.0, 0.5, 0.5),
             };
 
             Parameters {
+                schema_version: SCHEMA_VERSION,
+                seed: None,
                 players,
                 misc,
                 ball,
+                rally,
+                bounce,
+                power_ups,
+                rules,
+                arena: Arena::Levels,
                 levels,
                 brick,
                 scoreboard,
                 colors,
+                net: ParametersNet::default(),
+                replay: ParametersReplay::default(),
             }
+        }
+    }
+
+    /// Override file for level `level` (counting from 0), merged over the parameters file
+    /// `config` while that level is played. It only needs the values that differ. It sits next to
+    /// `config`, named after it: `parameters.level-0.toml` for `parameters.toml`.
+    pub fn level_parameters_file_path(config: &str, level: usize) -> String {
+        let config = Path::new(config);
+        let stem = config
+            .file_stem()
+            .map_or("parameters".into(), |stem| stem.to_string_lossy());
+        config
+            .with_file_name(format!("{stem}.level-{level}.toml"))
+            .display()
+            .to_string()
+    }
+
+    /// Reads the parameters file over the defaults, with `options` on top, writing the defaults
+    /// to `parameters.toml` first if it is the one read and doesn't exist. Files written for an
+    /// older `schema_version` are migrated as they are read; the file itself is never rewritten.
+    ///
+    /// Never panics: anything wrong with the file is returned, each problem with its TOML path
+    /// and line.
+    pub fn parameters_from_toml(options: &Options) -> Result<Parameters, ConfigError> {
+        fn write_config_to_file(
+            config: &Parameters,
+            file_path: &str,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            let toml_string = to_string(config)?;
+            let mut file = File::create(file_path)?;
+            file.write_all(toml_string.as_bytes())?;
+            info!("Wrote the default parameters to {file_path}");
+            Ok(())
+        }
+
+        // A file named on the command line must be there
+        if options.config.is_none() && !Path::new(PARAMETERS_FILE_PATH).exists() {
+            write_config_to_file(&Parameters::default(), PARAMETERS_FILE_PATH).map_err(
+                |error| {
+                    ConfigError::single(
+                        PARAMETERS_FILE_PATH,
+                        ConfigProblem::new(
+                            "",
+                            format!("couldn't write default parameters: {error}"),
+                        ),
+                    )
+                },
+            )?;
+        }
+        read_layers(&[options.config_path().to_string()], options)
+    }
+
+    /// Writes the controls of `players` to the parameters file `config`, leaving everything else
+    /// in it as it was. The file is brought up to the current `schema_version` on the way, and
+    /// its comments are lost.
+    pub fn save_controls(config: &str, players: &[Player]) -> Result<(), ConfigError> {
+        let problem =
+            |message: String| ConfigError::single(config, ConfigProblem::new("", message));
+
+        // A missing file is written from scratch, anything else that keeps it from being read
+        // would lose what is in it
+        let text = match fs::read_to_string(config) {
+            Ok(text) => text,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(problem(format!("couldn't read file: {error}"))),
         };
+        let mut file: toml::Value =
+            toml::from_str(&text).map_err(|error| ConfigError::from_toml(config, &text, error))?;
+        migrate(&mut file).map_err(|problems| ConfigError::new(config, problems).locate(&text))?;
+
+        let table = file
+            .as_table_mut()
+            .ok_or_else(|| problem("must be a table".to_string()))?;
+        let saved = table
+            .entry("players")
+            .or_insert_with(|| toml::Value::Array(Vec::new()));
+        let toml::Value::Array(saved) = saved else {
+            return Err(problem("players must be an array".to_string()));
+        };
+        for (i, player) in players.iter().enumerate() {
+            let controls = toml::Value::try_from(&player.controls)
+                .map_err(|error| problem(error.to_string()))?;
+            match saved.get_mut(i).and_then(toml::Value::as_table_mut) {
+                Some(saved) => {
+                    saved.insert("controls".to_string(), controls);
+                }
+                None => saved.push(
+                    toml::Value::try_from(player).map_err(|error| problem(error.to_string()))?,
+                ),
+            }
+        }
+
+        let text = to_string(&file).map_err(|error| problem(error.to_string()))?;
+        fs::write(config, text).map_err(|error| problem(format!("couldn't write file: {error}")))
+    }
 
-        let parameters: Parameters = match write_config_to_file_if_not_exists(
-            &parameters,
-            PARAMETERS_FILE_PATH,
-        ) {
-            Err(_) => panic!(
-                "Couldn't write config to file {} that didn't exist!",
-                PARAMETERS_FILE_PATH
-            ),
-            Ok(_) => {
-                let toml_str = fs::read_to_string(PARAMETERS_FILE_PATH)
-                    .expect("Failed to read Cargo.toml file, after writing if it didn't exist.");
-                toml::from_str(&toml_str).unwrap_or_else(|_| panic!("Unvalid TOML file structure ({}), delete file and a valid one will be generated.",
-                PARAMETERS_FILE_PATH))
+    /// Parameters for playing `level`: the parameters file with the level's override file, if
+    /// there is one, and `options` merged on top.
+    pub fn parameters_for_level(
+        options: &Options,
+        level: usize,
+    ) -> Result<Parameters, ConfigError> {
+        let mut files = vec![options.config_path().to_string()];
+        let override_path = level_parameters_file_path(options.config_path(), level);
+        if Path::new(&override_path).exists() {
+            files.push(override_path);
+        }
+        read_layers(&files, options)
+    }
+
+    /// Merges `files` in order over the defaults, then puts `options` in. Tables are merged key by
+    /// key, anything else (arrays included) is replaced by the later file.
+    fn read_layers(files: &[String], options: &Options) -> Result<Parameters, ConfigError> {
+        let mut merged = toml::Value::try_from(Parameters::default()).map_err(|error| {
+            ConfigError::single("(defaults)", ConfigProblem::new("", error.to_string()))
+        })?;
+
+        let mut layers = Vec::new();
+        for file in files {
+            let text = fs::read_to_string(file).map_err(|error| {
+                ConfigError::single(
+                    file,
+                    ConfigProblem::new("", format!("couldn't read file: {error}")),
+                )
+            })?;
+            let mut layer: toml::Value = toml::from_str(&text)
+                .map_err(|error| ConfigError::from_toml(file, &text, error))?;
+            let version = migrate(&mut layer)
+                .map_err(|problems| ConfigError::new(file, problems).locate(&text))?;
+            if version < SCHEMA_VERSION {
+                println!(
+                    "[{file}] is written for schema version {version}, reading it as version {SCHEMA_VERSION}. Set `schema_version = {SCHEMA_VERSION}` once it is updated."
+                );
             }
-        };
+            merge(&mut merged, layer.clone());
+            layers.push((file.as_str(), text, layer));
+        }
 
-        parameters
+        let mut parameters = Parameters::deserialize(merged).map_err(|error| {
+            let (file, text, _) = layers.last().expect("the parameters file is always read");
+            ConfigError::from_toml(file, text, error)
+        })?;
+        options.apply(&mut parameters);
+
+        let mut error = ConfigError::default();
+        for (file, text, layer) in &layers {
+            error.extend(ConfigError::new(file, unknown_keys(layer, &parameters)).locate(text));
+        }
+        let texts: Vec<_> = layers
+            .iter()
+            .map(|(file, text, _)| (*file, text.as_str()))
+            .collect();
+        error.extend(attribute(validate(&parameters), &texts));
+        if !error.is_empty() {
+            return Err(error);
+        }
+        Ok(parameters)
     }
 
-    #[derive(Serialize, Deserialize)]
+    #[derive(Resource, Clone, Serialize, Deserialize)]
     pub struct Parameters {
+        /// Layout the file was written for, see `migration`.
+        pub schema_version: u32,
+        /// Seed of `GameRng`. Without one, a new one is picked at every start; it is printed, so
+        /// the session can be played again.
+        pub seed: Option<u64>,
         pub players: Vec<Player>,
         pub misc: ParametersMisc,
         pub ball: Ball,
+        pub rally: ParametersRally,
+        pub bounce: ParametersBounce,
+        pub power_ups: ParametersPowerUps,
+        pub rules: ParametersRules,
+        pub arena: Arena,
         pub levels: Vec<Level>,
         pub brick: ParametersBrick,
         pub scoreboard: ParametersScoreboard,
         pub colors: ParametersColors,
+        pub net: ParametersNet,
+        pub replay: ParametersReplay,
+    }
+
+    /// The point cycle: a ball that reaches a goal wall leaves play, and once no ball is left a
+    /// new one is served after a countdown.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ParametersRally {
+        /// Seconds between the last ball leaving play and the next serve.
+        pub countdown: f32,
+        pub serve_toward: ServeToward,
+        /// Width of the cone the serve direction is picked in, in degrees, centered on the
+        /// receiving paddle.
+        pub serve_cone: f32,
+    }
+
+    /// How balls leave a paddle. The face of the paddle sends them straight back from its
+    /// middle and at up to `max_angle` from its ends, and a moving paddle drags them along.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ParametersBounce {
+        /// Largest angle between a ball leaving the paddle and the paddle's face normal, in
+        /// degrees.
+        pub max_angle: f32,
+        /// Share of the paddle's speed along its face given to the ball.
+        pub spin: f32,
+        /// Speed factor given to the ball on every paddle hit, up to `Ball.max_speed`.
+        pub speed_up: f32,
+    }
+
+    /// Pickups that appear in the arena from time to time. A ball running into one gives it to
+    /// the player who touched that ball last; balls no paddle touched yet pass through.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ParametersPowerUps {
+        /// Seconds between two pickups appearing.
+        pub interval: f32,
+        /// Most pickups waiting in the arena at once. With 0, no pickup ever appears.
+        pub max_pickups: usize,
+        pub radius: f32,
+        /// What a pickup may hold. Each appears with a chance proportional to its `weight`.
+        pub kinds: Vec<PowerUp>,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+    pub struct PowerUp {
+        pub effect: PowerUpEffect,
+        /// Who the effect applies to, for effects on players.
+        pub target: PowerUpTarget,
+        /// Seconds the effect lasts. Ignored by effects that happen once, like an extra ball.
+        pub duration: f32,
+        pub weight: f32,
+    }
+
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+    pub enum PowerUpEffect {
+        /// Multiplies the length of the target paddles.
+        PaddleSize(f32),
+        /// Multiplies the speed of every ball in play.
+        BallSpeed(f32),
+        /// Serves another ball from the pickup, owned by the player who took it, if
+        /// `Ball.max_balls` allows.
+        ExtraBall,
+        /// Closes the goals of the target players, as if they were plain walls.
+        Shield,
+        /// Reverses the moves, dashes and tilts of the target players.
+        InvertControls,
+    }
+
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    pub enum PowerUpTarget {
+        /// The player who took the pickup.
+        Taker,
+        /// Every other player.
+        Opponents,
+    }
+
+    impl std::fmt::Display for PowerUpEffect {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                PowerUpEffect::PaddleSize(factor) if *factor >= 1. => write!(f, "Long paddle"),
+                PowerUpEffect::PaddleSize(_) => write!(f, "Short paddle"),
+                PowerUpEffect::BallSpeed(factor) if *factor >= 1. => write!(f, "Fast balls"),
+                PowerUpEffect::BallSpeed(_) => write!(f, "Slow balls"),
+                PowerUpEffect::ExtraBall => write!(f, "Extra ball"),
+                PowerUpEffect::Shield => write!(f, "Shield"),
+                PowerUpEffect::InvertControls => write!(f, "Inverted controls"),
+            }
+        }
+    }
+
+    /// Where the sets of a match are played.
+    #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+    pub enum Arena {
+        /// The levels of the parameters file, then those of `assets/levels/`.
+        Levels,
+        /// Every player against every other on a regular polygon with one side per player,
+        /// generated for the number of players (3 to 8), see `arena::free_for_all`. A player is
+        /// out once they have let in `goal_limit` goals, and the last one left wins the set.
+        FreeForAll { radius: f32, goal_limit: u32 },
+        /// The arena `spec` describes, see `arena::generate`.
+        Generated(ArenaSpec),
+        /// A different generated arena for every set, see `arena::random_spec`.
+        Random { goal_limit: Option<u32> },
+    }
+
+    /// Playing over the local network, see `net`.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ParametersNet {
+        pub role: NetRole,
+        /// Where the host listens, or the client connects to, as `ip:port`.
+        pub address: String,
+        pub transport: Transport,
+        /// Players the host hands to clients, one each, in the order they join. The others are
+        /// played on the host.
+        pub remote_players: Vec<usize>,
+        /// Set on the host; clients do as it says.
+        pub mode: NetMode,
+        /// Latency and loss added to the link, for testing.
+        pub conditions: LinkConditions,
+    }
+
+    impl Default for ParametersNet {
+        fn default() -> Self {
+            Self {
+                role: NetRole::Local,
+                address: "127.0.0.1:7777".to_string(),
+                transport: Transport::Udp,
+                remote_players: vec![1],
+                mode: NetMode::Snapshots,
+                conditions: LinkConditions::default(),
+            }
+        }
+    }
+
+    /// Recording matches and playing them back, see `replay`.
+    #[derive(Clone, Serialize, Deserialize, Debug, Default)]
+    pub struct ParametersReplay {
+        /// File every match is recorded to, rewritten after each set.
+        pub record: Option<String>,
+        /// File played back when Play is pressed, instead of a match.
+        pub play: Option<String>,
+    }
+
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+    pub enum NetRole {
+        /// Every player is played on this machine.
+        #[default]
+        Local,
+        /// Runs the match and lets clients play `remote_players`.
+        Host,
+        /// Plays one player of the match hosted at `address`.
+        Join,
+    }
+
+    /// How a match is won. It is played in sets of one level each, going through the levels in
+    /// order and starting over after the last.
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct ParametersRules {
+        /// Points that win a set, unless the level's `win` says otherwise.
+        pub points_to_win: u32,
+        /// How many points ahead of every other player the winner of a set must be.
+        pub win_by: u32,
+        /// Seconds a set lasts at most. Once they run out the leader wins the set, or, if several
+        /// players share the lead, whoever scores next (sudden death).
+        pub time_limit: Option<f32>,
+        /// Sets in the match. It ends once a player has won more than half of them, or once all
+        /// have been played.
+        pub best_of: u32,
+    }
+
+    /// Who receives the next serve. The first serve of a level goes along
+    /// `Ball.starting_direction` instead.
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    pub enum ServeToward {
+        /// The player who defended the goal the last point was scored on.
+        Loser,
+        /// Every player in turn.
+        Alternate,
     }
 
     #[derive(Clone, Serialize, Deserialize)]
@@ -546,8 +1335,36 @@ pub mod parameters {
         pub minimum_gap_between_bricks_and_vertical_walls: f32,
     }
 
-    #[derive(Clone, Serialize, Deserialize, Debug)]
-    pub enum MyKeyCode {
+    /// Declares `MyKeyCode` with one variant per listed `KeyCode`, converting both ways. The
+    /// conversion from `KeyCode` is an exhaustive match, so the build breaks if Bevy adds a key
+    /// that is missing here.
+    macro_rules! key_codes {
+        ($($key:ident),* $(,)?) => {
+            /// A `KeyCode` that can be written to and read from the parameters file.
+            #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+            pub enum MyKeyCode {
+                $($key,)*
+            }
+
+            impl From<MyKeyCode> for KeyCode {
+                fn from(value: MyKeyCode) -> KeyCode {
+                    match value {
+                        $(MyKeyCode::$key => KeyCode::$key,)*
+                    }
+                }
+            }
+
+            impl From<KeyCode> for MyKeyCode {
+                fn from(value: KeyCode) -> MyKeyCode {
+                    match value {
+                        $(KeyCode::$key => MyKeyCode::$key,)*
+                    }
+                }
+            }
+        };
+    }
+
+    key_codes!(
         Key1,
         Key2,
         Key3,
@@ -711,174 +1528,107 @@ pub mod parameters {
         Copy,
         Paste,
         Cut,
+    );
+
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    pub enum MyGamepadButton {
+        South,
+        East,
+        North,
+        West,
+        C,
+        Z,
+        LeftTrigger,
+        LeftTrigger2,
+        RightTrigger,
+        RightTrigger2,
+        Select,
+        Start,
+        Mode,
+        LeftThumb,
+        RightThumb,
+        DPadUp,
+        DPadDown,
+        DPadLeft,
+        DPadRight,
+        Other(u8),
+    }
+
+    impl From<MyGamepadButton> for GamepadButtonType {
+        fn from(value: MyGamepadButton) -> GamepadButtonType {
+            match value {
+                MyGamepadButton::South => GamepadButtonType::South,
+                MyGamepadButton::East => GamepadButtonType::East,
+                MyGamepadButton::North => GamepadButtonType::North,
+                MyGamepadButton::West => GamepadButtonType::West,
+                MyGamepadButton::C => GamepadButtonType::C,
+                MyGamepadButton::Z => GamepadButtonType::Z,
+                MyGamepadButton::LeftTrigger => GamepadButtonType::LeftTrigger,
+                MyGamepadButton::LeftTrigger2 => GamepadButtonType::LeftTrigger2,
+                MyGamepadButton::RightTrigger => GamepadButtonType::RightTrigger,
+                MyGamepadButton::RightTrigger2 => GamepadButtonType::RightTrigger2,
+                MyGamepadButton::Select => GamepadButtonType::Select,
+                MyGamepadButton::Start => GamepadButtonType::Start,
+                MyGamepadButton::Mode => GamepadButtonType::Mode,
+                MyGamepadButton::LeftThumb => GamepadButtonType::LeftThumb,
+                MyGamepadButton::RightThumb => GamepadButtonType::RightThumb,
+                MyGamepadButton::DPadUp => GamepadButtonType::DPadUp,
+                MyGamepadButton::DPadDown => GamepadButtonType::DPadDown,
+                MyGamepadButton::DPadLeft => GamepadButtonType::DPadLeft,
+                MyGamepadButton::DPadRight => GamepadButtonType::DPadRight,
+                MyGamepadButton::Other(button) => GamepadButtonType::Other(button),
+            }
+        }
     }
 
-    impl From<MyKeyCode> for KeyCode {
-        fn from(value: MyKeyCode) -> KeyCode {
+    impl From<GamepadButtonType> for MyGamepadButton {
+        fn from(value: GamepadButtonType) -> MyGamepadButton {
             match value {
-                MyKeyCode::Key1 => KeyCode::Key1,
-                MyKeyCode::Key2 => KeyCode::Key2,
-                MyKeyCode::Key3 => KeyCode::Key3,
-                MyKeyCode::Key4 => KeyCode::Key4,
-                MyKeyCode::Key5 => KeyCode::Key5,
-                MyKeyCode::Key6 => KeyCode::Key6,
-                MyKeyCode::Key7 => KeyCode::Key7,
-                MyKeyCode::Key8 => KeyCode::Key8,
-                MyKeyCode::Key9 => KeyCode::Key9,
-                MyKeyCode::Key0 => KeyCode::Key0,
-                MyKeyCode::A => KeyCode::A,
-                MyKeyCode::B => KeyCode::B,
-                MyKeyCode::C => KeyCode::C,
-                MyKeyCode::D => KeyCode::D,
-                MyKeyCode::E => KeyCode::E,
-                MyKeyCode::F => KeyCode::F,
-                MyKeyCode::G => KeyCode::G,
-                MyKeyCode::H => KeyCode::H,
-                MyKeyCode::I => KeyCode::I,
-                MyKeyCode::J => KeyCode::J,
-                MyKeyCode::K => KeyCode::K,
-                MyKeyCode::L => KeyCode::L,
-                MyKeyCode::M => KeyCode::M,
-                MyKeyCode::N => KeyCode::N,
-                MyKeyCode::O => KeyCode::O,
-                MyKeyCode::P => KeyCode::P,
-                MyKeyCode::Q => KeyCode::Q,
-                MyKeyCode::R => KeyCode::R,
-                MyKeyCode::S => KeyCode::S,
-                MyKeyCode::T => KeyCode::T,
-                MyKeyCode::U => KeyCode::U,
-                MyKeyCode::V => KeyCode::V,
-                MyKeyCode::W => KeyCode::W,
-                MyKeyCode::X => KeyCode::X,
-                MyKeyCode::Y => KeyCode::Y,
-                MyKeyCode::Z => KeyCode::Z,
-                MyKeyCode::Escape => KeyCode::Escape,
-                MyKeyCode::F1 => KeyCode::F1,
-                MyKeyCode::F2 => KeyCode::F2,
-                MyKeyCode::F3 => KeyCode::F3,
-                MyKeyCode::F4 => KeyCode::F4,
-                MyKeyCode::F5 => KeyCode::F5,
-                MyKeyCode::F6 => KeyCode::F6,
-                MyKeyCode::F7 => KeyCode::F7,
-                MyKeyCode::F8 => KeyCode::F8,
-                MyKeyCode::F9 => KeyCode::F9,
-                MyKeyCode::F10 => KeyCode::F10,
-                MyKeyCode::F11 => KeyCode::F11,
-                MyKeyCode::F12 => KeyCode::F12,
-                MyKeyCode::F13 => KeyCode::F13,
-                MyKeyCode::F14 => KeyCode::F14,
-                MyKeyCode::F15 => KeyCode::F15,
-                MyKeyCode::F16 => KeyCode::F16,
-                MyKeyCode::F17 => KeyCode::F17,
-                MyKeyCode::F18 => KeyCode::F18,
-                MyKeyCode::F19 => KeyCode::F19,
-                MyKeyCode::F20 => KeyCode::F20,
-                MyKeyCode::F21 => KeyCode::F21,
-                MyKeyCode::F22 => KeyCode::F22,
-                MyKeyCode::F23 => KeyCode::F23,
-                MyKeyCode::F24 => KeyCode::F24,
-                MyKeyCode::Snapshot => KeyCode::Snapshot,
-                MyKeyCode::Scroll => KeyCode::Scroll,
-                MyKeyCode::Pause => KeyCode::Pause,
-                MyKeyCode::Insert => KeyCode::Insert,
-                MyKeyCode::Home => KeyCode::Home,
-                MyKeyCode::Delete => KeyCode::Delete,
-                MyKeyCode::End => KeyCode::End,
-                MyKeyCode::PageDown => KeyCode::PageDown,
-                MyKeyCode::PageUp => KeyCode::PageUp,
-                MyKeyCode::Left => KeyCode::Left,
-                MyKeyCode::Up => KeyCode::Up,
-                MyKeyCode::Right => KeyCode::Right,
-                MyKeyCode::Down => KeyCode::Down,
-                MyKeyCode::Back => KeyCode::Back,
-                MyKeyCode::Return => KeyCode::Return,
-                MyKeyCode::Space => KeyCode::Space,
-                MyKeyCode::Compose => KeyCode::Compose,
-                MyKeyCode::Caret => KeyCode::Caret,
-                MyKeyCode::Numlock => KeyCode::Numlock,
-                MyKeyCode::Numpad0 => KeyCode::Numpad0,
-                MyKeyCode::Numpad1 => KeyCode::Numpad1,
-                MyKeyCode::Numpad2 => KeyCode::Numpad2,
-                MyKeyCode::Numpad3 => KeyCode::Numpad3,
-                MyKeyCode::Numpad4 => KeyCode::Numpad4,
-                MyKeyCode::Numpad5 => KeyCode::Numpad5,
-                MyKeyCode::Numpad6 => KeyCode::Numpad6,
-                MyKeyCode::Numpad7 => KeyCode::Numpad7,
-                MyKeyCode::Numpad8 => KeyCode::Numpad8,
-                MyKeyCode::Numpad9 => KeyCode::Numpad9,
-                MyKeyCode::AbntC1 => KeyCode::AbntC1,
-                MyKeyCode::AbntC2 => KeyCode::AbntC2,
-                MyKeyCode::NumpadAdd => KeyCode::NumpadAdd,
-                MyKeyCode::Apostrophe => KeyCode::Apostrophe,
-                MyKeyCode::Apps => KeyCode::Apps,
-                MyKeyCode::Asterisk => KeyCode::Asterisk,
-                MyKeyCode::Plus => KeyCode::Plus,
-                MyKeyCode::At => KeyCode::At,
-                MyKeyCode::Ax => KeyCode::Ax,
-                MyKeyCode::Backslash => KeyCode::Backslash,
-                MyKeyCode::Calculator => KeyCode::Calculator,
-                MyKeyCode::Capital => KeyCode::Capital,
-                MyKeyCode::Colon => KeyCode::Colon,
-                MyKeyCode::Comma => KeyCode::Comma,
-                MyKeyCode::Convert => KeyCode::Convert,
-                MyKeyCode::NumpadDecimal => KeyCode::NumpadDecimal,
-                MyKeyCode::NumpadDivide => KeyCode::NumpadDivide,
-                MyKeyCode::Equals => KeyCode::Equals,
-                MyKeyCode::Grave => KeyCode::Grave,
-                MyKeyCode::Kana => KeyCode::Kana,
-                MyKeyCode::Kanji => KeyCode::Kanji,
-                MyKeyCode::AltLeft => KeyCode::AltLeft,
-                MyKeyCode::BracketLeft => KeyCode::BracketLeft,
-                MyKeyCode::ControlLeft => KeyCode::ControlLeft,
-                MyKeyCode::ShiftLeft => KeyCode::ShiftLeft,
-                MyKeyCode::SuperLeft => KeyCode::SuperLeft,
-                MyKeyCode::Mail => KeyCode::Mail,
-                MyKeyCode::MediaSelect => KeyCode::MediaSelect,
-                MyKeyCode::MediaStop => KeyCode::MediaStop,
-                MyKeyCode::Minus => KeyCode::Minus,
-                MyKeyCode::NumpadMultiply => KeyCode::NumpadMultiply,
-                MyKeyCode::Mute => KeyCode::Mute,
-                MyKeyCode::MyComputer => KeyCode::MyComputer,
-                MyKeyCode::NavigateForward => KeyCode::NavigateForward,
-                MyKeyCode::NavigateBackward => KeyCode::NavigateBackward,
-                MyKeyCode::NextTrack => KeyCode::NextTrack,
-                MyKeyCode::NoConvert => KeyCode::NoConvert,
-                MyKeyCode::NumpadComma => KeyCode::NumpadComma,
-                MyKeyCode::NumpadEnter => KeyCode::NumpadEnter,
-                MyKeyCode::NumpadEquals => KeyCode::NumpadEquals,
-                MyKeyCode::Oem102 => KeyCode::Oem102,
-                MyKeyCode::Period => KeyCode::Period,
-                MyKeyCode::PlayPause => KeyCode::PlayPause,
-                MyKeyCode::Power => KeyCode::Power,
-                MyKeyCode::PrevTrack => KeyCode::PrevTrack,
-                MyKeyCode::AltRight => KeyCode::AltRight,
-                MyKeyCode::BracketRight => KeyCode::BracketRight,
-                MyKeyCode::ControlRight => KeyCode::ControlRight,
-                MyKeyCode::ShiftRight => KeyCode::ShiftRight,
-                MyKeyCode::SuperRight => KeyCode::SuperRight,
-                MyKeyCode::Semicolon => KeyCode::Semicolon,
-                MyKeyCode::Slash => KeyCode::Slash,
-                MyKeyCode::Sleep => KeyCode::Sleep,
-                MyKeyCode::Stop => KeyCode::Stop,
-                MyKeyCode::NumpadSubtract => KeyCode::NumpadSubtract,
-                MyKeyCode::Sysrq => KeyCode::Sysrq,
-                MyKeyCode::Tab => KeyCode::Tab,
-                MyKeyCode::Underline => KeyCode::Underline,
-                MyKeyCode::Unlabeled => KeyCode::Unlabeled,
-                MyKeyCode::VolumeDown => KeyCode::VolumeDown,
-                MyKeyCode::VolumeUp => KeyCode::VolumeUp,
-                MyKeyCode::Wake => KeyCode::Wake,
-                MyKeyCode::WebBack => KeyCode::WebBack,
-                MyKeyCode::WebFavorites => KeyCode::WebFavorites,
-                MyKeyCode::WebForward => KeyCode::WebForward,
-                MyKeyCode::WebHome => KeyCode::WebHome,
-                MyKeyCode::WebRefresh => KeyCode::WebRefresh,
-                MyKeyCode::WebSearch => KeyCode::WebSearch,
-                MyKeyCode::WebStop => KeyCode::WebStop,
-                MyKeyCode::Yen => KeyCode::Yen,
-                MyKeyCode::Copy => KeyCode::Copy,
-                MyKeyCode::Paste => KeyCode::Paste,
-                MyKeyCode::Cut => KeyCode::Cut,
+                GamepadButtonType::South => MyGamepadButton::South,
+                GamepadButtonType::East => MyGamepadButton::East,
+                GamepadButtonType::North => MyGamepadButton::North,
+                GamepadButtonType::West => MyGamepadButton::West,
+                GamepadButtonType::C => MyGamepadButton::C,
+                GamepadButtonType::Z => MyGamepadButton::Z,
+                GamepadButtonType::LeftTrigger => MyGamepadButton::LeftTrigger,
+                GamepadButtonType::LeftTrigger2 => MyGamepadButton::LeftTrigger2,
+                GamepadButtonType::RightTrigger => MyGamepadButton::RightTrigger,
+                GamepadButtonType::RightTrigger2 => MyGamepadButton::RightTrigger2,
+                GamepadButtonType::Select => MyGamepadButton::Select,
+                GamepadButtonType::Start => MyGamepadButton::Start,
+                GamepadButtonType::Mode => MyGamepadButton::Mode,
+                GamepadButtonType::LeftThumb => MyGamepadButton::LeftThumb,
+                GamepadButtonType::RightThumb => MyGamepadButton::RightThumb,
+                GamepadButtonType::DPadUp => MyGamepadButton::DPadUp,
+                GamepadButtonType::DPadDown => MyGamepadButton::DPadDown,
+                GamepadButtonType::DPadLeft => MyGamepadButton::DPadLeft,
+                GamepadButtonType::DPadRight => MyGamepadButton::DPadRight,
+                GamepadButtonType::Other(button) => MyGamepadButton::Other(button),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    pub enum MyGamepadAxis {
+        LeftStickX,
+        LeftStickY,
+        LeftZ,
+        RightStickX,
+        RightStickY,
+        RightZ,
+        Other(u8),
+    }
+
+    impl From<MyGamepadAxis> for GamepadAxisType {
+        fn from(value: MyGamepadAxis) -> GamepadAxisType {
+            match value {
+                MyGamepadAxis::LeftStickX => GamepadAxisType::LeftStickX,
+                MyGamepadAxis::LeftStickY => GamepadAxisType::LeftStickY,
+                MyGamepadAxis::LeftZ => GamepadAxisType::LeftZ,
+                MyGamepadAxis::RightStickX => GamepadAxisType::RightStickX,
+                MyGamepadAxis::RightStickY => GamepadAxisType::RightStickY,
+                MyGamepadAxis::RightZ => GamepadAxisType::RightZ,
+                MyGamepadAxis::Other(axis) => GamepadAxisType::Other(axis),
             }
         }
     }
@@ -905,13 +1655,19 @@ pub mod parameters {
     pub struct ParametersColors {
         pub background: Color,
         pub ball: Color,
+        /// Bricks with one hit point left. Stronger ones shade toward `strongest_brick`.
         pub brick: Color,
+        pub strongest_brick: Color,
+        pub pickup: Color,
+        /// Drawn over goals closed by `PowerUpEffect::Shield`.
+        pub shield: Color,
         pub text: Color,
         pub score: Color,
     }
 }
 
 pub mod regular_polygon {
+    use bevy::math::Vec2;
     use bevy::render::{
         mesh::{Indices, Mesh},
         render_resource::PrimitiveTopology,
@@ -942,6 +1698,16 @@ pub mod regular_polygon {
         pub fn new(radius: f32, sides: usize) -> Self {
             Self { radius, sides }
         }
+
+        /// Corners of the polygon, clockwise from the top one.
+        pub fn vertices(&self) -> Vec<Vec2> {
+            let step = std::f32::consts::TAU / self.sides as f32;
+            (0..self.sides)
+                .map(|i| {
+                    Vec2::from_angle(std::f32::consts::FRAC_PI_2 - i as f32 * step) * self.radius
+                })
+                .collect()
+        }
     }
 
     impl From<RegularPolygon> for Mesh {
@@ -954,12 +1720,10 @@ pub mod regular_polygon {
             let mut normals = Vec::with_capacity(sides);
             let mut uvs = Vec::with_capacity(sides);
 
-            let step = std::f32::consts::TAU / sides as f32;
-            for i in 0..sides {
-                let theta = std::f32::consts::FRAC_PI_2 - i as f32 * step;
-                let (sin, cos) = theta.sin_cos();
+            for vertex in polygon.vertices() {
+                let (cos, sin) = (vertex.x / radius, vertex.y / radius);
 
-                positions.push([cos * radius, sin * radius, 0.0]);
+                positions.push([vertex.x, vertex.y, 0.0]);
                 normals.push([0.0, 0.0, 1.0]);
                 uvs.push([0.5 * (cos + 1.0), 1.0 - 0.5 * (sin + 1.0)]);
             }